@@ -1,16 +1,68 @@
+use crate::models::archive_dto::ArchiveFormat;
+use crate::models::resources_dto::Resources;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::error::Error;
+use std::fs;
 use std::time::Duration;
 use std::{env, time};
 use tracing::{info, warn};
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct Config {
     pub services: HashMap<String, Service>,
     pub db_path: String,
     pub data_path: String,
     pub max_age: Duration,
+    // Times a job is uploaded, or its status checked, before a transient error is final
+    pub max_attempts: u32,
+    // Added to the `max_runtime` of a service before a submitted job is timed out
+    pub runtime_grace: Duration,
+    pub routing: Vec<Rule>,
+    // Resources and datasets advertised when running as a client
+    pub resources: Resources,
+    pub datasets: Vec<String>,
+    // Key of the signature sent with the webhooks, see `services::webhooks`
+    pub webhook_secret: Secret,
+    // Of the archives uploaded with `extract=true`, see `utils::extract`
+    pub extract_limits: ExtractLimits,
+    // Most a job started with `/uploads` can declare, in bytes
+    pub max_upload_size: u64,
+    // A job started with `/uploads` is cleaned once no chunk came for this long
+    pub upload_ttl: Duration,
+}
+
+// Guards against decompression bombs when an uploaded archive is extracted
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct ExtractLimits {
+    pub max_files: u64,
+    // Of everything that is extracted, in bytes
+    pub max_size: u64,
+    // Bytes extracted for each byte of the archive
+    pub max_ratio: u64,
+}
+
+impl Default for ExtractLimits {
+    fn default() -> Self {
+        ExtractLimits {
+            max_files: 10_000,
+            max_size: 4 * 1024 * 1024 * 1024,
+            max_ratio: 100,
+        }
+    }
+}
+
+// A value that is left out when the config is logged
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct Secret(pub String);
+
+impl std::fmt::Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0.is_empty() {
+            true => write!(f, "Secret(None)"),
+            false => write!(f, "Secret(***)"),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -18,9 +70,239 @@ pub struct Service {
     pub name: String,
     pub upload_url: String,
     pub download_url: String,
+    pub status_url: String,
+    pub cancel_url: String,
+    pub logs_url: String,
+    pub stream_url: String,
+    pub resources_url: String,
+    pub runs_per_user: u16,
+    // Additional backends, used when the default one is at its quota
+    pub backends: HashMap<String, Backend>,
+    // Datasets every job of this service needs, see `services::routing`
+    pub datasets: Vec<String>,
+    pub dataset_policy: DatasetPolicy,
+    // Told about every job of this service that finished
+    pub webhook_url: String,
+    // Wall-clock limit of a `run.sh`, none when unlimited
+    pub max_runtime: Option<Duration>,
+    // Metadata fields passed on to `run.sh` as parameters, `*` for all of them
+    pub params: Vec<String>,
+    // Results are downloaded as this when the request does not ask for a format
+    pub archive_format: ArchiveFormat,
+    // Of the downloaded results, the default of the format when None
+    pub compression_level: Option<i32>,
+    // What a job of this service must be uploaded with, anything goes when None
+    pub inputs: Option<InputSchema>,
+}
+
+impl Default for Service {
+    fn default() -> Self {
+        Service {
+            name: String::new(),
+            upload_url: String::new(),
+            download_url: String::new(),
+            status_url: String::new(),
+            cancel_url: String::new(),
+            logs_url: String::new(),
+            stream_url: String::new(),
+            resources_url: String::new(),
+            runs_per_user: 5, // by default consider 5 runs per user per service
+            backends: HashMap::new(),
+            datasets: Vec::new(),
+            dataset_policy: DatasetPolicy::Prefer,
+            webhook_url: String::new(),
+            max_runtime: None,
+            params: Vec::new(),
+            archive_format: ArchiveFormat::Zip,
+            compression_level: None,
+            inputs: None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+pub enum DatasetPolicy {
+    // Backends that hold the datasets go first
+    #[default]
+    Prefer,
+    // Only backends that hold the datasets are used
+    Require,
+}
+
+impl DatasetPolicy {
+    pub fn from_string(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "prefer" => Some(DatasetPolicy::Prefer),
+            "require" => Some(DatasetPolicy::Require),
+            _ => None,
+        }
+    }
+}
+
+// Parse a comma separated list
+pub fn parse_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+        .collect()
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+pub enum BackendKind {
+    // An orchestrator client, see `services::client::Client`
+    #[default]
+    Client,
+    // Another orchestrator server, see `services::partner::Partner`
+    Orchestrator,
+}
+
+impl BackendKind {
+    pub fn from_string(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "client" => Some(BackendKind::Client),
+            "orchestrator" => Some(BackendKind::Orchestrator),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Backend {
+    pub name: String,
+    pub kind: BackendKind,
+    pub upload_url: String,
+    pub download_url: String,
+    pub status_url: String,
+    pub cancel_url: String,
+    pub logs_url: String,
+    pub stream_url: String,
+    pub resources_url: String,
     pub runs_per_user: u16,
 }
 
+impl Default for Backend {
+    fn default() -> Self {
+        Backend {
+            name: String::new(),
+            kind: BackendKind::Client,
+            upload_url: String::new(),
+            download_url: String::new(),
+            status_url: String::new(),
+            cancel_url: String::new(),
+            logs_url: String::new(),
+            stream_url: String::new(),
+            resources_url: String::new(),
+            runs_per_user: 5,
+        }
+    }
+}
+
+// Sends the jobs it matches to a given backend, see `services::routing`.
+//  Every condition that is set must hold for the rule to match
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    // Backend the jobs go to, `default` for the default backend of the service
+    pub backend: String,
+    // Only for jobs of this service, otherwise for the services that have the backend
+    pub service: Option<String>,
+    #[serde(default)]
+    pub user_ids: Vec<i32>,
+    // Total size of the input files, in bytes
+    pub min_input_size: Option<u64>,
+    pub max_input_size: Option<u64>,
+    // The job must have all of these labels
+    #[serde(default)]
+    pub labels: Vec<String>,
+    // Time of day window, as `HH:MM` in UTC, it can wrap around midnight
+    pub after: Option<String>,
+    pub before: Option<String>,
+}
+
+// The inputs a service expects, checked before a job is queued, see `services::validation`
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct InputSchema {
+    // Files the job can have, any file is accepted when empty
+    #[serde(default)]
+    pub files: Vec<InputFile>,
+    // Extensions every file must have, without the dot, any when empty
+    #[serde(default)]
+    pub extensions: Vec<String>,
+    // Of all the inputs together, in bytes
+    pub max_size: Option<u64>,
+    // Metadata fields that must be given, ie `-F "run_name=first"`
+    #[serde(default)]
+    pub params: Vec<String>,
+    // Run in the job directory, in order, once the other checks passed
+    #[serde(default)]
+    pub validators: Vec<Validator>,
+    // Of all the validator runs together, in seconds, see `services::validation`
+    pub timeout: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct InputFile {
+    // Path in the job directory, where `*` matches any part of a file or directory name
+    pub path: String,
+    // At least one uploaded file must match
+    #[serde(default)]
+    pub required: bool,
+    // Of each matching file, in bytes
+    pub max_size: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Validator {
+    // Program and arguments, the input is invalid when it exits with an error
+    pub command: Vec<String>,
+    // Run once for each file matching this pattern, with its path as last argument, as
+    //  `./<path>`, otherwise once for the whole job
+    pub files: Option<String>,
+    // In seconds
+    #[serde(default = "default_validator_timeout")]
+    pub timeout: u64,
+}
+
+fn default_validator_timeout() -> u64 {
+    60
+}
+
+// Each rule must name a backend that is there, in its service or in any service when it
+//  does not have one. Otherwise it is a typo that would never match
+fn check_rule_backends(rules: &[Rule], services: &HashMap<String, Service>) -> Result<(), String> {
+    for rule in rules {
+        let candidates: Vec<&Service> = match &rule.service {
+            Some(name) => vec![services
+                .get(name)
+                .ok_or(format!("unknown service in routing rule: {name:?}"))?],
+            None => services.values().collect(),
+        };
+        if rule.backend != "default"
+            && !candidates
+                .iter()
+                .any(|service| service.backends.contains_key(&rule.backend))
+        {
+            return Err(format!(
+                "unknown backend in routing rule: {:?}",
+                rule.backend
+            ));
+        }
+    }
+    Ok(())
+}
+
+// Minutes since midnight of a `HH:MM` time
+pub fn parse_time_of_day(time: &str) -> Option<u32> {
+    let (hours, minutes) = time.split_once(':')?;
+    let hours: u32 = hours.parse().ok()?;
+    let minutes: u32 = minutes.parse().ok()?;
+    (hours < 24 && minutes < 60).then_some(hours * 60 + minutes)
+}
+
 impl Config {
     pub fn new() -> Result<Config, Box<dyn Error>> {
         let mut services = HashMap::new();
@@ -30,7 +312,21 @@ impl Config {
             // Look for service environment variables with the pattern:
             // - SERVICE_<NAME>_UPLOAD_URL
             // - SERVICE_<NAME>_DOWNLOAD_URL
+            // - SERVICE_<NAME>_STATUS_URL (optional)
+            // - SERVICE_<NAME>_CANCEL_URL (optional)
+            // - SERVICE_<NAME>_LOGS_URL (optional)
+            // - SERVICE_<NAME>_STREAM_URL (optional)
+            // - SERVICE_<NAME>_RESOURCES_URL (optional)
+            // - SERVICE_<NAME>_DATASETS (optional)
+            // - SERVICE_<NAME>_DATASET_POLICY (optional)
+            // - SERVICE_<NAME>_WEBHOOK_URL (optional)
+            // - SERVICE_<NAME>_MAX_RUNTIME (optional, in seconds)
+            // - SERVICE_<NAME>_PARAMS (optional)
+            // - SERVICE_<NAME>_ARCHIVE_FORMAT (optional, `zip` by default)
+            // - SERVICE_<NAME>_COMPRESSION_LEVEL (optional)
+            // - SERVICE_<NAME>_INPUTS_PATH (optional, a YAML `InputSchema`)
             // - SERVICE_<NAME>_RUNS_PER_USER
+            // - SERVICE_<NAME>_BACKEND_<BACKEND>_<VAR>, with the same variables plus `KIND`
             if key.starts_with("SERVICE_") {
                 let parts: Vec<&str> = key.split('_').collect();
                 if parts.len() >= 3 {
@@ -42,15 +338,70 @@ impl Config {
                         .entry(service_name.to_string().to_ascii_lowercase())
                         .or_insert(Service {
                             name: service_name.to_string().to_ascii_lowercase(),
-                            upload_url: String::new(),
-                            download_url: String::new(),
-                            runs_per_user: 5, // by default consider 5 runs per user per service
+                            ..Default::default()
                         });
 
+                    if let Some(backend_vars) = service_vars.strip_prefix("BACKEND_") {
+                        if let Some((backend_name, var)) = backend_vars.split_once('_') {
+                            let name = backend_name.to_ascii_lowercase();
+                            let backend = service.backends.entry(name.clone()).or_insert(Backend {
+                                name,
+                                ..Default::default()
+                            });
+                            match var {
+                                "KIND" => match BackendKind::from_string(&value) {
+                                    Some(kind) => backend.kind = kind,
+                                    None => warn!("{key}: unknown backend kind {value:?}"),
+                                },
+                                "UPLOAD_URL" => backend.upload_url = value,
+                                "DOWNLOAD_URL" => backend.download_url = value,
+                                "STATUS_URL" => backend.status_url = value,
+                                "CANCEL_URL" => backend.cancel_url = value,
+                                "LOGS_URL" => backend.logs_url = value,
+                                "STREAM_URL" => backend.stream_url = value,
+                                "RESOURCES_URL" => backend.resources_url = value,
+                                "RUNS_PER_USER" => {
+                                    backend.runs_per_user = value.parse::<u16>().unwrap()
+                                }
+                                _ => continue,
+                            };
+                        }
+                        continue;
+                    }
+
                     // Assign the corresponding vars to the config
                     match service_vars.as_str() {
                         "UPLOAD_URL" => service.upload_url = value,
                         "DOWNLOAD_URL" => service.download_url = value,
+                        "STATUS_URL" => service.status_url = value,
+                        "CANCEL_URL" => service.cancel_url = value,
+                        "LOGS_URL" => service.logs_url = value,
+                        "STREAM_URL" => service.stream_url = value,
+                        "RESOURCES_URL" => service.resources_url = value,
+                        "DATASETS" => service.datasets = parse_list(&value),
+                        "WEBHOOK_URL" => service.webhook_url = value,
+                        "PARAMS" => service.params = parse_list(&value),
+                        "MAX_RUNTIME" => {
+                            service.max_runtime = Some(Duration::from_secs(value.parse()?))
+                        }
+                        "ARCHIVE_FORMAT" => match ArchiveFormat::from_string(&value) {
+                            Some(format) => service.archive_format = format,
+                            None => warn!("{key}: unknown archive format {value:?}"),
+                        },
+                        "COMPRESSION_LEVEL" => service.compression_level = Some(value.parse()?),
+                        "INPUTS_PATH" => {
+                            let content = fs::read_to_string(&value)?;
+                            let schema: InputSchema = serde_yaml::from_str(&content)
+                                .map_err(|e| format!("{key}: {e}"))?;
+                            if schema.validators.iter().any(|v| v.command.is_empty()) {
+                                return Err(format!("{key}: validator without a command").into());
+                            }
+                            service.inputs = Some(schema);
+                        }
+                        "DATASET_POLICY" => match DatasetPolicy::from_string(&value) {
+                            Some(policy) => service.dataset_policy = policy,
+                            None => warn!("{key}: unknown dataset policy {value:?}"),
+                        },
                         "RUNS_PER_USER" => service.runs_per_user = value.parse::<u16>().unwrap(),
                         _ => continue,
                     };
@@ -90,25 +441,266 @@ impl Config {
             }
         };
 
+        let max_attempts = match env::var("MAX_ATTEMPTS") {
+            Ok(v) => v.parse()?,
+            Err(_) => 5,
+        };
+
+        let runtime_grace = match env::var("RUNTIME_GRACE") {
+            Ok(v) => Duration::from_secs(v.parse()?),
+            Err(_) => Duration::from_secs(300),
+        };
+
+        // Routing rules are read from a YAML file with a list of `Rule`
+        let routing: Vec<Rule> = match env::var("ROUTING_PATH") {
+            Ok(p) => {
+                let content = fs::read_to_string(&p)?;
+                serde_yaml::from_str(&content)?
+            }
+            Err(_) => Vec::new(),
+        };
+        for rule in &routing {
+            for time in [&rule.after, &rule.before].into_iter().flatten() {
+                if parse_time_of_day(time).is_none() {
+                    return Err(format!("invalid time of day in routing rule: {time:?}").into());
+                }
+            }
+        }
+        check_rule_backends(&routing, &services)?;
+
+        // Resources advertised by a client, memory and scratch in MB, 0 when not tracked
+        let resources = Resources {
+            cores: match env::var("CLIENT_CORES") {
+                Ok(v) => v.parse()?,
+                Err(_) => std::thread::available_parallelism()
+                    .map(|n| n.get() as u32)
+                    .unwrap_or(1),
+            },
+            memory_mb: match env::var("CLIENT_MEMORY_MB") {
+                Ok(v) => v.parse()?,
+                Err(_) => 0,
+            },
+            scratch_mb: match env::var("CLIENT_SCRATCH_MB") {
+                Ok(v) => v.parse()?,
+                Err(_) => 0,
+            },
+        };
+
+        let datasets = match env::var("CLIENT_DATASETS") {
+            Ok(v) => parse_list(&v),
+            Err(_) => Vec::new(),
+        };
+
+        // Notifications are always signed, a webhook can't go without the secret
+        let webhook_secret = Secret(env::var("WEBHOOK_SECRET").unwrap_or_default());
+        if webhook_secret.0.is_empty() {
+            if let Some(service) = services.values().find(|s| !s.webhook_url.is_empty()) {
+                return Err(format!(
+                    "webhook of service {} needs a WEBHOOK_SECRET to sign its notifications",
+                    service.name
+                )
+                .into());
+            }
+        }
+
+        let mut extract_limits = ExtractLimits::default();
+        if let Ok(v) = env::var("EXTRACT_MAX_FILES") {
+            extract_limits.max_files = v.parse()?;
+        }
+        if let Ok(v) = env::var("EXTRACT_MAX_SIZE") {
+            extract_limits.max_size = v.parse()?;
+        }
+        if let Ok(v) = env::var("EXTRACT_MAX_RATIO") {
+            extract_limits.max_ratio = v.parse()?;
+        }
+
+        let max_upload_size = match env::var("UPLOAD_MAX_SIZE") {
+            Ok(v) => v.parse()?,
+            Err(_) => 4 * 1024 * 1024 * 1024,
+        };
+
+        let upload_ttl = match env::var("UPLOAD_TTL") {
+            Ok(v) => Duration::from_secs(v.parse()?),
+            Err(_) => Duration::from_secs(86400),
+        };
+
         let config = Config {
             services,
             db_path,
             data_path,
             max_age,
+            max_attempts,
+            runtime_grace,
+            routing,
+            resources,
+            datasets,
+            webhook_secret,
+            extract_limits,
+            max_upload_size,
+            upload_ttl,
         };
         info!("{:?}", config);
         Ok(config)
     }
 
-    pub fn get_download_url(&self, service_name: &str) -> Option<&str> {
+    // An empty `backend` refers to the default backend of the service
+    pub fn get_download_url(&self, service_name: &str, backend: &str) -> Option<&str> {
+        let service = self.services.get(service_name)?;
+        if backend.is_empty() {
+            Some(service.download_url.as_str())
+        } else {
+            service
+                .backends
+                .get(backend)
+                .map(|b| b.download_url.as_str())
+        }
+    }
+
+    pub fn get_upload_url(&self, service_name: &str, backend: &str) -> Option<&str> {
+        let service = self.services.get(service_name)?;
+        if backend.is_empty() {
+            Some(service.upload_url.as_str())
+        } else {
+            service.backends.get(backend).map(|b| b.upload_url.as_str())
+        }
+    }
+
+    // The status and cancel urls are optional, `None` when they are not configured
+    pub fn get_status_url(&self, service_name: &str, backend: &str) -> Option<&str> {
+        let service = self.services.get(service_name)?;
+        let url = if backend.is_empty() {
+            service.status_url.as_str()
+        } else {
+            service.backends.get(backend)?.status_url.as_str()
+        };
+        Some(url).filter(|u| !u.is_empty())
+    }
+
+    pub fn get_cancel_url(&self, service_name: &str, backend: &str) -> Option<&str> {
+        let service = self.services.get(service_name)?;
+        let url = if backend.is_empty() {
+            service.cancel_url.as_str()
+        } else {
+            service.backends.get(backend)?.cancel_url.as_str()
+        };
+        Some(url).filter(|u| !u.is_empty())
+    }
+
+    pub fn get_logs_url(&self, service_name: &str, backend: &str) -> Option<&str> {
+        let service = self.services.get(service_name)?;
+        let url = if backend.is_empty() {
+            service.logs_url.as_str()
+        } else {
+            service.backends.get(backend)?.logs_url.as_str()
+        };
+        Some(url).filter(|u| !u.is_empty())
+    }
+
+    pub fn get_stream_url(&self, service_name: &str, backend: &str) -> Option<&str> {
+        let service = self.services.get(service_name)?;
+        let url = if backend.is_empty() {
+            service.stream_url.as_str()
+        } else {
+            service.backends.get(backend)?.stream_url.as_str()
+        };
+        Some(url).filter(|u| !u.is_empty())
+    }
+
+    pub fn get_webhook_url(&self, service_name: &str) -> Option<&str> {
+        let service = self.services.get(service_name)?;
+        Some(service.webhook_url.as_str()).filter(|u| !u.is_empty())
+    }
+
+    pub fn get_max_runtime(&self, service_name: &str) -> Option<Duration> {
+        self.services.get(service_name)?.max_runtime
+    }
+
+    // Format and compression level the results of a service are downloaded with by default
+    pub fn get_archive(&self, service_name: &str) -> (ArchiveFormat, Option<i32>) {
         self.services
             .get(service_name)
-            .map(|service| service.download_url.as_str())
+            .map(|s| (s.archive_format, s.compression_level))
+            .unwrap_or_default()
+    }
+
+    // What the inputs of a job are checked against, see `services::validation`
+    pub fn get_inputs(&self, service_name: &str) -> Option<&InputSchema> {
+        self.services.get(service_name)?.inputs.as_ref()
     }
 
-    pub fn get_upload_url(&self, service_name: &str) -> Option<&str> {
+    // The metadata of a job its `run.sh` gets, see `services::client::spawn_payload`
+    pub fn get_params(
+        &self,
+        service_name: &str,
+        metadata: &BTreeMap<String, String>,
+    ) -> BTreeMap<String, String> {
+        let Some(service) = self.services.get(service_name) else {
+            return BTreeMap::new();
+        };
+        metadata
+            .iter()
+            .filter(|(key, _)| service.params.iter().any(|p| p == "*" || p == *key))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect()
+    }
+
+    pub fn get_runs_per_user(&self, service_name: &str, backend: &str) -> Option<u16> {
+        let service = self.services.get(service_name)?;
+        if backend.is_empty() {
+            Some(service.runs_per_user)
+        } else {
+            service.backends.get(backend).map(|b| b.runs_per_user)
+        }
+    }
+
+    pub fn get_resources_url(&self, service_name: &str, backend: &str) -> Option<&str> {
+        let service = self.services.get(service_name)?;
+        let url = if backend.is_empty() {
+            service.resources_url.as_str()
+        } else {
+            service.backends.get(backend)?.resources_url.as_str()
+        };
+        Some(url).filter(|u| !u.is_empty())
+    }
+
+    pub fn get_backend_kind(&self, service_name: &str, backend: &str) -> BackendKind {
         self.services
             .get(service_name)
-            .map(|service| service.upload_url.as_str())
+            .and_then(|service| service.backends.get(backend))
+            .map(|b| b.kind)
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn test_check_rule_backends() {
+        let services = HashMap::from([(
+            String::from("example"),
+            Service {
+                name: String::from("example"),
+                backends: HashMap::from([(String::from("hpc"), Backend::default())]),
+                ..Default::default()
+            },
+        )]);
+        let rule = |service: Option<&str>, backend: &str| Rule {
+            backend: backend.to_string(),
+            service: service.map(String::from),
+            ..Default::default()
+        };
+
+        let rules = [
+            rule(None, "hpc"),
+            rule(Some("example"), "hpc"),
+            rule(Some("example"), "default"),
+        ];
+        assert!(check_rule_backends(&rules, &services).is_ok());
+        assert!(check_rule_backends(&[rule(None, "hcp")], &services).is_err());
+        assert!(check_rule_backends(&[rule(Some("other"), "hpc")], &services).is_err());
+        assert!(check_rule_backends(&[rule(Some("other"), "default")], &services).is_err());
     }
 }
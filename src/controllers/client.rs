@@ -1,11 +1,23 @@
-use crate::{routes::router::AppState, utils::io::sanitize_filename};
+use crate::{routes::router::AppState, utils::io::input_path};
 
+use crate::models::logs_dto::{LogTail, Logs, STDOUT_LOG, STREAM_INTERVAL};
 use crate::models::payload_dao::Payload;
+use crate::models::resources_dto::Advertisement;
 use crate::models::status_dto::Status;
+use crate::services::client::{kill_payload, KILL_GRACE};
+use crate::utils::serve::serve_file;
 use axum::{
     extract::{Json, Multipart, Path, State},
-    http::StatusCode,
+    http::{HeaderMap, StatusCode},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
 };
+use futures::Stream;
+use std::collections::HashSet;
+use std::convert::Infallible;
+use tracing::warn;
 
 #[utoipa::path(
     post,
@@ -15,7 +27,7 @@ use axum::{
     ),
     responses(
         (status = 200, description = "File uploaded successfully", body = Payload),
-        // (status = 400, description = "Bad request"),
+        (status = 400, description = "Invalid max_runtime, params or input path"),
         (status = 500, description = "Internal server error"),
         // (status = 503, description = "Service unavailable")
     ),
@@ -27,13 +39,36 @@ pub async fn submit(
 ) -> Result<Json<Payload>, (StatusCode, String)> {
     let mut payload = Payload::new();
 
+    let mut input_paths = HashSet::new();
+
     // Parse the multipart form data
     while let Some(field) = multipart.next_field().await.unwrap() {
         if let Some(filename) = field.file_name() {
-            let clean_filename = sanitize_filename(filename);
+            // The server sends the path relative to the job directory as the field name
+            let relative = input_path(field.name(), filename)
+                .map_err(|e| (e.status_code(), e.to_string()))?
+                .to_string_lossy()
+                .to_string();
+            if !input_paths.insert(relative.clone()) {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    format!("Duplicate input '{relative}'"),
+                ));
+            }
             let data = field.bytes().await.unwrap();
 
-            payload.add_input(clean_filename, data.to_vec());
+            payload.add_input(relative, data.to_vec());
+        } else if field.name() == Some("max_runtime") {
+            let value = field.text().await.unwrap_or_default();
+            let max_runtime = value
+                .parse()
+                .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid max_runtime".to_string()))?;
+            payload.set_max_runtime(max_runtime);
+        } else if field.name() == Some("params") {
+            let value = field.text().await.unwrap_or_default();
+            let params = serde_json::from_str(&value)
+                .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid params".to_string()))?;
+            payload.set_params(params);
         }
     }
 
@@ -66,8 +101,11 @@ pub async fn submit(
     responses(
         (status = 200, description = "File downloaded successfully", body = Vec<u8>),
         (status = 202, description = "Job not ready"),
-        (status = 204, description = "Job failed or cleaned"),
+        (status = 204, description = "Job failed or cleaned, a failure has the x-failure-kind, x-exit-code and x-signal headers"),
+        (status = 206, description = "Part of the file asked for with `Range`", body = Vec<u8>),
+        (status = 304, description = "Results unchanged since the `If-None-Match` ETag"),
         (status = 404, description = "Job not found"),
+        (status = 416, description = "Range outside of the file"),
         (status = 500, description = "Internal server error")
     ),
     tag = "files"
@@ -75,7 +113,8 @@ pub async fn submit(
 pub async fn retrieve(
     State(state): State<AppState>,
     Path(id): Path<u32>,
-) -> Result<Vec<u8>, StatusCode> {
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
     let mut payload = Payload::new();
 
     payload
@@ -87,42 +126,252 @@ pub async fn retrieve(
         })?;
 
     match payload.status {
-        Status::Completed => Ok(payload.zip_directory()),
-        Status::Failed => Err(StatusCode::NO_CONTENT),
+        Status::Completed => {
+            payload.set_loc(std::path::Path::new(&state.config.data_path).join(id.to_string()));
+            // Kept on disk rather than streamed like `results_dto::repack`, a stream has no
+            //  `Content-Length` and can't answer the `Range` of a resumed download
+            let archive = tokio::task::spawn_blocking(move || payload.zip_directory())
+                .await
+                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+                .map_err(|e| {
+                    warn!("Failed to zip the outputs of payload {id}: {e}");
+                    StatusCode::INTERNAL_SERVER_ERROR
+                })?;
+            serve_file(&archive, "application/zip", &headers)
+                .await
+                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+        }
+        // Tell why in the headers, see `Failure::to_headers`
+        Status::Failed | Status::TimedOut => {
+            let headers = payload.failure.map(|f| f.to_headers()).unwrap_or_default();
+            Ok((StatusCode::NO_CONTENT, headers).into_response())
+        }
         Status::Cleaned => Err(StatusCode::NO_CONTENT),
         // TODO: Handle other status here
         _ => Err(StatusCode::ACCEPTED),
     }
 }
+#[utoipa::path(
+    get,
+    path = "/status/{id}",
+    params(
+        ("id" = i32, Path, description = "Payload identifier")
+    ),
+    responses(
+        (status = 200, description = "Payload status", body = Payload),
+        (status = 404, description = "Payload not found"),
+        (status = 500, description = "Internal server error")
+    ),
+    tag = "files"
+)]
+pub async fn status(
+    State(state): State<AppState>,
+    Path(id): Path<u32>,
+) -> Result<Json<Payload>, StatusCode> {
+    let mut payload = Payload::new();
+
+    payload
+        .retrieve_id(id, &state.pool)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => StatusCode::NOT_FOUND,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        })?;
+
+    Ok(Json(payload))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/payload/{id}",
+    params(
+        ("id" = i32, Path, description = "Payload identifier")
+    ),
+    responses(
+        (status = 200, description = "Payload cancelled", body = Payload),
+        (status = 404, description = "Payload not found"),
+        (status = 409, description = "Payload already finished"),
+        (status = 500, description = "Internal server error")
+    ),
+    tag = "files"
+)]
+pub async fn cancel(
+    State(state): State<AppState>,
+    Path(id): Path<u32>,
+) -> Result<Json<Payload>, StatusCode> {
+    let mut payload = Payload::new();
+
+    payload
+        .retrieve_id(id, &state.pool)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => StatusCode::NOT_FOUND,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        })?;
+
+    match payload.status {
+        Status::Cancelled => Ok(Json(payload)),
+        Status::Completed | Status::Failed | Status::TimedOut | Status::Cleaned => {
+            Err(StatusCode::CONFLICT)
+        }
+        _ => {
+            let current = payload.status.clone();
+            let cancelled = payload
+                .transition_status(current, Status::Cancelled, &state.pool)
+                .await
+                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+            // The runner picked it up, or it finished, in the meantime
+            if !cancelled {
+                return Err(StatusCode::CONFLICT);
+            }
+
+            // Stop the running script, the runner leaves the status as it is. Without a pid
+            //  the runner is still starting it and stops it once it knows the pid
+            if let Some(pid) = payload.pid {
+                if let Err(e) = kill_payload(pid, KILL_GRACE) {
+                    warn!("Could not kill payload {}: {:?}", payload.id, e);
+                }
+            }
+
+            Ok(Json(payload))
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/logs/{id}",
+    params(
+        ("id" = i32, Path, description = "Payload identifier")
+    ),
+    responses(
+        (status = 200, description = "Output of the payload so far", body = Logs),
+        (status = 404, description = "Payload or logs not found"),
+        (status = 500, description = "Internal server error")
+    ),
+    tag = "files"
+)]
+pub async fn logs(
+    State(state): State<AppState>,
+    Path(id): Path<u32>,
+) -> Result<Json<Logs>, StatusCode> {
+    let mut payload = Payload::new();
+
+    payload
+        .retrieve_id(id, &state.pool)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => StatusCode::NOT_FOUND,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        })?;
+
+    // Not started yet, or already cleaned
+    payload.set_loc(std::path::Path::new(&state.config.data_path).join(id.to_string()));
+    let logs = Logs::read(&payload.state_dir()).map_err(|e| match e.kind() {
+        std::io::ErrorKind::NotFound => StatusCode::NOT_FOUND,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    })?;
+
+    Ok(Json(logs))
+}
+
+#[utoipa::path(
+    get,
+    path = "/logs/{id}/stream",
+    params(
+        ("id" = i32, Path, description = "Payload identifier")
+    ),
+    responses(
+        (status = 200, description = "Server-Sent Events: `stdout` with each line of output, `status` when the status changes. Ends once the payload finished", content_type = "text/event-stream", body = String),
+        (status = 404, description = "Payload not found"),
+        (status = 500, description = "Internal server error")
+    ),
+    tag = "files"
+)]
+pub async fn stream(
+    State(state): State<AppState>,
+    Path(id): Path<u32>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, StatusCode> {
+    let mut payload = Payload::new();
+
+    payload
+        .retrieve_id(id, &state.pool)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => StatusCode::NOT_FOUND,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        })?;
+
+    payload.set_loc(std::path::Path::new(&state.config.data_path).join(id.to_string()));
+    let mut tail = LogTail::new(payload.state_dir().join(STDOUT_LOG));
+
+    let events = async_stream::stream! {
+        let mut last_status = None;
+        let mut interval = tokio::time::interval(STREAM_INTERVAL);
+        loop {
+            interval.tick().await;
+            // The status goes first, so the output written before it finished is not missed
+            if payload.retrieve_id(id, &state.pool).await.is_err() {
+                break;
+            }
+            for line in tail.read_lines().unwrap_or_default() {
+                yield Ok(Event::default().event("stdout").data(line));
+            }
+            if last_status.as_ref() != Some(&payload.status) {
+                yield Ok(Event::default().event("status").data(payload.status.to_string()));
+                last_status = Some(payload.status.clone());
+            }
+            if payload.status.is_finished() {
+                if let Some(rest) = tail.rest() {
+                    yield Ok(Event::default().event("stdout").data(rest));
+                }
+                break;
+            }
+        }
+    };
+
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+#[utoipa::path(
+    get,
+    path = "/resources",
+    responses(
+        (status = 200, description = "Resources and datasets advertised by this client", body = Advertisement),
+    ),
+    tag = "resources"
+)]
+pub async fn resources(State(state): State<AppState>) -> Json<Advertisement> {
+    Json(Advertisement {
+        resources: state.config.resources,
+        datasets: state.config.datasets,
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::config::loader::Config;
+    use crate::models::failure_dto::{Failure, FailureKind};
+    use crate::models::logs_dto::STDOUT_LOG;
+    use crate::models::resources_dto::Resources;
     use crate::routes::router::AppState;
+    use crate::services::client::{spawn_payload, wait_payload};
     use axum::body::to_bytes;
     use axum::body::Body;
-    use axum::{routing::get, routing::post, Router};
+    use axum::{routing::delete, routing::get, routing::post, Router};
     use http::{header, Request, StatusCode};
     use sqlx::SqlitePool;
     use std::path::PathBuf;
-    use tempfile::tempdir;
+    use std::time::Duration;
+    use tempfile::{tempdir, TempDir};
     use tower::ServiceExt; // for `oneshot`
     use uuid::Uuid;
 
     // Helper function to initialize the database schema
     pub async fn init_db(pool: &SqlitePool) -> Result<(), sqlx::Error> {
-        sqlx::query(
-            r#"
-        CREATE TABLE IF NOT EXISTS payloads (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            status TEXT NOT NULL,
-            created_at DATETIME DEFAULT CURRENT_TIMESTAMP
-        )
-    "#,
-        )
-        .execute(pool)
-        .await?;
-        Ok(())
+        crate::models::payload_dto::create_payload_table(pool).await
     }
 
     // Helper functions to create multipart form data
@@ -175,7 +424,8 @@ mod tests {
         )
     }
 
-    async fn setup_retrieve_test_router(endpoint: &str) -> (Router, u32, u32) {
+    // The data directory goes along, the outputs are zipped from it
+    async fn setup_retrieve_test_router(endpoint: &str) -> (Router, u32, u32, TempDir) {
         // Setup the route
         let data_dir = tempdir().unwrap();
         let mut config = Config::new().unwrap();
@@ -215,6 +465,18 @@ mod tests {
             .update_status(Status::Failed, &pool)
             .await
             .expect("Failed to update status");
+        payload
+            .update_failure(
+                Failure {
+                    kind: FailureKind::Script,
+                    exit_code: Some(2),
+                    signal: None,
+                    stderr_tail: Some("error".to_string()),
+                },
+                &pool,
+            )
+            .await
+            .expect("Failed to update failure");
         let failed_jobid = payload.id;
 
         (
@@ -223,6 +485,7 @@ mod tests {
                 .with_state(state),
             complete_jobid,
             failed_jobid,
+            data_dir,
         )
     }
 
@@ -247,6 +510,13 @@ mod tests {
             "application/octet-stream",
             b"\x00\x01\x02\x03",
         ));
+        // As `Client::upload` sends the inputs in subdirectories
+        body.extend(form_text_file(
+            &boundary,
+            "data/ligands/test01.txt",
+            "test01.txt",
+            "a nested test file",
+        ));
         body.extend(format!("--{boundary}--\r\n").as_bytes());
 
         // Create the request
@@ -275,27 +545,61 @@ mod tests {
         assert!(expected_file.exists());
         let expected_file = PathBuf::from(expected_loc).join("test.dat");
         assert!(expected_file.exists());
+        let expected_file = PathBuf::from(expected_loc).join("data/ligands/test01.txt");
+        assert_eq!(
+            std::fs::read_to_string(expected_file).unwrap(),
+            "a nested test file"
+        );
     }
 
     #[tokio::test]
     async fn test_retrieve() {
-        let (test_app, valid_jobid, _) = setup_retrieve_test_router("/retrieve/{id}").await;
+        let (test_app, valid_jobid, _, _data_dir) =
+            setup_retrieve_test_router("/retrieve/{id}").await;
         let endpoint = format!("/retrieve/{}", valid_jobid);
 
         let req = Request::builder()
             .method("GET")
-            .uri(endpoint)
+            .uri(&endpoint)
             .body(Body::empty())
             .unwrap();
 
-        assert_eq!(
-            test_app.oneshot(req).await.unwrap().status(),
-            StatusCode::OK
-        );
+        let response = test_app.clone().oneshot(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let length = response.headers()[header::CONTENT_LENGTH].clone();
+        let etag = response.headers()[header::ETAG].clone();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(length, body.len().to_string().as_str());
+        let mut zip = zip::ZipArchive::new(std::io::Cursor::new(body.to_vec())).unwrap();
+        assert!(zip.by_name("test01.txt").is_ok());
+
+        // The archive is built once, the same one is asked for again
+        let req = Request::builder()
+            .method("GET")
+            .uri(&endpoint)
+            .header(header::IF_NONE_MATCH, etag.clone())
+            .body(Body::empty())
+            .unwrap();
+        let response = test_app.clone().oneshot(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+        let req = Request::builder()
+            .method("GET")
+            .uri(&endpoint)
+            .header(header::RANGE, "bytes=0-1")
+            .header(header::IF_RANGE, etag)
+            .body(Body::empty())
+            .unwrap();
+        let response = test_app.oneshot(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        // Zip archives start with `PK`
+        assert_eq!(&body[..], b"PK");
     }
     #[tokio::test]
     async fn test_retrieve_nocontent() {
-        let (test_app, _, failed_jobid) = setup_retrieve_test_router("/retrieve/{id}").await;
+        let (test_app, _, failed_jobid, _data_dir) =
+            setup_retrieve_test_router("/retrieve/{id}").await;
         let endpoint = format!("/retrieve/{}", failed_jobid);
 
         let req = Request::builder()
@@ -304,15 +608,18 @@ mod tests {
             .body(Body::empty())
             .unwrap();
 
-        assert_eq!(
-            test_app.oneshot(req).await.unwrap().status(),
-            StatusCode::NO_CONTENT
-        );
+        let response = test_app.oneshot(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        // The failure goes along in the headers
+        let failure = Failure::from_headers(response.headers()).unwrap();
+        assert_eq!(failure.kind, FailureKind::Script);
+        assert_eq!(failure.exit_code, Some(2));
     }
 
     #[tokio::test]
     async fn test_retrieve_not_found() {
-        let (test_app, _, _) = setup_retrieve_test_router("/retrieve/{id}").await;
+        let (test_app, _, _, _data_dir) = setup_retrieve_test_router("/retrieve/{id}").await;
         let endpoint = "/retrieve/999";
 
         // Create the request
@@ -326,4 +633,243 @@ mod tests {
         let response = test_app.oneshot(req).await.unwrap();
         assert_eq!(response.status(), StatusCode::NOT_FOUND);
     }
+
+    #[tokio::test]
+    async fn test_status() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        init_db(&pool).await.unwrap();
+        let mut payload = Payload::new();
+        payload.add_to_db(&pool).await.unwrap();
+        payload
+            .update_status(Status::Prepared, &pool)
+            .await
+            .unwrap();
+        let state = AppState {
+            pool,
+            config: Config::new().unwrap(),
+        };
+        let test_app = Router::new()
+            .route("/status/{id}", get(status))
+            .with_state(state);
+
+        let req = Request::builder()
+            .method("GET")
+            .uri(format!("/status/{}", payload.id))
+            .body(Body::empty())
+            .unwrap();
+        let response = test_app.clone().oneshot(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["status"], String::from("Prepared"));
+
+        let req = Request::builder()
+            .method("GET")
+            .uri("/status/999")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(
+            test_app.oneshot(req).await.unwrap().status(),
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[tokio::test]
+    async fn test_logs() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        init_db(&pool).await.unwrap();
+        let temp_dir = tempdir().unwrap();
+        let mut config = Config::new().unwrap();
+        config.data_path = temp_dir.path().to_str().unwrap().to_string();
+        let mut payload = Payload::new();
+        payload.add_to_db(&pool).await.unwrap();
+        payload.prepare(&config.data_path).unwrap();
+        std::fs::create_dir(payload.state_dir()).unwrap();
+        std::fs::write(payload.state_dir().join(STDOUT_LOG), b"out").unwrap();
+        let state = AppState { pool, config };
+        let test_app = Router::new()
+            .route("/logs/{id}", get(logs))
+            .with_state(state);
+
+        let req = Request::builder()
+            .method("GET")
+            .uri(format!("/logs/{}", payload.id))
+            .body(Body::empty())
+            .unwrap();
+        let response = test_app.clone().oneshot(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["stdout"], "out");
+        assert_eq!(json["stderr"], "");
+
+        let req = Request::builder()
+            .method("GET")
+            .uri("/logs/999")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(
+            test_app.oneshot(req).await.unwrap().status(),
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stream() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        init_db(&pool).await.unwrap();
+        let temp_dir = tempdir().unwrap();
+        let mut config = Config::new().unwrap();
+        config.data_path = temp_dir.path().to_str().unwrap().to_string();
+        let mut payload = Payload::new();
+        payload.add_to_db(&pool).await.unwrap();
+        payload.prepare(&config.data_path).unwrap();
+        payload
+            .update_status(Status::Completed, &pool)
+            .await
+            .unwrap();
+        std::fs::create_dir(payload.state_dir()).unwrap();
+        std::fs::write(payload.state_dir().join(STDOUT_LOG), b"one\ntwo").unwrap();
+        let state = AppState { pool, config };
+        let test_app = Router::new()
+            .route("/logs/{id}/stream", get(stream))
+            .with_state(state);
+
+        let req = Request::builder()
+            .method("GET")
+            .uri(format!("/logs/{}/stream", payload.id))
+            .body(Body::empty())
+            .unwrap();
+        let response = test_app.clone().oneshot(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // A finished payload sends what it has and ends the stream
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert_eq!(
+            body,
+            "event: stdout\ndata: one\n\nevent: status\ndata: completed\n\nevent: stdout\ndata: two\n\n"
+        );
+
+        let req = Request::builder()
+            .method("GET")
+            .uri("/logs/999/stream")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(
+            test_app.oneshot(req).await.unwrap().status(),
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cancel() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        init_db(&pool).await.unwrap();
+        let mut prepared = Payload::new();
+        prepared.add_to_db(&pool).await.unwrap();
+        prepared
+            .update_status(Status::Prepared, &pool)
+            .await
+            .unwrap();
+        let mut completed = Payload::new();
+        completed.add_to_db(&pool).await.unwrap();
+        completed
+            .update_status(Status::Completed, &pool)
+            .await
+            .unwrap();
+        let state = AppState {
+            pool: pool.clone(),
+            config: Config::new().unwrap(),
+        };
+        let test_app = Router::new()
+            .route("/payload/{id}", delete(cancel))
+            .with_state(state);
+
+        // A payload that did not start yet is cancelled
+        let req = Request::builder()
+            .method("DELETE")
+            .uri(format!("/payload/{}", prepared.id))
+            .body(Body::empty())
+            .unwrap();
+        let response = test_app.clone().oneshot(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let mut payload = Payload::new();
+        payload.retrieve_id(prepared.id, &pool).await.unwrap();
+        assert_eq!(payload.status, Status::Cancelled);
+
+        // A finished payload is not
+        let req = Request::builder()
+            .method("DELETE")
+            .uri(format!("/payload/{}", completed.id))
+            .body(Body::empty())
+            .unwrap();
+        let response = test_app.oneshot(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn test_cancel_running() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        init_db(&pool).await.unwrap();
+        let temp_dir = tempdir().unwrap();
+        let mut running = Payload::new();
+        running.set_loc(temp_dir.path().to_path_buf());
+        std::fs::write(running.loc.join("run.sh"), b"#!/bin/bash\nsleep 30").unwrap();
+        running.add_to_db(&pool).await.unwrap();
+        running
+            .update_status(Status::Processing, &pool)
+            .await
+            .unwrap();
+        let child = spawn_payload(&running).unwrap();
+        running
+            .update_pid(child.id().unwrap(), &pool)
+            .await
+            .unwrap();
+        let state = AppState {
+            pool: pool.clone(),
+            config: Config::new().unwrap(),
+        };
+        let test_app = Router::new()
+            .route("/payload/{id}", delete(cancel))
+            .with_state(state);
+
+        let req = Request::builder()
+            .method("DELETE")
+            .uri(format!("/payload/{}", running.id))
+            .body(Body::empty())
+            .unwrap();
+        let response = test_app.oneshot(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // The script was killed instead of sleeping on
+        let result =
+            tokio::time::timeout(Duration::from_secs(5), wait_payload(&running, child)).await;
+        assert!(result.unwrap().is_err());
+
+        let mut payload = Payload::new();
+        payload.retrieve_id(running.id, &pool).await.unwrap();
+        assert_eq!(payload.status, Status::Cancelled);
+    }
+
+    #[tokio::test]
+    async fn test_resources() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        let mut config = Config::new().unwrap();
+        config.resources = Resources {
+            cores: 16,
+            memory_mb: 64000,
+            scratch_mb: 0,
+        };
+        config.datasets = vec!["genomes".to_string()];
+        let state = State(AppState { pool, config });
+
+        let response = resources(state).await;
+        assert_eq!(response.0.resources.cores, 16);
+        assert_eq!(response.0.resources.memory_mb, 64000);
+        assert_eq!(response.0.datasets, vec!["genomes"]);
+    }
 }
@@ -1,26 +1,65 @@
-use crate::models::job_dao::Job;
+use crate::config::loader::{Config, ExtractLimits};
+use crate::models::archive_dto::{ArchiveFormat, ArchiveQuery};
+use crate::models::job_dao::{Job, JobDetail};
+use crate::models::job_dto::{list_jobs, list_retryable_jobs, normalize_timestamp};
+use crate::models::job_event_dao::JobEvent;
+use crate::models::job_event_dto::list_events;
+use crate::models::job_filter_dao::{JobFilter, JobPage, RequeueFilter, RequeueResult};
+use crate::models::logs_dto::{Logs, STREAM_INTERVAL};
+use crate::models::payload_dao::PARAMS_FILE;
+use crate::models::registration_dto::list_registrations;
+use crate::models::resources_dto::{Resources, MAX_REQUEST_MB};
+use crate::models::results_dto::{list_results, open_result, repack, ResultFile};
 use crate::models::status_dto::Status;
+use crate::models::upload_dao::{FinalizeUpload, InputError, InputErrors};
 use crate::routes::router::AppState;
-use crate::utils::io::{sanitize_filename, save_file};
+use crate::services::client::param_env_name;
+use crate::services::orchestrator::{self, CancelError, StreamError};
+use crate::services::validation;
+use crate::services::webhooks;
+use crate::utils::extract::{extract, ArchiveKind, ExtractError};
+use crate::utils::io::{
+    checksum_files, create_input_dirs, input_path, list_files, lock_exclusive, normalize_path,
+    save_file,
+};
+use crate::utils::serve::serve_file;
 use axum::{
-    extract::{Json, Multipart, Path, State},
-    http::StatusCode,
+    body::Body,
+    extract::{Json, Multipart, Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
 };
-use std::collections::HashMap;
+use futures::{Stream, StreamExt};
+use sqlx::SqlitePool;
+use std::collections::{HashMap, HashSet};
+use std::convert::Infallible;
+use std::path::PathBuf;
 use tokio::fs::create_dir_all;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+use tracing::warn;
 use utoipa;
 
 #[utoipa::path(
     get,
     path = "/download/{id}",
     params(
-        ("id" = i32, Path, description = "Job identifier")
+        ("id" = i32, Path, description = "Job identifier"),
+        ArchiveQuery
     ),
     responses(
         (status = 200, description = "File downloaded successfully", body = Vec<u8>),
         (status = 202, description = "Job not ready"),
-        (status = 204, description = "Job failed or cleaned"),
+        (status = 204, description = "Job failed, cancelled or cleaned"),
+        (status = 206, description = "Part of the file asked for with `Range`", body = Vec<u8>),
+        (status = 304, description = "Results unchanged since the `If-None-Match` ETag"),
+        (status = 400, description = "Unknown format"),
         (status = 404, description = "Job not found"),
+        (status = 406, description = "None of the `Accept` types is an archive format"),
+        (status = 416, description = "Range outside of the file"),
         (status = 500, description = "Internal server error")
     ),
     tag = "files"
@@ -28,7 +67,9 @@ use utoipa;
 pub async fn download(
     State(state): State<AppState>,
     Path(id): Path<i32>,
-) -> Result<Vec<u8>, StatusCode> {
+    Query(query): Query<ArchiveQuery>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
     let mut job = Job::new(&state.config.data_path);
 
     job.retrieve_id(id, &state.pool)
@@ -39,11 +80,709 @@ pub async fn download(
         })?;
 
     match job.status {
-        Status::Completed => Ok(job.download()),
-        Status::Failed => Err(StatusCode::NO_CONTENT),
-        Status::Cleaned => Err(StatusCode::NO_CONTENT),
+        Status::Completed => {}
+        Status::Failed | Status::TimedOut => return Err(StatusCode::NO_CONTENT),
+        Status::Cleaned => return Err(StatusCode::NO_CONTENT),
+        Status::Cancelled => return Err(StatusCode::NO_CONTENT),
         // TODO: Handle other status here
-        _ => Err(StatusCode::ACCEPTED),
+        _ => return Err(StatusCode::ACCEPTED),
+    }
+
+    // `?format=` goes over the `Accept` header, which goes over the service default
+    let (default, level) = state.config.get_archive(&job.service);
+    let format = match (query.format, headers.get(header::ACCEPT)) {
+        (Some(format), _) => ArchiveFormat::from_string(&format).ok_or(StatusCode::BAD_REQUEST)?,
+        (None, Some(accept)) => {
+            let accept = accept.to_str().map_err(|_| StatusCode::BAD_REQUEST)?;
+            ArchiveFormat::negotiate(accept, default).ok_or(StatusCode::NOT_ACCEPTABLE)?
+        }
+        (None, None) => default,
+    };
+
+    let path = job.results_path();
+    let not_found = |e: std::io::Error| match e.kind() {
+        std::io::ErrorKind::NotFound => StatusCode::NOT_FOUND,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    let mut response = match (format, level) {
+        // The archive retrieved from the backend is sent as is, so it can be resumed
+        (ArchiveFormat::Zip, None) => serve_file(&path, format.content_type(), &headers)
+            .await
+            .map_err(not_found)?,
+        _ => {
+            tokio::fs::metadata(&path).await.map_err(not_found)?;
+            let body = Body::from_stream(repack(path, format, level));
+            ([(header::CONTENT_TYPE, format.content_type())], body).into_response()
+        }
+    };
+    let disposition = format!("attachment; filename=\"{id}.{}\"", format.extension());
+    if let Ok(value) = disposition.parse() {
+        response
+            .headers_mut()
+            .insert(header::CONTENT_DISPOSITION, value);
+    }
+    Ok(response)
+}
+
+// Times are compared against `created_at`, so they have to be in the same format
+async fn normalize_range(
+    created_after: &mut Option<String>,
+    created_before: &mut Option<String>,
+    pool: &SqlitePool,
+) -> Result<(), (StatusCode, String)> {
+    for timestamp in [created_after, created_before] {
+        if let Some(value) = timestamp.as_deref() {
+            let normalized = normalize_timestamp(value, pool)
+                .await
+                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+                .ok_or((StatusCode::BAD_REQUEST, format!("Invalid time: {value}")))?;
+            *timestamp = Some(normalized);
+        }
+    }
+    Ok(())
+}
+
+#[utoipa::path(
+    get,
+    path = "/jobs",
+    params(JobFilter),
+    responses(
+        (status = 200, description = "A page of the matching jobs", body = JobPage),
+        (status = 400, description = "Invalid filter"),
+        (status = 500, description = "Internal server error")
+    ),
+    tag = "jobs"
+)]
+pub async fn list(
+    State(state): State<AppState>,
+    Query(mut filter): Query<JobFilter>,
+) -> Result<Json<JobPage>, (StatusCode, String)> {
+    if let Some(status) = &filter.status {
+        if Status::from_string(status) == Status::Unknown && !status.eq_ignore_ascii_case("unknown")
+        {
+            return Err((StatusCode::BAD_REQUEST, format!("Invalid status: {status}")));
+        }
+    }
+    if filter.metadata_pairs().is_none() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Invalid metadata filter".to_string(),
+        ));
+    }
+
+    normalize_range(
+        &mut filter.created_after,
+        &mut filter.created_before,
+        &state.pool,
+    )
+    .await?;
+
+    let page = list_jobs(&filter, &state.pool)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    Ok(Json(page))
+}
+
+#[utoipa::path(
+    get,
+    path = "/jobs/{id}",
+    params(
+        ("id" = i32, Path, description = "Job identifier")
+    ),
+    responses(
+        (status = 200, description = "Job status, lifecycle and expiry", body = JobDetail),
+        (status = 404, description = "Job not found"),
+        (status = 500, description = "Internal server error")
+    ),
+    tag = "jobs"
+)]
+pub async fn detail(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+) -> Result<Json<JobDetail>, StatusCode> {
+    let mut job = Job::new(&state.config.data_path);
+
+    job.retrieve_id(id, &state.pool)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => StatusCode::NOT_FOUND,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        })?;
+
+    let expires_at = job
+        .expires_at(state.config.max_age, &state.pool)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(Json(JobDetail { job, expires_at }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/jobs/{id}/events",
+    params(
+        ("id" = i32, Path, description = "Job identifier")
+    ),
+    responses(
+        (status = 200, description = "Status transitions of the job, oldest first", body = Vec<JobEvent>),
+        (status = 404, description = "Job not found"),
+        (status = 500, description = "Internal server error")
+    ),
+    tag = "jobs"
+)]
+pub async fn events(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+) -> Result<Json<Vec<JobEvent>>, StatusCode> {
+    let mut job = Job::new(&state.config.data_path);
+
+    job.retrieve_id(id, &state.pool)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => StatusCode::NOT_FOUND,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        })?;
+
+    let events = list_events(job.id, &state.pool)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(Json(events))
+}
+
+#[utoipa::path(
+    get,
+    path = "/jobs/{id}/logs",
+    params(
+        ("id" = i32, Path, description = "Job identifier")
+    ),
+    responses(
+        (status = 200, description = "Output of the finished job, also when it failed", body = Logs),
+        (status = 404, description = "Job or logs not found"),
+        (status = 500, description = "Internal server error")
+    ),
+    tag = "jobs"
+)]
+pub async fn logs(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+) -> Result<Json<Logs>, StatusCode> {
+    let mut job = Job::new(&state.config.data_path);
+
+    job.retrieve_id(id, &state.pool)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => StatusCode::NOT_FOUND,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        })?;
+
+    // Only there once the getter collected them
+    let logs = Logs::read(&job.state_dir()).map_err(|e| match e.kind() {
+        std::io::ErrorKind::NotFound => StatusCode::NOT_FOUND,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    })?;
+
+    Ok(Json(logs))
+}
+
+#[utoipa::path(
+    get,
+    path = "/jobs/{id}/files",
+    params(
+        ("id" = i32, Path, description = "Job identifier")
+    ),
+    responses(
+        (status = 200, description = "Files in the results of the job", body = Vec<ResultFile>),
+        (status = 404, description = "Job or results not found"),
+        (status = 500, description = "Internal server error")
+    ),
+    tag = "files"
+)]
+pub async fn files(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+) -> Result<Json<Vec<ResultFile>>, StatusCode> {
+    let mut job = Job::new(&state.config.data_path);
+
+    job.retrieve_id(id, &state.pool)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => StatusCode::NOT_FOUND,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        })?;
+
+    if job.status != Status::Completed {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    // The first listing reads the whole archive
+    let files = tokio::task::spawn_blocking(move || list_results(&job.state_dir()))
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => StatusCode::NOT_FOUND,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        })?;
+
+    Ok(Json(files))
+}
+
+#[utoipa::path(
+    get,
+    path = "/jobs/{id}/files/{path}",
+    params(
+        ("id" = i32, Path, description = "Job identifier"),
+        ("path" = String, Path, description = "Path of the file, as in `/jobs/{id}/files`")
+    ),
+    responses(
+        (status = 200, description = "Content of the file", content_type = "application/octet-stream", body = Vec<u8>),
+        (status = 404, description = "Job or file not found"),
+        (status = 500, description = "Internal server error")
+    ),
+    tag = "files"
+)]
+pub async fn file(
+    State(state): State<AppState>,
+    Path((id, path)): Path<(i32, String)>,
+) -> Result<Response, StatusCode> {
+    let mut job = Job::new(&state.config.data_path);
+
+    job.retrieve_id(id, &state.pool)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => StatusCode::NOT_FOUND,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        })?;
+
+    if job.status != Status::Completed {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    let (size, stream) = open_result(job.results_path(), path.clone())
+        .await
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => StatusCode::NOT_FOUND,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        })?;
+
+    let filename = path.rsplit('/').next().unwrap_or("file").replace('"', "");
+    let headers = [
+        (header::CONTENT_TYPE, "application/octet-stream".to_string()),
+        (header::CONTENT_LENGTH, size.to_string()),
+        (
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{filename}\""),
+        ),
+    ];
+    Ok((headers, Body::from_stream(stream)).into_response())
+}
+
+#[utoipa::path(
+    get,
+    path = "/jobs/{id}/stream",
+    params(
+        ("id" = i32, Path, description = "Job identifier")
+    ),
+    responses(
+        (status = 200, description = "Server-Sent Events: `status` with each status transition, as in `/jobs/{id}/events`, and `stdout` with the output relayed from the backend while the job runs. Ends once the job finished", content_type = "text/event-stream", body = String),
+        (status = 404, description = "Job not found"),
+        (status = 500, description = "Internal server error")
+    ),
+    tag = "jobs"
+)]
+pub async fn stream(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, StatusCode> {
+    let mut job = Job::new(&state.config.data_path);
+
+    job.retrieve_id(id, &state.pool)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => StatusCode::NOT_FOUND,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        })?;
+
+    // Something to send, or time to look at the job again
+    enum Next {
+        Stdout(String),
+        Tick,
+    }
+
+    let events = async_stream::stream! {
+        let (tx, mut rx) = mpsc::channel(100);
+        let mut relaying = false;
+        let mut last_event = 0;
+        let mut interval = tokio::time::interval(STREAM_INTERVAL);
+        loop {
+            let next = tokio::select! {
+                Some(line) = rx.recv() => Next::Stdout(line),
+                _ = interval.tick() => Next::Tick,
+            };
+            if let Next::Stdout(line) = next {
+                yield Ok(Event::default().event("stdout").data(line));
+                continue;
+            }
+
+            if job.retrieve_id(id, &state.pool).await.is_err() {
+                break;
+            }
+            let transitions = list_events(id, &state.pool).await.unwrap_or_default();
+            let seen = last_event;
+            for transition in transitions.into_iter().filter(|e| e.id > seen) {
+                last_event = transition.id;
+                if let Ok(event) = Event::default().event("status").json_data(&transition) {
+                    yield Ok(event);
+                }
+            }
+
+            // Follow the output from the backend once the job is there
+            if job.status == Status::Submitted && !relaying {
+                relaying = true;
+                let (pool, config, tx) = (state.pool.clone(), state.config.clone(), tx.clone());
+                tokio::spawn(async move {
+                    let mut j = Job::new(&config.data_path);
+                    if j.retrieve_id(id, &pool).await.is_err() {
+                        return;
+                    }
+                    let target = config.get_backend_kind(&j.service, &j.backend);
+                    match orchestrator::stream(&j, &config, target, tx).await {
+                        Ok(_) | Err(StreamError::NotConfigured) => {}
+                        Err(e) => warn!("Could not stream the output of job {}: {:?}", id, e),
+                    }
+                });
+            }
+
+            if job.status.is_finished() {
+                while let Ok(line) = rx.try_recv() {
+                    yield Ok(Event::default().event("stdout").data(line));
+                }
+                break;
+            }
+        }
+    };
+
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+#[utoipa::path(
+    post,
+    path = "/jobs/{id}/retry",
+    params(
+        ("id" = i32, Path, description = "Job identifier")
+    ),
+    responses(
+        (status = 200, description = "Job queued again, with the inputs it was uploaded with", body = Job),
+        (status = 404, description = "Job not found"),
+        (status = 409, description = "Job is not failed, timed out or unknown"),
+        (status = 410, description = "Inputs of the job are gone"),
+        (status = 500, description = "Internal server error"),
+        (status = 502, description = "Backend could not be reached to cancel the last run of an unknown job")
+    ),
+    tag = "jobs"
+)]
+pub async fn retry(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+) -> Result<Json<Job>, (StatusCode, String)> {
+    let mut job = Job::new(&state.config.data_path);
+
+    job.retrieve_id(id, &state.pool)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => (StatusCode::NOT_FOUND, "Job not found".to_string()),
+            _ => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+        })?;
+
+    if !job.status.is_retryable() {
+        return Err((
+            StatusCode::CONFLICT,
+            format!("Job is {}, it cannot be retried", job.status),
+        ));
+    }
+
+    match retry_job(&mut job, "Retried", &state).await? {
+        true => Ok(Json(job)),
+        false => Err((
+            StatusCode::CONFLICT,
+            format!("Job is already {}", job.status),
+        )),
+    }
+}
+
+// Queue a job again, whether it moved. Its inputs have to still be there
+async fn retry_job(
+    job: &mut Job,
+    reason: &str,
+    state: &AppState,
+) -> Result<bool, (StatusCode, String)> {
+    if !job.loc.is_dir() {
+        return Err((StatusCode::GONE, "Inputs of the job are gone".to_string()));
+    }
+    // Lost track of, it may still be running on its backend and must not run twice
+    if job.status == Status::Unknown && job.dest_id != 0 {
+        let target = state.config.get_backend_kind(&job.service, &job.backend);
+        match orchestrator::cancel(job, &state.config, target).await {
+            Ok(_) => {}
+            // Gone or finished on the backend, or no way to tell it
+            Err(
+                e @ (CancelError::JobNotFound
+                | CancelError::NotCancellable
+                | CancelError::NotFound
+                | CancelError::NotConfigured),
+            ) => warn!("Job {} not cancelled on its backend: {:?}", job.id, e),
+            Err(e) => return Err((StatusCode::BAD_GATEWAY, e.to_string())),
+        }
+    }
+    let retried = job
+        .retry(reason, &state.pool)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    // Only once it is certain to run again, another retry may have won
+    if retried {
+        job.remove_outputs()
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    }
+    Ok(retried)
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/requeue",
+    request_body = RequeueFilter,
+    responses(
+        (status = 200, description = "Jobs queued again, and the ones whose inputs are gone or whose last run could not be cancelled", body = RequeueResult),
+        (status = 400, description = "Invalid filter"),
+        (status = 500, description = "Internal server error")
+    ),
+    tag = "admin"
+)]
+pub async fn requeue(
+    State(state): State<AppState>,
+    Json(mut filter): Json<RequeueFilter>,
+) -> Result<Json<RequeueResult>, (StatusCode, String)> {
+    if let Some(status) = &filter.status {
+        if Status::from_string(status) == Status::Unknown && !status.eq_ignore_ascii_case("unknown")
+        {
+            return Err((StatusCode::BAD_REQUEST, format!("Invalid status: {status}")));
+        }
+        if !Status::from_string(status).is_retryable() {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("Status cannot be retried: {status}"),
+            ));
+        }
+    }
+
+    normalize_range(
+        &mut filter.created_after,
+        &mut filter.created_before,
+        &state.pool,
+    )
+    .await?;
+
+    let jobs = list_retryable_jobs(&filter, &state.pool)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    let mut result = RequeueResult {
+        requeued: Vec::new(),
+        skipped: Vec::new(),
+    };
+    for mut job in jobs {
+        match retry_job(&mut job, "Requeued by an operator", &state).await {
+            Ok(true) => result.requeued.push(job.id),
+            // Moved on in the meantime
+            Ok(false) => {}
+            Err((StatusCode::GONE | StatusCode::BAD_GATEWAY, _)) => result.skipped.push(job.id),
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(Json(result))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/jobs/{id}",
+    params(
+        ("id" = i32, Path, description = "Job identifier")
+    ),
+    responses(
+        (status = 200, description = "Job cancelled", body = Job),
+        (status = 404, description = "Job not found"),
+        (status = 409, description = "Job already finished"),
+        (status = 500, description = "Internal server error"),
+        (status = 502, description = "Backend could not be reached to cancel the job")
+    ),
+    tag = "jobs"
+)]
+pub async fn cancel(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+) -> Result<Json<Job>, (StatusCode, String)> {
+    let mut job = Job::new(&state.config.data_path);
+
+    job.retrieve_id(id, &state.pool)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => (StatusCode::NOT_FOUND, "Job not found".to_string()),
+            _ => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+        })?;
+
+    // The tasks can move the job along while it is being cancelled, in which case it is
+    //  looked at again in its new status
+    loop {
+        let current = job.status.clone();
+        match current {
+            Status::Cancelled => return Ok(Json(job)),
+            Status::Completed
+            | Status::Failed
+            | Status::TimedOut
+            | Status::Cleaned
+            | Status::Unknown => {
+                return Err((StatusCode::CONFLICT, format!("Job is already {current}")));
+            }
+            Status::Submitted => {
+                let target = state.config.get_backend_kind(&job.service, &job.backend);
+                match orchestrator::cancel(&job, &state.config, target).await {
+                    Ok(_) => {}
+                    // Gone or finished on the backend, or no way to tell it
+                    Err(
+                        e @ (CancelError::JobNotFound
+                        | CancelError::NotCancellable
+                        | CancelError::NotFound
+                        | CancelError::NotConfigured),
+                    ) => warn!("Job {} not cancelled on its backend: {:?}", job.id, e),
+                    Err(e) => return Err((StatusCode::BAD_GATEWAY, e.to_string())),
+                }
+            }
+            _ => {}
+        }
+
+        let cancelled = job
+            .transition_status(current.clone(), Status::Cancelled, &state.pool)
+            .await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+        if cancelled {
+            // While `Processing` the sender is uploading from `loc`, it cleans up itself
+            if current != Status::Processing {
+                if let Err(e) = job.remove_from_disk() {
+                    warn!("Could not remove {:?}: {:?}", job.loc, e);
+                }
+            }
+            return Ok(Json(job));
+        }
+
+        job.retrieve_id(id, &state.pool)
+            .await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    }
+}
+
+// Fields of `/upload` with a meaning of their own, the others are the metadata of the job
+const UPLOAD_FIELDS: &[&str] = &[
+    "user_id",
+    "service",
+    "labels",
+    "datasets",
+    "callback_url",
+    "cores",
+    "memory_mb",
+    "scratch_mb",
+    "extract",
+];
+
+// Error of the upload endpoints, inputs that do not fit the service are listed in the body
+#[derive(Debug)]
+pub enum UploadRejection {
+    Status(StatusCode, String),
+    Invalid(Vec<InputError>),
+}
+
+impl From<(StatusCode, String)> for UploadRejection {
+    fn from((status, message): (StatusCode, String)) -> Self {
+        UploadRejection::Status(status, message)
+    }
+}
+
+impl IntoResponse for UploadRejection {
+    fn into_response(self) -> Response {
+        match self {
+            UploadRejection::Status(status, message) => (status, message).into_response(),
+            UploadRejection::Invalid(errors) => {
+                (StatusCode::BAD_REQUEST, Json(InputErrors { errors })).into_response()
+            }
+        }
+    }
+}
+
+// Removes the directory of an upload that is refused, or dropped half way, unless it is
+//  kept once the job is in the database
+struct UploadDir(Option<PathBuf>);
+
+impl UploadDir {
+    fn keep(mut self) {
+        self.0 = None;
+    }
+}
+
+impl Drop for UploadDir {
+    fn drop(&mut self) {
+        if let Some(loc) = self.0.take() {
+            let _ = std::fs::remove_dir_all(loc);
+        }
+    }
+}
+
+// Check the inputs of `job` against the schema of its service, if it has one
+async fn validate_inputs(job: &Job, config: &Config) -> Result<(), UploadRejection> {
+    // The client writes the parameters there, see `Payload::prepare`
+    if !job.params.is_empty() && job.loc.join(PARAMS_FILE).exists() {
+        return Err(UploadRejection::Invalid(vec![InputError {
+            input: Some(PARAMS_FILE.to_string()),
+            message: "Reserved for the parameters of the job".to_string(),
+        }]));
+    }
+    let Some(schema) = config.get_inputs(&job.service) else {
+        return Ok(());
+    };
+    let errors = validation::validate(&job.loc, &job.metadata, schema)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    match errors.is_empty() {
+        true => Ok(()),
+        false => Err(UploadRejection::Invalid(errors)),
+    }
+}
+
+// Refuse resources that no backend of the service advertises that much of. A backend that
+//  did not advertise its resources yet might have them
+async fn check_resources(
+    job: &Job,
+    config: &Config,
+    pool: &SqlitePool,
+) -> Result<(), (StatusCode, String)> {
+    let Some(service) = config.services.get(&job.service) else {
+        return Ok(());
+    };
+    let registrations = list_registrations(pool)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let mut backends = std::iter::once("").chain(service.backends.keys().map(String::as_str));
+    let fits = backends.any(|backend| {
+        registrations
+            .get(&(job.service.clone(), backend.to_string()))
+            .is_none_or(|r| r.resources.can_take(&Resources::default(), &job.resources))
+    });
+    match fits {
+        true => Ok(()),
+        false => Err((
+            StatusCode::BAD_REQUEST,
+            "No backend has the resources requested".to_string(),
+        )),
     }
 }
 
@@ -54,11 +793,17 @@ pub async fn download(
         content_type = "multipart/form-data",
         description = "Upload a file and metadata fields as multipart/form-data. \
         The request must include a file field (with any filename and content type), a 'user_id' field (integer), and a 'service' field (string). \
-        Additional fields may be included as needed."
+        An optional 'labels' field takes a comma separated list of labels, used for routing. \
+        An optional 'datasets' field takes a comma separated list of datasets the job needs on its backend. \
+        An optional 'callback_url' field takes an http(s) url that is notified once the job is finished. \
+        Optional 'cores', 'memory_mb' and 'scratch_mb' fields request resources, the job is only dispatched to a backend that has them free. \
+        With 'extract' set to 'true', the .zip, .tar, .tar.gz and .tgz files are unpacked in the job directory, in place of the archives. \
+        Any other text field is kept as metadata of the job, ie a run name or project id, and can be filtered on in '/jobs'. \
+        The files and metadata are then checked against the input schema of the service, when it has one."
     ),
     responses(
         (status = 200, description = "File uploaded successfully", body = Job),
-        (status = 400, description = "Bad request"),
+        (status = 400, description = "Bad request, or inputs that do not fit the service, listed in the body", body = InputErrors),
         (status = 500, description = "Internal server error"),
         (status = 503, description = "Service unavailable")
     ),
@@ -67,7 +812,7 @@ pub async fn download(
 pub async fn upload(
     State(state): State<AppState>,
     mut multipart: Multipart,
-) -> Result<Json<Job>, (StatusCode, String)> {
+) -> Result<Json<Job>, UploadRejection> {
     // Create a new job with unique ID
     let mut job = Job::new(&state.config.data_path);
 
@@ -78,9 +823,14 @@ pub async fn upload(
             format!("Failed to create directory: {e}"),
         )
     })?;
+    let upload_dir = UploadDir(Some(job.loc.clone()));
 
     let mut text_fields = HashMap::new();
+    let mut input_paths = HashSet::new();
+    // Unpacked once the form is read, when it asks for it
+    let mut archives = Vec::new();
     let mut file_count = 0;
+    let mut input_size: u64 = 0;
 
     // Process each field in the multipart stream
     while let Some(field) = multipart.next_field().await.map_err(|e| {
@@ -91,13 +841,32 @@ pub async fn upload(
 
         if let Some(filename) = field.file_name() {
             file_count += 1;
-            let filename = sanitize_filename(filename); // Important for security!
-            let file_path = job.loc.join(&filename);
+            // Important for security!
+            let relative =
+                input_path(field.name(), filename).map_err(|e| (e.status_code(), e.to_string()))?;
+            if !input_paths.insert(relative.clone()) {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    format!("Duplicate input '{}'", relative.display()),
+                )
+                    .into());
+            }
+            let file_path = create_input_dirs(&job.loc, &relative)
+                .map_err(|e| (e.status_code(), e.to_string()))?;
 
-            tracing::info!("Saving file: {} to {}", filename, file_path.display());
+            tracing::info!(
+                "Saving file: {} to {}",
+                relative.display(),
+                file_path.display()
+            );
 
             // Create and save the file
-            save_file(field, &file_path).await?;
+            let kind = ArchiveKind::from_name(filename);
+            let size = save_file(field, &file_path).await?;
+            input_size += size;
+            if let Some(kind) = kind {
+                archives.push((file_path, kind, size));
+            }
         } else {
             // Handle text field
             let text = field.text().await.map_err(|e| {
@@ -116,35 +885,528 @@ pub async fn upload(
         file_count,
         text_fields.len()
     );
+    let extract = text_fields.get("extract").is_some_and(|v| v == "true");
+
+    apply_fields(&mut job, text_fields, &state.config)?;
+    check_resources(&job, &state.config, &state.pool).await?;
+
+    if extract {
+        if archives.is_empty() {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "Nothing to extract, archives are .zip, .tar, .tar.gz or .tgz".to_string(),
+            )
+                .into());
+        }
+        input_size =
+            extract_inputs(&job.loc, archives, state.config.extract_limits, input_size).await?;
+    }
+    job.set_input_size(input_size);
 
-    // Now handle special fields
+    // A broken submission is refused now rather than failing on its backend
+    validate_inputs(&job, &state.config).await?;
+
+    // Add job to database
+    job.add_to_db(&state.pool)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    // The cleaner takes care of it from now on
+    upload_dir.keep();
+
+    job.update_status(Status::Queued, &state.pool)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    Ok(Json(job))
+}
+
+// Set what the text fields of `/upload`, or the fields of `/uploads`, ask for on `job`
+fn apply_fields(
+    job: &mut Job,
+    text_fields: HashMap<String, String>,
+    config: &Config,
+) -> Result<(), (StatusCode, String)> {
     let user_id = text_fields
         .get("user_id")
         .ok_or((StatusCode::BAD_REQUEST, "Missing user_id".to_string()))?
         .parse::<i32>()
         .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid user_id".to_string()))?;
 
-    let service = text_fields
-        .get("service")
-        .ok_or((StatusCode::BAD_REQUEST, "Missing service".to_string()))?
-        .to_string();
+    let service = text_fields
+        .get("service")
+        .ok_or((StatusCode::BAD_REQUEST, "Missing service".to_string()))?
+        .to_string();
+
+    // Validate service exists
+    if !config.services.contains_key(&service) {
+        return Err((StatusCode::BAD_REQUEST, "Invalid service".to_string()));
+    }
+
+    job.set_user_id(user_id);
+    job.set_service(service);
+    job.set_max_runtime(config.get_max_runtime(&job.service));
+    if let Some(labels) = text_fields.get("labels") {
+        job.set_labels(labels);
+    }
+    if let Some(datasets) = text_fields.get("datasets") {
+        job.set_datasets(datasets);
+    }
+    if let Some(callback_url) = text_fields.get("callback_url") {
+        // Notifications are always signed
+        if config.webhook_secret.0.is_empty() {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "Callbacks are not enabled on this server".to_string(),
+            ));
+        }
+        match reqwest::Url::parse(callback_url) {
+            Ok(url)
+                if ["http", "https"].contains(&url.scheme()) && !webhooks::is_internal(&url) =>
+            {
+                job.set_callback_url(callback_url.to_string())
+            }
+            _ => return Err((StatusCode::BAD_REQUEST, "Invalid callback_url".to_string())),
+        }
+    }
+
+    // Optional resource requests
+    let mut resources = job.resources;
+    if let Some(cores) = text_fields.get("cores") {
+        resources.cores = cores
+            .parse()
+            .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid cores".to_string()))?;
+    }
+    if let Some(memory_mb) = text_fields.get("memory_mb") {
+        resources.memory_mb = memory_mb
+            .parse()
+            .ok()
+            .filter(|mb| *mb <= MAX_REQUEST_MB)
+            .ok_or((StatusCode::BAD_REQUEST, "Invalid memory_mb".to_string()))?;
+    }
+    if let Some(scratch_mb) = text_fields.get("scratch_mb") {
+        resources.scratch_mb = scratch_mb
+            .parse()
+            .ok()
+            .filter(|mb| *mb <= MAX_REQUEST_MB)
+            .ok_or((StatusCode::BAD_REQUEST, "Invalid scratch_mb".to_string()))?;
+    }
+    job.set_resources(resources);
+
+    // Everything else is kept as it is
+    let metadata = text_fields
+        .into_iter()
+        .filter(|(name, _)| !UPLOAD_FIELDS.contains(&name.as_str()))
+        .collect();
+    let params = config.get_params(&job.service, &metadata);
+    // They end up in the environment of `run.sh`, one variable each
+    let mut names = HashMap::new();
+    for (key, value) in &params {
+        if value.contains('\0') {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("Invalid parameter '{key}'"),
+            ));
+        }
+        if let Some(other) = names.insert(param_env_name(key), key) {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("Parameters '{other}' and '{key}' have the same variable"),
+            ));
+        }
+    }
+    job.set_params(params);
+    job.set_metadata(metadata);
+
+    Ok(())
+}
+
+// Unpack the `archives` in `loc`, in place of themselves, and give what `input_size` becomes.
+//  Nothing is kept when one of them can't be, what was extracted up to there could be large
+async fn extract_inputs(
+    loc: &std::path::Path,
+    archives: Vec<(PathBuf, ArchiveKind, u64)>,
+    limits: ExtractLimits,
+    mut input_size: u64,
+) -> Result<u64, (StatusCode, String)> {
+    for (archive, kind, size) in archives {
+        let dest = loc.to_path_buf();
+        let extracted = tokio::task::spawn_blocking(move || {
+            let extracted = extract(&archive, kind, &dest, &limits)?;
+            std::fs::remove_file(&archive)?;
+            Ok::<_, ExtractError>(extracted)
+        })
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+        .and_then(|r| r.map_err(|e| (e.status_code(), e.to_string())));
+        match extracted {
+            // The input is what is in the archive
+            Ok(extracted) => input_size = input_size - size + extracted,
+            Err(e) => {
+                let _ = tokio::fs::remove_dir_all(loc).await;
+                return Err(e);
+            }
+        }
+    }
+    Ok(input_size)
+}
+
+// How much of a file sent with `/uploads` is there, where the next chunk goes
+const UPLOAD_OFFSET: &str = "upload-offset";
+
+// A job that is still waiting for its inputs
+async fn uploading_job(id: i32, state: &AppState) -> Result<Job, (StatusCode, String)> {
+    let mut job = Job::new(&state.config.data_path);
+    job.retrieve_id(id, &state.pool)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => (StatusCode::NOT_FOUND, "Job not found".to_string()),
+            _ => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+        })?;
+    match job.status {
+        Status::Uploading => Ok(job),
+        status => Err((StatusCode::CONFLICT, format!("Job is {status}"))),
+    }
+}
+
+// Chunks of a job are written one at a time, and not while it is finalized. Its directory
+//  stays locked until the returned file is dropped
+async fn lock_job(loc: &std::path::Path) -> Result<std::fs::File, (StatusCode, String)> {
+    let loc = loc.to_path_buf();
+    tokio::task::spawn_blocking(move || lock_exclusive(&loc))
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+}
+
+#[utoipa::path(
+    post,
+    path = "/uploads",
+    request_body(
+        content = HashMap<String, String>,
+        description = "The same fields as the text fields of '/upload', 'user_id' and 'service' are required. \
+        'upload_length' is required as well, the total size in bytes of the files that will be uploaded."
+    ),
+    responses(
+        (status = 201, description = "Job waiting for its inputs", body = Job),
+        (status = 400, description = "Bad request"),
+        (status = 413, description = "'upload_length' is more than the server or the service accepts"),
+        (status = 500, description = "Internal server error")
+    ),
+    tag = "files"
+)]
+pub async fn create_upload(
+    State(state): State<AppState>,
+    Json(fields): Json<HashMap<String, serde_json::Value>>,
+) -> Result<(StatusCode, Json<Job>), (StatusCode, String)> {
+    // Numbers are taken as they are written, like in a form
+    let mut text_fields: HashMap<String, String> = fields
+        .into_iter()
+        .map(|(name, value)| match value {
+            serde_json::Value::String(value) => (name, value),
+            value => (name, value.to_string()),
+        })
+        .collect();
+    let upload_length: u64 = text_fields
+        .remove("upload_length")
+        .ok_or((StatusCode::BAD_REQUEST, "Missing upload_length".to_string()))?
+        .parse()
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid upload_length".to_string()))?;
+
+    let mut job = Job::new(&state.config.data_path);
+    apply_fields(&mut job, text_fields, &state.config)?;
+    check_resources(&job, &state.config, &state.pool).await?;
+
+    let max_size = state
+        .config
+        .get_inputs(&job.service)
+        .and_then(|schema| schema.max_size)
+        .map_or(state.config.max_upload_size, |max| {
+            max.min(state.config.max_upload_size)
+        });
+    if upload_length > max_size {
+        return Err((
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("At most {max_size} bytes can be uploaded"),
+        ));
+    }
+    // What the job is going to get, until it is finalized with what it got
+    job.set_input_size(upload_length);
+
+    create_dir_all(&job.loc).await.map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to create directory: {e}"),
+        )
+    })?;
+    job.add_to_db(&state.pool)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    job.update_status(Status::Uploading, &state.pool)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    Ok((StatusCode::CREATED, Json(job)))
+}
+
+#[utoipa::path(
+    patch,
+    path = "/uploads/{id}/files/{path}",
+    params(
+        ("id" = i32, Path, description = "Job identifier"),
+        ("path" = String, Path, description = "Path of the file in the job directory"),
+        ("Upload-Offset" = u64, Header, description = "Size of the file so far, where the chunk goes")
+    ),
+    request_body(content = Vec<u8>, content_type = "application/octet-stream"),
+    responses(
+        (status = 204, description = "Chunk written, `Upload-Offset` has the new size of the file"),
+        (status = 400, description = "Invalid path or Upload-Offset"),
+        (status = 404, description = "Job not found"),
+        (status = 409, description = "Job is not uploading, or the file has another size, given in `Upload-Offset`"),
+        (status = 413, description = "The chunk goes over the 'upload_length' of the job, nothing of it is kept"),
+        (status = 500, description = "Internal server error")
+    ),
+    tag = "files"
+)]
+pub async fn upload_chunk(
+    State(state): State<AppState>,
+    Path((id, path)): Path<(i32, String)>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<Response, (StatusCode, String)> {
+    let offset: u64 = headers
+        .get(UPLOAD_OFFSET)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok())
+        .ok_or((
+            StatusCode::BAD_REQUEST,
+            "Missing or invalid Upload-Offset".to_string(),
+        ))?;
+    let relative = normalize_path(&path).map_err(|e| (e.status_code(), e.to_string()))?;
+
+    let job = uploading_job(id, &state).await?;
+    let _lock = lock_job(&job.loc).await?;
+    // Finalized while this one waited for the lock
+    let job = uploading_job(id, &state).await?;
+    job.update_last_chunk(&state.pool)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let received: u64 = list_files(&job.loc)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .values()
+        .sum();
+    let remaining = job.input_size.saturating_sub(received);
+
+    let file_path =
+        create_input_dirs(&job.loc, &relative).map_err(|e| (e.status_code(), e.to_string()))?;
+    let mut file = tokio::fs::OpenOptions::new()
+        .append(true)
+        .create(true)
+        .custom_flags(libc::O_NOFOLLOW)
+        .open(&file_path)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let mut size = file
+        .metadata()
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .len();
+    // A chunk that was sent again, or one that was skipped
+    if size != offset {
+        return Ok((
+            StatusCode::CONFLICT,
+            [(UPLOAD_OFFSET, size.to_string())],
+            format!("File is {size} bytes"),
+        )
+            .into_response());
+    }
+
+    // What made it before the connection dropped is kept, the upload resumes from there
+    let mut stream = body.into_data_stream();
+    while let Some(chunk) = stream.next().await {
+        let chunk =
+            chunk.map_err(|e| (StatusCode::BAD_REQUEST, format!("Chunk read failed: {e}")))?;
+        if size - offset + chunk.len() as u64 > remaining {
+            file.set_len(offset)
+                .await
+                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+            return Err((
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!("More than the {} bytes declared", job.input_size),
+            ));
+        }
+        file.write_all(&chunk).await.map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Write failed: {e}"),
+            )
+        })?;
+        size += chunk.len() as u64;
+    }
+    file.flush().await.map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Flush failed: {e}"),
+        )
+    })?;
+
+    Ok((StatusCode::NO_CONTENT, [(UPLOAD_OFFSET, size.to_string())]).into_response())
+}
+
+#[utoipa::path(
+    head,
+    path = "/uploads/{id}/files/{path}",
+    params(
+        ("id" = i32, Path, description = "Job identifier"),
+        ("path" = String, Path, description = "Path of the file in the job directory")
+    ),
+    responses(
+        (status = 200, description = "`Upload-Offset` has the size of the file so far, 0 before its first chunk"),
+        (status = 400, description = "Invalid path"),
+        (status = 404, description = "Job not found"),
+        (status = 409, description = "Job is not uploading"),
+        (status = 500, description = "Internal server error")
+    ),
+    tag = "files"
+)]
+pub async fn upload_offset(
+    State(state): State<AppState>,
+    Path((id, path)): Path<(i32, String)>,
+) -> Result<Response, (StatusCode, String)> {
+    let job = uploading_job(id, &state).await?;
+    let relative = normalize_path(&path).map_err(|e| (e.status_code(), e.to_string()))?;
+
+    let size = match tokio::fs::symlink_metadata(job.loc.join(relative)).await {
+        Ok(m) if m.is_file() => m.len(),
+        Ok(_) => return Err((StatusCode::BAD_REQUEST, "Not a file".to_string())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => 0,
+        Err(e) => return Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+    };
+    Ok((
+        StatusCode::OK,
+        [
+            (UPLOAD_OFFSET, size.to_string()),
+            (header::CACHE_CONTROL.as_str(), "no-store".to_string()),
+        ],
+    )
+        .into_response())
+}
+
+#[utoipa::path(
+    post,
+    path = "/uploads/{id}/finalize",
+    params(
+        ("id" = i32, Path, description = "Job identifier")
+    ),
+    request_body = FinalizeUpload,
+    responses(
+        (status = 200, description = "Job queued", body = Job),
+        (status = 400, description = "Missing, unexpected or corrupted files, invalid archives, or inputs that do not fit the service, listed in the body", body = InputErrors),
+        (status = 404, description = "Job not found"),
+        (status = 409, description = "Job is not uploading"),
+        (status = 500, description = "Internal server error")
+    ),
+    tag = "files"
+)]
+pub async fn finalize_upload(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+    Json(request): Json<FinalizeUpload>,
+) -> Result<Json<Job>, UploadRejection> {
+    let job = uploading_job(id, &state).await?;
+    let _lock = lock_job(&job.loc).await?;
+    // Finalized by another request while this one waited for the lock
+    let mut job = uploading_job(id, &state).await?;
+
+    // Reading every file takes a while for large inputs
+    let loc = job.loc.clone();
+    let uploaded = tokio::task::spawn_blocking(move || checksum_files(&loc))
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
 
-    // Validate service exists
-    if !state.config.services.contains_key(&service) {
-        return Err((StatusCode::BAD_REQUEST, "Invalid service".to_string()));
+    let mut expected = HashMap::new();
+    for (path, sha256) in &request.files {
+        let relative = normalize_path(path).map_err(|e| (e.status_code(), e.to_string()))?;
+        expected.insert(relative.to_string_lossy().to_string(), sha256);
+    }
+    let mut problems = Vec::new();
+    for (path, sha256) in &expected {
+        match uploaded.get(path) {
+            None => problems.push(format!("'{path}' was not uploaded")),
+            Some((_, actual)) if !actual.eq_ignore_ascii_case(sha256) => {
+                problems.push(format!("'{path}' has checksum {actual}"))
+            }
+            Some(_) => {}
+        }
+    }
+    for path in uploaded.keys().filter(|p| !expected.contains_key(*p)) {
+        problems.push(format!("'{path}' has no checksum"));
+    }
+    if !problems.is_empty() {
+        problems.sort();
+        return Err((StatusCode::BAD_REQUEST, problems.join("; ")).into());
     }
 
-    job.set_user_id(user_id);
-    job.set_service(service);
+    let mut input_size = uploaded.values().map(|(size, _)| size).sum();
+    if request.extract {
+        let archives = uploaded
+            .iter()
+            .filter_map(|(path, (size, _))| {
+                ArchiveKind::from_name(path).map(|kind| (job.loc.join(path), kind, *size))
+            })
+            .collect();
+        input_size =
+            match extract_inputs(&job.loc, archives, state.config.extract_limits, input_size).await
+            {
+                Ok(input_size) => input_size,
+                // Its inputs are gone, it won't go anywhere
+                Err((status, reason)) => {
+                    job.update_failure(
+                        Some(Status::Uploading),
+                        Status::Failed,
+                        &reason,
+                        &state.pool,
+                    )
+                    .await
+                    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+                    return Err((status, reason).into());
+                }
+            };
+    }
 
-    // Add job to database
-    job.add_to_db(&state.pool)
+    match validate_inputs(&job, &state.config).await {
+        // Like a direct upload that is refused, nothing is kept. Other errors leave the job
+        //  uploading, to be finalized again
+        Err(UploadRejection::Invalid(errors)) => {
+            let _ = tokio::fs::remove_dir_all(&job.loc).await;
+            job.update_failure(
+                Some(Status::Uploading),
+                Status::Failed,
+                "Invalid inputs",
+                &state.pool,
+            )
+            .await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+            return Err(UploadRejection::Invalid(errors));
+        }
+        Err(e) => return Err(e),
+        Ok(()) => {}
+    }
+
+    job.update_input_size(input_size, &state.pool)
         .await
         .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
-
-    job.update_status(Status::Queued, &state.pool)
+    let queued = job
+        .transition_status(Status::Uploading, Status::Queued, &state.pool)
         .await
         .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    if !queued {
+        return Err((
+            StatusCode::CONFLICT,
+            "Job is no longer uploading".to_string(),
+        )
+            .into());
+    }
 
     Ok(Json(job))
 }
@@ -152,13 +1414,17 @@ pub async fn upload(
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config::loader::{Config, Service};
+    use crate::config::loader::{Config, InputFile, InputSchema, Secret, Service, Validator};
+    use crate::models::logs_dto::STDOUT_LOG;
+    use crate::models::registration_dao::Registration;
+    use crate::models::resources_dto::Advertisement;
+    use crate::models::results_dto::{RESULTS_ARCHIVE, RESULTS_INDEX};
     use crate::routes::router::AppState;
     use axum::body::to_bytes;
     use axum::body::Body;
-    use axum::{routing::post, Router};
+    use axum::{routing::get, routing::post, Router};
     use http::{header, Request, StatusCode};
-    use sqlx::SqlitePool;
+    use std::collections::BTreeMap;
     use std::fs;
     use std::io::Write;
     use std::path::PathBuf;
@@ -168,22 +1434,8 @@ mod tests {
 
     // Helper function to initialize the database schema
     pub async fn init_db(pool: &SqlitePool) -> Result<(), sqlx::Error> {
-        sqlx::query(
-            r#"
-        CREATE TABLE IF NOT EXISTS jobs (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            user_id INTEGER NOT NULL,
-            service TEXT NOT NULL,
-            status TEXT NOT NULL,
-            loc TEXT NOT NULL,
-            dest_id TEXT,
-            created_at DATETIME DEFAULT CURRENT_TIMESTAMP
-        )
-    "#,
-        )
-        .execute(pool)
-        .await?;
-        Ok(())
+        crate::models::job_dto::create_jobs_table(pool).await?;
+        crate::models::registration_dto::create_registrations_table(pool).await
     }
 
     // Helper functions to create multipart form data
@@ -238,8 +1490,11 @@ mod tests {
                 upload_url: String::from("http://localhost/upload"),
                 download_url: String::from("http://localhost/download"),
                 runs_per_user: 5,
+                params: vec!["run_name".to_string()],
+                ..Default::default()
             },
         )]);
+        config.webhook_secret = Secret("secret".to_string());
 
         let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
         init_db(&pool).await.unwrap(); // Initialize the database schema
@@ -254,6 +1509,15 @@ mod tests {
         let mut body = Vec::new();
         body.extend(form_field(&boundary, "service", "test-service"));
         body.extend(form_field(&boundary, "user_id", "42"));
+        body.extend(form_field(&boundary, "labels", "gpu,large"));
+        body.extend(form_field(&boundary, "memory_mb", "2048"));
+        body.extend(form_field(&boundary, "project_id", "p-17"));
+        body.extend(form_field(&boundary, "run_name", "first"));
+        body.extend(form_field(
+            &boundary,
+            "callback_url",
+            "https://portal.example.com/hook",
+        ));
         body.extend(form_file(
             &boundary,
             "file",
@@ -280,23 +1544,697 @@ mod tests {
             .body(Body::from(body))
             .unwrap();
 
-        // Make the request
-        let response = app.oneshot(req).await.unwrap();
-        assert_eq!(response.status(), StatusCode::OK);
+        // Make the request
+        let response = app.oneshot(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["id"], 1);
+        assert_eq!(json["status"], String::from("Queued"));
+        assert_eq!(json["service"], String::from("test-service"));
+        assert_eq!(json["user_id"], 42);
+        assert_eq!(json["labels"], serde_json::json!(["gpu", "large"]));
+        assert_eq!(json["input_size"], 4 + 25);
+        assert_eq!(json["resources"]["cores"], 1);
+        assert_eq!(json["resources"]["memory_mb"], 2048);
+        assert_eq!(json["callback_url"], "https://portal.example.com/hook");
+        // Only the fields without a meaning of their own
+        assert_eq!(
+            json["metadata"],
+            serde_json::json!({"project_id": "p-17", "run_name": "first"})
+        );
+        // Only the ones the service asks for
+        assert_eq!(json["params"], serde_json::json!({"run_name": "first"}));
+
+        // Check if the file was saved correctly
+        let expected_loc = json["loc"].as_str().unwrap();
+        let expected_file = PathBuf::from(expected_loc).join("test.txt");
+        assert!(expected_file.exists());
+        let expected_file = PathBuf::from(expected_loc).join("test01.txt");
+        assert!(expected_file.exists());
+    }
+
+    #[tokio::test]
+    async fn test_upload_nested_inputs() {
+        let data_dir = tempdir().unwrap();
+        let mut config = Config::new().unwrap();
+        config.data_path = data_dir.path().to_str().unwrap().to_string();
+        config.services = HashMap::from([(
+            String::from("test-service"),
+            Service {
+                name: String::from("test-service"),
+                ..Default::default()
+            },
+        )]);
+
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        init_db(&pool).await.unwrap();
+        let state = AppState { pool, config };
+
+        let app = Router::new()
+            .route("/upload", post(upload))
+            .with_state(state);
+        let send = |files: Vec<(&str, &str)>| {
+            let boundary = format!("----Boundary{}", Uuid::new_v4());
+            let mut body = Vec::new();
+            body.extend(form_field(&boundary, "service", "test-service"));
+            body.extend(form_field(&boundary, "user_id", "42"));
+            for (name, filename) in files {
+                body.extend(form_text_file(&boundary, name, filename, name));
+            }
+            body.extend(format!("--{boundary}--\r\n").as_bytes());
+            let req = Request::builder()
+                .method("POST")
+                .uri("/upload")
+                .header(
+                    header::CONTENT_TYPE,
+                    format!("multipart/form-data; boundary={boundary}"),
+                )
+                .body(Body::from(body))
+                .unwrap();
+            app.clone().oneshot(req)
+        };
+
+        // As sent by `Client::upload` of another orchestrator, and by curl
+        let response = send(vec![
+            ("data/ligands/a.mol2", "a.mol2"),
+            ("file", "data/receptors/a.mol2"),
+        ])
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let loc = PathBuf::from(json["loc"].as_str().unwrap());
+        assert_eq!(
+            fs::read_to_string(loc.join("data/ligands/a.mol2")).unwrap(),
+            "data/ligands/a.mol2"
+        );
+        assert_eq!(
+            fs::read_to_string(loc.join("data/receptors/a.mol2")).unwrap(),
+            "file"
+        );
+
+        for files in [
+            vec![("file", "../a.mol2")],
+            vec![("file", "/etc/a.mol2")],
+            vec![("../../a.mol2", "a.mol2")],
+            vec![("file", "data/a.mol2"), ("file", "data//a.mol2")],
+        ] {
+            let response = send(files.clone()).await.unwrap();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{files:?}");
+        }
+        // Only the directory of the first job is left
+        assert_eq!(fs::read_dir(data_dir.path()).unwrap().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_upload_extract() {
+        let data_dir = tempdir().unwrap();
+        let mut config = Config::new().unwrap();
+        config.data_path = data_dir.path().to_str().unwrap().to_string();
+        config.services = HashMap::from([(
+            String::from("test-service"),
+            Service {
+                name: String::from("test-service"),
+                ..Default::default()
+            },
+        )]);
+
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        init_db(&pool).await.unwrap();
+        let state = AppState { pool, config };
+
+        let app = Router::new()
+            .route("/upload", post(upload))
+            .with_state(state);
+        let send = |files: &[(&str, &[u8])]| {
+            let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+            for (name, content) in files {
+                zip.start_file(*name, zip::write::SimpleFileOptions::default())
+                    .unwrap();
+                zip.write_all(content).unwrap();
+            }
+            let archive = zip.finish().unwrap().into_inner();
+
+            let boundary = format!("----Boundary{}", Uuid::new_v4());
+            let mut body = Vec::new();
+            body.extend(form_field(&boundary, "service", "test-service"));
+            body.extend(form_field(&boundary, "user_id", "42"));
+            body.extend(form_field(&boundary, "extract", "true"));
+            body.extend(form_file(
+                &boundary,
+                "file",
+                "inputs.zip",
+                "application/zip",
+                &archive,
+            ));
+            body.extend(format!("--{boundary}--\r\n").as_bytes());
+            let req = Request::builder()
+                .method("POST")
+                .uri("/upload")
+                .header(
+                    header::CONTENT_TYPE,
+                    format!("multipart/form-data; boundary={boundary}"),
+                )
+                .body(Body::from(body))
+                .unwrap();
+            app.clone().oneshot(req)
+        };
+
+        let response = send(&[("run.sh", b"echo"), ("data/a.mol2", b"mol")])
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let loc = PathBuf::from(json["loc"].as_str().unwrap());
+        assert_eq!(fs::read(loc.join("data/a.mol2")).unwrap(), b"mol");
+        assert!(!loc.join("inputs.zip").exists());
+        assert_eq!(json["input_size"], 7);
+        // Not part of the metadata
+        assert_eq!(json["metadata"], serde_json::json!({}));
+
+        let response = send(&[("../escape.txt", b"nope")]).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert!(!data_dir.path().join("escape.txt").exists());
+        // Only the job that was extracted is left
+        assert_eq!(fs::read_dir(data_dir.path()).unwrap().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_upload_schema() {
+        let data_dir = tempdir().unwrap();
+        let mut config = Config::new().unwrap();
+        config.data_path = data_dir.path().to_str().unwrap().to_string();
+        config.services = HashMap::from([(
+            String::from("test-service"),
+            Service {
+                name: String::from("test-service"),
+                inputs: Some(InputSchema {
+                    files: vec![
+                        InputFile {
+                            path: String::from("run.sh"),
+                            required: true,
+                            max_size: None,
+                        },
+                        InputFile {
+                            path: String::from("*.pdb"),
+                            required: false,
+                            max_size: Some(100),
+                        },
+                    ],
+                    params: vec![String::from("run_name")],
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        )]);
+
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        init_db(&pool).await.unwrap();
+        let state = AppState { pool, config };
+
+        let app = Router::new()
+            .route("/upload", post(upload))
+            .with_state(state);
+        let send = |fields: &[(&str, &str)], files: &[(&str, &str)]| {
+            let boundary = format!("----Boundary{}", Uuid::new_v4());
+            let mut body = Vec::new();
+            body.extend(form_field(&boundary, "service", "test-service"));
+            body.extend(form_field(&boundary, "user_id", "42"));
+            for (name, value) in fields {
+                body.extend(form_field(&boundary, name, value));
+            }
+            for (name, content) in files {
+                body.extend(form_text_file(&boundary, "file", name, content));
+            }
+            body.extend(format!("--{boundary}--\r\n").as_bytes());
+            let req = Request::builder()
+                .method("POST")
+                .uri("/upload")
+                .header(
+                    header::CONTENT_TYPE,
+                    format!("multipart/form-data; boundary={boundary}"),
+                )
+                .body(Body::from(body))
+                .unwrap();
+            app.clone().oneshot(req)
+        };
+
+        let response = send(&[], &[("2oob.pdb", "ATOM"), ("notes.txt", "hi")])
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            json["errors"],
+            serde_json::json!([
+                {"input": "run_name", "message": "Missing parameter"},
+                {"input": "notes.txt", "message": "Not an input of this service"},
+                {"input": "run.sh", "message": "Missing required file"}
+            ])
+        );
+        // Nothing is kept of a refused job
+        assert_eq!(fs::read_dir(data_dir.path()).unwrap().count(), 0);
+
+        let response = send(&[("run_name", "first")], &[("run.sh", "echo")])
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_resumable_upload() {
+        let data_dir = tempdir().unwrap();
+        let mut config = Config::new().unwrap();
+        config.data_path = data_dir.path().to_str().unwrap().to_string();
+        config.services = HashMap::from([(
+            String::from("test-service"),
+            Service {
+                name: String::from("test-service"),
+                ..Default::default()
+            },
+        )]);
+
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        init_db(&pool).await.unwrap();
+        let state = AppState { pool, config };
+
+        let app = Router::new()
+            .route("/uploads", post(create_upload))
+            .route(
+                "/uploads/{id}/files/{*path}",
+                axum::routing::patch(upload_chunk).head(upload_offset),
+            )
+            .route("/uploads/{id}/finalize", post(finalize_upload))
+            .with_state(state);
+
+        let create = |fields: &'static str| {
+            let req = Request::builder()
+                .method("POST")
+                .uri("/uploads")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(fields))
+                .unwrap();
+            app.clone().oneshot(req)
+        };
+        let response = create(r#"{"service": "test-service", "user_id": 42}"#)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let response =
+            create(r#"{"service": "test-service", "user_id": 42, "upload_length": 1000000000000}"#)
+                .await
+                .unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        let response = create(r#"{"service": "test-service", "user_id": 42, "upload_length": 11}"#)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["status"], "Uploading");
+        let id = json["id"].as_i64().unwrap();
+        let loc = PathBuf::from(json["loc"].as_str().unwrap());
+
+        let chunk = |offset: u64, content: &'static [u8]| {
+            let req = Request::builder()
+                .method("PATCH")
+                .uri(format!("/uploads/{id}/files/data/a.mol2"))
+                .header(UPLOAD_OFFSET, offset)
+                .body(Body::from(content))
+                .unwrap();
+            app.clone().oneshot(req)
+        };
+        // The same chunk sent twice at once is only written once
+        let (first, second) = tokio::join!(chunk(0, b"hello "), chunk(0, b"hello "));
+        let mut statuses = [first.unwrap().status(), second.unwrap().status()];
+        statuses.sort();
+        assert_eq!(statuses, [StatusCode::NO_CONTENT, StatusCode::CONFLICT]);
+        // Sent again after a dropped connection
+        let response = chunk(0, b"hello ").await.unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        assert_eq!(response.headers()[UPLOAD_OFFSET], "6");
+        // More than was declared
+        let response = chunk(6, b"world!").await.unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(fs::read(loc.join("data/a.mol2")).unwrap(), b"hello ");
+        let response = chunk(6, b"world").await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(fs::read(loc.join("data/a.mol2")).unwrap(), b"hello world");
+
+        let req = Request::builder()
+            .method("HEAD")
+            .uri(format!("/uploads/{id}/files/data/a.mol2"))
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[UPLOAD_OFFSET], "11");
+
+        let finalize = |sha256: &str| {
+            let req = Request::builder()
+                .method("POST")
+                .uri(format!("/uploads/{id}/finalize"))
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(
+                    serde_json::json!({"files": {"data/a.mol2": sha256}}).to_string(),
+                ))
+                .unwrap();
+            app.clone().oneshot(req)
+        };
+        let response = finalize("00").await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        // sha256 of "hello world"
+        let response = finalize("B94D27B9934D3E08A52E52D7DA7DABFAC484EFE37A5380EE9088F7ACE2EFCDE9")
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["status"], "Queued");
+        assert_eq!(json["input_size"], 11);
+
+        // Nothing more goes in once it is queued
+        let response = chunk(11, b"!").await.unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn test_upload_resources() {
+        let data_dir = tempdir().unwrap();
+        let mut config = Config::new().unwrap();
+        config.data_path = data_dir.path().to_str().unwrap().to_string();
+        config.services = HashMap::from([(
+            String::from("test-service"),
+            Service {
+                name: String::from("test-service"),
+                ..Default::default()
+            },
+        )]);
+
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        init_db(&pool).await.unwrap();
+        let app = Router::new()
+            .route("/uploads", post(create_upload))
+            .with_state(AppState {
+                pool: pool.clone(),
+                config,
+            });
+        let create = |resources: &str| {
+            let req = Request::builder()
+                .method("POST")
+                .uri("/uploads")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(format!(
+                    r#"{{"service": "test-service", "user_id": 42, "upload_length": 5, {resources}}}"#
+                )))
+                .unwrap();
+            app.clone().oneshot(req)
+        };
+
+        let response = create(r#""memory_mb": 18446744073709551615"#)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        // Any amount might fit before the backend advertised its resources
+        let response = create(r#""cores": 64"#).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        let advertisement = Advertisement {
+            resources: Resources {
+                cores: 16,
+                ..Default::default()
+            },
+            datasets: Vec::new(),
+        };
+        Registration::new("test-service", "", advertisement)
+            .save(&pool)
+            .await
+            .unwrap();
+        let response = create(r#""cores": 64"#).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let response = create(r#""cores": 16, "memory_mb": 64000"#).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+
+    #[tokio::test]
+    async fn test_upload_params() {
+        let data_dir = tempdir().unwrap();
+        let mut config = Config::new().unwrap();
+        config.data_path = data_dir.path().to_str().unwrap().to_string();
+        config.services = HashMap::from([(
+            String::from("test-service"),
+            Service {
+                name: String::from("test-service"),
+                params: vec![String::from("*")],
+                ..Default::default()
+            },
+        )]);
+
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        init_db(&pool).await.unwrap();
+        let app = Router::new()
+            .route("/uploads", post(create_upload))
+            .route(
+                "/uploads/{id}/files/{*path}",
+                axum::routing::patch(upload_chunk),
+            )
+            .route("/uploads/{id}/finalize", post(finalize_upload))
+            .with_state(AppState { pool, config });
+        let create = |params: serde_json::Value| {
+            let mut fields = serde_json::json!({
+                "service": "test-service",
+                "user_id": 42,
+                "upload_length": 5,
+            });
+            fields
+                .as_object_mut()
+                .unwrap()
+                .extend(params.as_object().unwrap().clone());
+            let req = Request::builder()
+                .method("POST")
+                .uri("/uploads")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(fields.to_string()))
+                .unwrap();
+            app.clone().oneshot(req)
+        };
+
+        // Both would be JOB_PARAM_RUN_NAME
+        let response = create(serde_json::json!({"run-name": "a", "run_name": "b"}))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        // Not something an environment variable can hold
+        let response = create(serde_json::json!({"run_name": "a\u{0}b"}))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let response = create(serde_json::json!({"run_name": "a", "project": "b"}))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let id = json["id"].as_i64().unwrap();
+
+        // The parameters go in params.json, an input can't take its place
+        let req = Request::builder()
+            .method("PATCH")
+            .uri(format!("/uploads/{id}/files/{PARAMS_FILE}"))
+            .header(UPLOAD_OFFSET, 0)
+            .body(Body::from("hello"))
+            .unwrap();
+        let response = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        // sha256 of "hello"
+        let sha256 = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+        let req = Request::builder()
+            .method("POST")
+            .uri(format!("/uploads/{id}/finalize"))
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(
+                serde_json::json!({"files": {PARAMS_FILE: sha256}}).to_string(),
+            ))
+            .unwrap();
+        let response = app.oneshot(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["errors"][0]["input"], PARAMS_FILE);
+    }
+
+    #[tokio::test]
+    async fn test_finalize_invalid_inputs() {
+        let data_dir = tempdir().unwrap();
+        let mut config = Config::new().unwrap();
+        config.data_path = data_dir.path().to_str().unwrap().to_string();
+        config.services = HashMap::from([(
+            String::from("test-service"),
+            Service {
+                name: String::from("test-service"),
+                inputs: Some(InputSchema {
+                    extensions: vec![String::from("txt")],
+                    validators: vec![Validator {
+                        command: vec![String::from("/nonexistent/validator")],
+                        files: None,
+                        timeout: 10,
+                    }],
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        )]);
+
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        init_db(&pool).await.unwrap();
+        let state = AppState {
+            pool: pool.clone(),
+            config: config.clone(),
+        };
+        let app = Router::new()
+            .route("/uploads", post(create_upload))
+            .route(
+                "/uploads/{id}/files/{*path}",
+                axum::routing::patch(upload_chunk),
+            )
+            .route("/uploads/{id}/finalize", post(finalize_upload))
+            .with_state(state);
+
+        // Upload "hello" as `path` and finalize it
+        let send = |path: &'static str| {
+            let app = app.clone();
+            async move {
+                let req = Request::builder()
+                    .method("POST")
+                    .uri("/uploads")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(
+                        r#"{"service": "test-service", "user_id": 42, "upload_length": 5}"#,
+                    ))
+                    .unwrap();
+                let response = app.clone().oneshot(req).await.unwrap();
+                let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+                let id = json["id"].as_i64().unwrap() as i32;
+
+                let req = Request::builder()
+                    .method("PATCH")
+                    .uri(format!("/uploads/{id}/files/{path}"))
+                    .header(UPLOAD_OFFSET, 0)
+                    .body(Body::from("hello"))
+                    .unwrap();
+                app.clone().oneshot(req).await.unwrap();
+
+                // sha256 of "hello"
+                let sha256 = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+                let req = Request::builder()
+                    .method("POST")
+                    .uri(format!("/uploads/{id}/finalize"))
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(
+                        serde_json::json!({"files": {path: sha256}}).to_string(),
+                    ))
+                    .unwrap();
+                (id, app.oneshot(req).await.unwrap().status())
+            }
+        };
+        let job = |id: i32| {
+            let pool = pool.clone();
+            let data_path = config.data_path.clone();
+            async move {
+                let mut job = Job::new(&data_path);
+                job.retrieve_id(id, &pool).await.unwrap();
+                job
+            }
+        };
+
+        // Invalid inputs fail the job, without extract as well
+        let (id, status) = send("a.pdb").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let failed = job(id).await;
+        assert_eq!(failed.status, Status::Failed);
+        assert!(!failed.loc.exists());
+
+        // A validator that can't be run is not the fault of the inputs
+        let (id, status) = send("a.txt").await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        let uploading = job(id).await;
+        assert_eq!(uploading.status, Status::Uploading);
+        assert!(uploading.loc.join("a.txt").exists());
+    }
+
+    #[tokio::test]
+    async fn test_upload_invalid_callback_url() {
+        let data_dir = tempdir().unwrap();
+        let mut config = Config::new().unwrap();
+        config.data_path = data_dir.path().to_str().unwrap().to_string();
+        config.services = HashMap::from([(
+            String::from("test-service"),
+            Service {
+                name: String::from("test-service"),
+                ..Default::default()
+            },
+        )]);
+
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        init_db(&pool).await.unwrap();
+        let send = |secret: &str, callback_url: &str| {
+            let mut config = config.clone();
+            config.webhook_secret = Secret(secret.to_string());
+            let app = Router::new()
+                .route("/upload", post(upload))
+                .with_state(AppState {
+                    pool: pool.clone(),
+                    config,
+                });
 
-        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
-        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
-        assert_eq!(json["id"], 1);
-        assert_eq!(json["status"], String::from("Queued"));
-        assert_eq!(json["service"], String::from("test-service"));
-        assert_eq!(json["user_id"], 42);
+            let boundary = format!("----Boundary{}", Uuid::new_v4());
+            let mut body = Vec::new();
+            body.extend(form_field(&boundary, "service", "test-service"));
+            body.extend(form_field(&boundary, "user_id", "42"));
+            body.extend(form_field(&boundary, "callback_url", callback_url));
+            body.extend(form_text_file(&boundary, "file", "test.txt", "hello"));
+            body.extend(format!("--{boundary}--\r\n").as_bytes());
 
-        // Check if the file was saved correctly
-        let expected_loc = json["loc"].as_str().unwrap();
-        let expected_file = PathBuf::from(expected_loc).join("test.txt");
-        assert!(expected_file.exists());
-        let expected_file = PathBuf::from(expected_loc).join("test01.txt");
-        assert!(expected_file.exists());
+            let req = Request::builder()
+                .method("POST")
+                .uri("/upload")
+                .header(
+                    header::CONTENT_TYPE,
+                    format!("multipart/form-data; boundary={boundary}"),
+                )
+                .body(Body::from(body))
+                .unwrap();
+            app.oneshot(req)
+        };
+
+        for callback_url in [
+            "file:///etc/passwd",
+            "http://127.0.0.1:8080/",
+            "http://localhost/",
+            "http://10.0.0.1/hooks",
+            "http://169.254.169.254/latest/meta-data/",
+            "http://[::1]/",
+        ] {
+            let response = send("secret", callback_url).await.unwrap();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{callback_url}");
+        }
+        // Without a secret to sign them, there are no callbacks at all
+        let response = send("", "https://portal.example.com/hooks").await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(fs::read_dir(data_dir.path()).unwrap().count(), 0);
+
+        let response = send("secret", "https://portal.example.com/hooks")
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
     }
 
     #[tokio::test]
@@ -356,7 +2294,13 @@ mod tests {
         let pool = SqlitePool::connect("sqlite::memory:").await.unwrap(); // Mock database connection;
         let state = State(AppState { pool, config }); // Mock state for testing
         let path = Path(1);
-        let response = download(state, path).await;
+        let response = download(
+            state,
+            path,
+            Query(ArchiveQuery::default()),
+            HeaderMap::new(),
+        )
+        .await;
         match response {
             Ok(_) => {}
             Err(e) => assert_eq!(e, StatusCode::INTERNAL_SERVER_ERROR),
@@ -370,7 +2314,13 @@ mod tests {
         init_db(&pool).await.unwrap(); // Initialize the database schema
         let state = State(AppState { pool, config }); // Mock state for testing
         let path = Path(1);
-        let response = download(state, path).await;
+        let response = download(
+            state,
+            path,
+            Query(ArchiveQuery::default()),
+            HeaderMap::new(),
+        )
+        .await;
         match response {
             Ok(_) => {}
             Err(e) => assert_eq!(e, StatusCode::NOT_FOUND),
@@ -386,20 +2336,136 @@ mod tests {
         // Make a completed job
         let data_dir = tempdir().unwrap();
         let mut job = Job::new(data_dir.path().to_str().unwrap());
-        fs::create_dir(&job.loc).unwrap(); // Create data directory
-        let dummy_file_path = job.loc.join("output.zip");
+        fs::create_dir_all(job.state_dir()).unwrap(); // Create data directory
+        let dummy_file_path = job.results_path();
         let mut file = fs::File::create(&dummy_file_path).unwrap();
         writeln!(file, "dummy data").unwrap(); // Create a dummy file
                                                //
         job.add_to_db(&pool).await.unwrap(); // Add job to the database;
         job.update_status(Status::Completed, &pool).await.unwrap(); // Update job status to Failed;
 
-        let state = State(AppState { pool, config }); // Mock state for testing
+        let state = State(AppState {
+            pool: pool.clone(),
+            config: config.clone(),
+        }); // Mock state for testing
+        let state_again = State(AppState { pool, config });
         let path = Path(job.id);
 
-        if let Ok(v) = download(state, path).await {
-            assert_eq!(v, fs::read(dummy_file_path).unwrap());
-        }
+        let response = download(
+            state,
+            path,
+            Query(ArchiveQuery::default()),
+            HeaderMap::new(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_LENGTH], "11");
+        let etag = response.headers()[header::ETAG].clone();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(body.to_vec(), fs::read(&dummy_file_path).unwrap());
+
+        // Resumed from where it stopped
+        let mut headers = HeaderMap::new();
+        headers.insert(header::RANGE, "bytes=6-".parse().unwrap());
+        headers.insert(header::IF_RANGE, etag.clone());
+        let response = download(
+            state_again,
+            Path(job.id),
+            Query(ArchiveQuery::default()),
+            headers,
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes 6-10/11");
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"data\n");
+    }
+
+    #[tokio::test]
+    async fn test_download_formats() {
+        let mut config = Config::new().unwrap();
+        config.services.insert(
+            "example".to_string(),
+            Service {
+                name: "example".to_string(),
+                archive_format: ArchiveFormat::TarGz,
+                ..Default::default()
+            },
+        );
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        init_db(&pool).await.unwrap();
+
+        let data_dir = tempdir().unwrap();
+        let mut job = Job::new(data_dir.path().to_str().unwrap());
+        job.set_service("example".to_string());
+        fs::create_dir_all(job.state_dir()).unwrap();
+        let mut zip = zip::ZipWriter::new(fs::File::create(job.results_path()).unwrap());
+        zip.start_file("out.txt", zip::write::SimpleFileOptions::default())
+            .unwrap();
+        zip.write_all(b"done").unwrap();
+        zip.finish().unwrap();
+        job.add_to_db(&pool).await.unwrap();
+        job.update_status(Status::Completed, &pool).await.unwrap();
+
+        let app = Router::new()
+            .route("/download/{id}", get(download))
+            .with_state(AppState { pool, config });
+        let get_download = |uri: String, accept: Option<&'static str>| {
+            let mut req = Request::builder().method("GET").uri(uri);
+            if let Some(accept) = accept {
+                req = req.header(header::ACCEPT, accept);
+            }
+            app.clone().oneshot(req.body(Body::empty()).unwrap())
+        };
+
+        // The default of the service
+        let response = get_download(format!("/download/{}", job.id), None)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/gzip");
+        assert_eq!(
+            response.headers()[header::CONTENT_DISPOSITION],
+            format!("attachment; filename=\"{}.tar.gz\"", job.id).as_str()
+        );
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let mut tar = tar::Archive::new(flate2::read::GzDecoder::new(&body[..]));
+        let mut entry = tar.entries().unwrap().next().unwrap().unwrap();
+        let mut content = String::new();
+        std::io::Read::read_to_string(&mut entry, &mut content).unwrap();
+        assert_eq!(content, "done");
+
+        // The zip from the backend, as is
+        let response = get_download(format!("/download/{}", job.id), Some("application/zip"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::ACCEPT_RANGES], "bytes");
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(body.to_vec(), fs::read(job.results_path()).unwrap());
+
+        // The parameter goes first
+        let response = get_download(
+            format!("/download/{}?format=tar", job.id),
+            Some("application/zip"),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "application/x-tar"
+        );
+
+        let response = get_download(format!("/download/{}?format=rar", job.id), None)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let response = get_download(format!("/download/{}", job.id), Some("text/html"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_ACCEPTABLE);
     }
 
     #[tokio::test]
@@ -414,7 +2480,14 @@ mod tests {
         let state = State(AppState { pool, config }); // Mock state for testing
         let path = Path(job.id);
 
-        match download(state, path).await {
+        match download(
+            state,
+            path,
+            Query(ArchiveQuery::default()),
+            HeaderMap::new(),
+        )
+        .await
+        {
             Ok(_) => {}
             Err(e) => assert_eq!(e, StatusCode::NO_CONTENT),
         }
@@ -432,7 +2505,14 @@ mod tests {
         let state = State(AppState { pool, config }); // Mock state for testing
         let path = Path(job.id);
 
-        match download(state, path).await {
+        match download(
+            state,
+            path,
+            Query(ArchiveQuery::default()),
+            HeaderMap::new(),
+        )
+        .await
+        {
             Ok(_) => {}
             Err(e) => assert_eq!(e, StatusCode::NO_CONTENT),
         }
@@ -450,9 +2530,580 @@ mod tests {
         let state = State(AppState { pool, config }); // Mock state for testing
         let path = Path(job.id);
 
-        match download(state, path).await {
+        match download(
+            state,
+            path,
+            Query(ArchiveQuery::default()),
+            HeaderMap::new(),
+        )
+        .await
+        {
             Ok(_) => {}
             Err(e) => assert_eq!(e, StatusCode::ACCEPTED),
         }
     }
+
+    #[tokio::test]
+    async fn test_cancel_queued_job() {
+        let config = Config::new().unwrap();
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        init_db(&pool).await.unwrap();
+        let data_dir = tempdir().unwrap();
+        let mut job = Job::new(data_dir.path().to_str().unwrap());
+        fs::create_dir(&job.loc).unwrap();
+        job.add_to_db(&pool).await.unwrap();
+        job.update_status(Status::Queued, &pool).await.unwrap();
+
+        let state = State(AppState {
+            pool: pool.clone(),
+            config,
+        });
+        let response = cancel(state, Path(job.id)).await.unwrap();
+        assert_eq!(response.0.status, Status::Cancelled);
+        assert!(!job.loc.exists());
+
+        let mut stored = Job::new("");
+        stored.retrieve_id(job.id, &pool).await.unwrap();
+        assert_eq!(stored.status, Status::Cancelled);
+    }
+
+    #[tokio::test]
+    async fn test_cancel_submitted_job() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("DELETE", "/payload/42")
+            .with_status(200)
+            .create_async()
+            .await;
+
+        let mut config = Config::new().unwrap();
+        config.services = HashMap::from([(
+            String::from("test-service"),
+            Service {
+                name: String::from("test-service"),
+                cancel_url: format!("{}/payload", server.url()),
+                ..Default::default()
+            },
+        )]);
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        init_db(&pool).await.unwrap();
+        let data_dir = tempdir().unwrap();
+        let mut job = Job::new(data_dir.path().to_str().unwrap());
+        job.set_service(String::from("test-service"));
+        fs::create_dir(&job.loc).unwrap();
+        job.add_to_db(&pool).await.unwrap();
+        job.update_status(Status::Submitted, &pool).await.unwrap();
+        job.update_dest_id(42, &pool).await.unwrap();
+
+        let state = State(AppState { pool, config });
+        let response = cancel(state, Path(job.id)).await.unwrap();
+
+        mock.assert_async().await;
+        assert_eq!(response.0.status, Status::Cancelled);
+        assert!(!job.loc.exists());
+    }
+
+    #[tokio::test]
+    async fn test_cancel_finished_job() {
+        let config = Config::new().unwrap();
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        init_db(&pool).await.unwrap();
+        let mut job = Job::new("");
+        job.add_to_db(&pool).await.unwrap();
+        job.update_status(Status::Completed, &pool).await.unwrap();
+
+        let state = State(AppState { pool, config });
+        let response = cancel(state.clone(), Path(job.id)).await;
+        assert!(matches!(response, Err((StatusCode::CONFLICT, _))));
+
+        let response = cancel(state, Path(job.id + 1)).await;
+        assert!(matches!(response, Err((StatusCode::NOT_FOUND, _))));
+    }
+
+    #[tokio::test]
+    async fn test_list() {
+        let config = Config::new().unwrap();
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        init_db(&pool).await.unwrap();
+        for (user_id, project) in [(1, "a"), (1, "b"), (2, "a")] {
+            let mut job = Job::new("");
+            job.set_user_id(user_id);
+            job.set_metadata(BTreeMap::from([(
+                "project".to_string(),
+                project.to_string(),
+            )]));
+            job.add_to_db(&pool).await.unwrap();
+            job.update_status(Status::Queued, &pool).await.unwrap();
+        }
+        let state = AppState { pool, config };
+        let test_app = Router::new().route("/jobs", get(list)).with_state(state);
+
+        let req = Request::builder()
+            .uri("/jobs?user_id=1&status=queued&created_after=2000-01-01&limit=1")
+            .body(Body::empty())
+            .unwrap();
+        let response = test_app.clone().oneshot(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["jobs"].as_array().unwrap().len(), 1);
+        assert_eq!(json["jobs"][0]["user_id"], 1);
+        assert!(json["jobs"][0]["created_at"].is_string());
+        assert!(json["next_cursor"].is_number());
+
+        let req = Request::builder()
+            .uri("/jobs?metadata=project:a")
+            .body(Body::empty())
+            .unwrap();
+        let response = test_app.clone().oneshot(req).await.unwrap();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let jobs = json["jobs"].as_array().unwrap();
+        assert_eq!(jobs.len(), 2);
+        assert!(jobs.iter().all(|j| j["metadata"]["project"] == "a"));
+
+        for query in [
+            "status=done",
+            "created_before=soon",
+            "sort=name",
+            "metadata=project",
+        ] {
+            let req = Request::builder()
+                .uri(format!("/jobs?{query}"))
+                .body(Body::empty())
+                .unwrap();
+            let response = test_app.clone().oneshot(req).await.unwrap();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{query}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_detail() {
+        let mut config = Config::new().unwrap();
+        config.max_age = std::time::Duration::from_secs(3600);
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        init_db(&pool).await.unwrap();
+        let data_dir = tempdir().unwrap();
+        let mut job = Job::new(data_dir.path().to_str().unwrap());
+        fs::create_dir(&job.loc).unwrap();
+        job.add_to_db(&pool).await.unwrap();
+        job.update_status(Status::Queued, &pool).await.unwrap();
+
+        let state = State(AppState { pool, config });
+        let response = detail(state.clone(), Path(job.id)).await.unwrap();
+        let json = serde_json::to_value(&response.0).unwrap();
+        assert_eq!(json["id"], job.id);
+        assert_eq!(json["status"], "Queued");
+        assert!(json["timestamps"]["queued_at"].is_string());
+        assert!(json["timestamps"]["dispatched_at"].is_null());
+        assert!(json["failure_reason"].is_null());
+        assert!(json["expires_at"].is_string());
+
+        // Without its files there is nothing left to expire
+        fs::remove_dir(&job.loc).unwrap();
+        let response = detail(state.clone(), Path(job.id)).await.unwrap();
+        assert_eq!(response.0.expires_at, None);
+
+        let response = detail(state, Path(job.id + 1)).await;
+        assert_eq!(response.unwrap_err(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_events() {
+        let config = Config::new().unwrap();
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        init_db(&pool).await.unwrap();
+        let mut job = Job::new("");
+        job.add_to_db(&pool).await.unwrap();
+        job.update_status(Status::Queued, &pool).await.unwrap();
+        job.update_status(Status::Cancelled, &pool).await.unwrap();
+
+        let state = State(AppState { pool, config });
+        let response = events(state.clone(), Path(job.id)).await.unwrap();
+        let json = serde_json::to_value(&response.0).unwrap();
+        assert_eq!(json.as_array().unwrap().len(), 2);
+        assert_eq!(json[1]["actor"], "api");
+        assert_eq!(json[1]["from_status"], "Queued");
+        assert_eq!(json[1]["to_status"], "Cancelled");
+
+        let response = events(state, Path(job.id + 1)).await;
+        assert_eq!(response.unwrap_err(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_retry() {
+        let config = Config::new().unwrap();
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        init_db(&pool).await.unwrap();
+        let temp_dir = tempdir().unwrap();
+        let mut job = Job::new(temp_dir.path().to_str().unwrap());
+        job.add_to_db(&pool).await.unwrap();
+        fs::create_dir_all(&job.loc).unwrap();
+        fs::write(job.loc.join("run.sh"), "#!/bin/bash").unwrap();
+        job.update_status(Status::Submitted, &pool).await.unwrap();
+        job.update_dest_id(7, &pool).await.unwrap();
+        job.update_failure(None, Status::Failed, "Failed on its backend", &pool)
+            .await
+            .unwrap();
+        // An input that happens to have the name of a log
+        fs::write(job.loc.join(STDOUT_LOG), "input").unwrap();
+        Logs {
+            stdout: "out".to_string(),
+            stderr: "err".to_string(),
+        }
+        .write(&job.state_dir())
+        .unwrap();
+
+        let state = State(AppState { pool, config });
+        let response = retry(state.clone(), Path(job.id)).await.unwrap();
+        assert_eq!(response.0.status, Status::Queued);
+        assert_eq!(response.0.dest_id, 0);
+        assert_eq!(response.0.failure_reason, None);
+        let mut stored = Job::new("");
+        stored.retrieve_id(job.id, &state.pool).await.unwrap();
+        assert!(stored.timestamps.dispatched_at.is_none());
+
+        // The inputs stay, what the last run left does not
+        assert!(job.loc.join("run.sh").exists());
+        assert_eq!(
+            fs::read_to_string(job.loc.join(STDOUT_LOG)).unwrap(),
+            "input"
+        );
+        assert!(!job.state_dir().exists());
+
+        // A retry that lost the race changes nothing, outputs included
+        fs::create_dir(job.state_dir()).unwrap();
+        stored.update_dest_id(8, &state.pool).await.unwrap();
+        let retried = retry_job(&mut job, "Retried", &state).await.unwrap();
+        assert!(!retried);
+        assert!(job.state_dir().exists());
+        stored.retrieve_id(job.id, &state.pool).await.unwrap();
+        assert_eq!(stored.dest_id, 8);
+
+        let events = list_events(job.id, &state.pool).await.unwrap();
+        let last = events.last().unwrap();
+        assert_eq!(last.from_status, Status::Failed);
+        assert_eq!(last.to_status, Status::Queued);
+        assert_eq!(last.message.as_deref(), Some("Retried"));
+
+        // Queued already
+        let response = retry(state.clone(), Path(job.id)).await;
+        assert_eq!(response.unwrap_err().0, StatusCode::CONFLICT);
+
+        let response = retry(state, Path(job.id + 1)).await;
+        assert_eq!(response.unwrap_err().0, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_retry_unknown_job() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("DELETE", "/payload/42")
+            .with_status(200)
+            .create_async()
+            .await;
+
+        let mut config = Config::new().unwrap();
+        config.services = HashMap::from([(
+            String::from("test-service"),
+            Service {
+                name: String::from("test-service"),
+                cancel_url: format!("{}/payload", server.url()),
+                ..Default::default()
+            },
+        )]);
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        init_db(&pool).await.unwrap();
+        let data_dir = tempdir().unwrap();
+        let mut job = Job::new(data_dir.path().to_str().unwrap());
+        job.set_service(String::from("test-service"));
+        fs::create_dir(&job.loc).unwrap();
+        job.add_to_db(&pool).await.unwrap();
+        job.update_status(Status::Submitted, &pool).await.unwrap();
+        job.update_dest_id(42, &pool).await.unwrap();
+        job.update_failure(None, Status::Unknown, "Not found on its backend", &pool)
+            .await
+            .unwrap();
+
+        // The old run is stopped before the job can be sent again
+        let state = State(AppState { pool, config });
+        let response = retry(state, Path(job.id)).await.unwrap();
+        mock.assert_async().await;
+        assert_eq!(response.0.status, Status::Queued);
+        assert_eq!(response.0.dest_id, 0);
+    }
+
+    #[tokio::test]
+    async fn test_retry_without_inputs() {
+        let config = Config::new().unwrap();
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        init_db(&pool).await.unwrap();
+        let temp_dir = tempdir().unwrap();
+        let mut job = Job::new(temp_dir.path().to_str().unwrap());
+        job.add_to_db(&pool).await.unwrap();
+        job.update_status(Status::Unknown, &pool).await.unwrap();
+
+        let state = State(AppState { pool, config });
+        let response = retry(state, Path(job.id)).await;
+        assert_eq!(response.unwrap_err().0, StatusCode::GONE);
+    }
+
+    #[tokio::test]
+    async fn test_requeue() {
+        let config = Config::new().unwrap();
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        init_db(&pool).await.unwrap();
+        let temp_dir = tempdir().unwrap();
+
+        let mut ids = Vec::new();
+        for (service, status) in [
+            ("A", Status::Failed),
+            ("A", Status::TimedOut),
+            ("A", Status::Completed),
+            ("B", Status::Failed),
+        ] {
+            let mut job = Job::new(temp_dir.path().to_str().unwrap());
+            job.set_service(service.to_string());
+            job.add_to_db(&pool).await.unwrap();
+            fs::create_dir_all(&job.loc).unwrap();
+            job.update_status(status, &pool).await.unwrap();
+            ids.push(job.id);
+        }
+        // One whose inputs are gone
+        let mut gone = Job::new(temp_dir.path().to_str().unwrap());
+        gone.set_service("A".to_string());
+        gone.add_to_db(&pool).await.unwrap();
+        gone.update_status(Status::Unknown, &pool).await.unwrap();
+
+        let state = State(AppState { pool, config });
+        let filter = RequeueFilter {
+            service: Some("A".to_string()),
+            ..Default::default()
+        };
+        let response = requeue(state.clone(), Json(filter)).await.unwrap();
+        assert_eq!(response.0.requeued, vec![ids[0], ids[1]]);
+        assert_eq!(response.0.skipped, vec![gone.id]);
+
+        let mut job = Job::new("");
+        job.retrieve_id(ids[1], &state.pool).await.unwrap();
+        assert_eq!(job.status, Status::Queued);
+        job.retrieve_id(ids[3], &state.pool).await.unwrap();
+        assert_eq!(job.status, Status::Failed);
+
+        let filter = RequeueFilter {
+            status: Some("completed".to_string()),
+            ..Default::default()
+        };
+        let response = requeue(state.clone(), Json(filter)).await;
+        assert_eq!(response.unwrap_err().0, StatusCode::BAD_REQUEST);
+
+        // Not taken for `unknown`, which would requeue every unknown job
+        let filter = RequeueFilter {
+            status: Some("faild".to_string()),
+            ..Default::default()
+        };
+        let response = requeue(state.clone(), Json(filter)).await;
+        assert_eq!(response.unwrap_err().0, StatusCode::BAD_REQUEST);
+
+        let filter = RequeueFilter {
+            created_after: Some("yesterday".to_string()),
+            ..Default::default()
+        };
+        let response = requeue(state, Json(filter)).await;
+        assert_eq!(response.unwrap_err().0, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_logs() {
+        let config = Config::new().unwrap();
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        init_db(&pool).await.unwrap();
+        let temp_dir = tempdir().unwrap();
+        let mut job = Job::new(temp_dir.path().to_str().unwrap());
+        job.add_to_db(&pool).await.unwrap();
+        fs::create_dir_all(&job.loc).unwrap();
+
+        let state = State(AppState { pool, config });
+        let response = logs(state.clone(), Path(job.id)).await;
+        assert_eq!(response.unwrap_err(), StatusCode::NOT_FOUND);
+
+        let collected = Logs {
+            stdout: "out".to_string(),
+            stderr: "err".to_string(),
+        };
+        collected.write(&job.state_dir()).unwrap();
+        let response = logs(state.clone(), Path(job.id)).await.unwrap();
+        assert_eq!(response.0, collected);
+
+        let response = logs(state, Path(job.id + 1)).await;
+        assert_eq!(response.unwrap_err(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_files() {
+        let config = Config::new().unwrap();
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        init_db(&pool).await.unwrap();
+        let temp_dir = tempdir().unwrap();
+        let mut job = Job::new(temp_dir.path().to_str().unwrap());
+        job.add_to_db(&pool).await.unwrap();
+        fs::create_dir_all(job.state_dir()).unwrap();
+        // Inputs with the names of the results are not taken for them
+        fs::write(
+            job.loc.join(RESULTS_INDEX),
+            r#"[{"path": "x", "size": 1, "sha256": ""}]"#,
+        )
+        .unwrap();
+        fs::write(job.loc.join(RESULTS_ARCHIVE), "not the results").unwrap();
+        let mut zip = zip::ZipWriter::new(fs::File::create(job.results_path()).unwrap());
+        zip.start_file("scores/best.csv", zip::write::SimpleFileOptions::default())
+            .unwrap();
+        zip.write_all(b"abc").unwrap();
+        zip.finish().unwrap();
+
+        let state = AppState { pool, config };
+        let test_app = Router::new()
+            .route("/jobs/{id}/files", get(files))
+            .route("/jobs/{id}/files/{*path}", get(file))
+            .with_state(state.clone());
+
+        // Not before the job is completed
+        let req = Request::builder()
+            .uri(format!("/jobs/{}/files", job.id))
+            .body(Body::empty())
+            .unwrap();
+        let response = test_app.clone().oneshot(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        job.update_status(Status::Completed, &state.pool)
+            .await
+            .unwrap();
+        let req = Request::builder()
+            .uri(format!("/jobs/{}/files", job.id))
+            .body(Body::empty())
+            .unwrap();
+        let response = test_app.clone().oneshot(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json.as_array().unwrap().len(), 1);
+        assert_eq!(json[0]["path"], "scores/best.csv");
+        assert_eq!(json[0]["size"], 3);
+
+        let req = Request::builder()
+            .uri(format!("/jobs/{}/files/scores/best.csv", job.id))
+            .body(Body::empty())
+            .unwrap();
+        let response = test_app.clone().oneshot(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_LENGTH], "3");
+        assert_eq!(
+            response.headers()[header::CONTENT_DISPOSITION],
+            "attachment; filename=\"best.csv\""
+        );
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"abc");
+
+        let req = Request::builder()
+            .uri(format!("/jobs/{}/files/scores/other.csv", job.id))
+            .body(Body::empty())
+            .unwrap();
+        let response = test_app.oneshot(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_stream() {
+        let config = Config::new().unwrap();
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        init_db(&pool).await.unwrap();
+        let mut job = Job::new("");
+        job.add_to_db(&pool).await.unwrap();
+        job.update_status(Status::Queued, &pool).await.unwrap();
+        job.update_status(Status::Cancelled, &pool).await.unwrap();
+        let test_app = Router::new()
+            .route("/jobs/{id}/stream", get(stream))
+            .with_state(AppState { pool, config });
+
+        let req = Request::builder()
+            .uri(format!("/jobs/{}/stream", job.id))
+            .body(Body::empty())
+            .unwrap();
+        let response = test_app.clone().oneshot(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // A finished job sends its transitions and ends the stream
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert_eq!(body.matches("event: status").count(), 2);
+        assert!(body.contains(r#""to_status":"Cancelled""#));
+
+        let req = Request::builder()
+            .uri(format!("/jobs/{}/stream", job.id + 1))
+            .body(Body::empty())
+            .unwrap();
+        let response = test_app.oneshot(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_stream_relays_stdout() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/logs/7")
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body("event: status\ndata: processing\n\nevent: stdout\ndata: hello\n\n")
+            .create_async()
+            .await;
+
+        let mut config = Config::new().unwrap();
+        config.services.insert(
+            "A".to_string(),
+            Service {
+                name: "A".to_string(),
+                stream_url: format!("{}/logs", server.url()),
+                ..Default::default()
+            },
+        );
+        // A single connection, the job is updated while the stream reads it
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        init_db(&pool).await.unwrap();
+        let mut job = Job::new("");
+        job.set_service("A".to_string());
+        job.add_to_db(&pool).await.unwrap();
+        job.update_status(Status::Submitted, &pool).await.unwrap();
+        job.update_dest_id(7, &pool).await.unwrap();
+
+        let pool_clone = pool.clone();
+        let id = job.id;
+        tokio::spawn(async move {
+            tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+            let mut job = Job::new("");
+            job.retrieve_id(id, &pool_clone).await.unwrap();
+            job.update_status(Status::Completed, &pool_clone)
+                .await
+                .unwrap();
+        });
+
+        let test_app = Router::new()
+            .route("/jobs/{id}/stream", get(stream))
+            .with_state(AppState { pool, config });
+        let req = Request::builder()
+            .uri(format!("/jobs/{}/stream", job.id))
+            .body(Body::empty())
+            .unwrap();
+        let response = test_app.oneshot(req).await.unwrap();
+
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains("event: stdout\ndata: hello\n\n"));
+        // Only the output is relayed, the status comes from the job itself
+        assert!(!body.contains("data: processing"));
+        assert!(body.contains(r#""to_status":"Completed""#));
+    }
 }
@@ -1,5 +1,6 @@
 use crate::models::job_dto::create_jobs_table;
 use crate::models::payload_dto::create_payload_table;
+use crate::models::registration_dto::create_registrations_table;
 use sqlx::{Pool, Sqlite, SqlitePool};
 use tracing::info;
 
@@ -14,6 +15,10 @@ pub async fn init_db(db_path: &str) -> Pool<Sqlite> {
         .await
         .expect("failed to create the jobs table");
 
+    create_registrations_table(&pool)
+        .await
+        .expect("failed to create the registrations table");
+
     pool
 }
 
@@ -10,7 +10,7 @@ use crate::routes::router::create_routes;
 use crate::{datasource::db::init_db, routes::router::create_client_routes};
 use clap::{Parser, Subcommand};
 use config::loader::Config;
-use services::tasks::{cleaner, getter, runner, sender};
+use services::tasks::{cleaner, getter, notifier, registrar, runner, sender, timekeeper};
 use std::net::SocketAddr;
 use tokio::net::TcpListener;
 use tokio_schedule::{every, Job};
@@ -64,6 +64,9 @@ async fn start_server(config: Config) -> anyhow::Result<()> {
     // Initialize the filesystem
     let _ = init_fs(&config.data_path).await;
 
+    // Register the backends before dispatching anything to them
+    registrar(pool.clone(), config.clone()).await;
+
     // Create a scheduled job
     let sender_task = every(500).millisecond().perform(|| {
         let pool_clone = pool.clone();
@@ -83,6 +86,24 @@ async fn start_server(config: Config) -> anyhow::Result<()> {
         async move { cleaner(pool_clone, config_clone).await }
     });
 
+    let registrar_task = every(30).second().perform(|| {
+        let pool_clone = pool.clone();
+        let config_clone = config.clone();
+        async move { registrar(pool_clone, config_clone).await }
+    });
+
+    let notifier_task = every(5).second().perform(|| {
+        let pool_clone = pool.clone();
+        let config_clone = config.clone();
+        async move { notifier(pool_clone, config_clone).await }
+    });
+
+    let timekeeper_task = every(10).second().perform(|| {
+        let pool_clone = pool.clone();
+        let config_clone = config.clone();
+        async move { timekeeper(pool_clone, config_clone).await }
+    });
+
     // Create app
     let app = create_routes(pool.clone(), config.clone());
 
@@ -96,6 +117,9 @@ async fn start_server(config: Config) -> anyhow::Result<()> {
         _ = sender_task => {},
         _ = getter_task => {},
         _ = cleaner_task => {},
+        _ = registrar_task => {},
+        _ = notifier_task => {},
+        _ = timekeeper_task => {},
         _ = axum::serve(listener, app.into_make_service()) => {},
     }
 
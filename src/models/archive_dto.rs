@@ -0,0 +1,146 @@
+use serde::{Deserialize, Serialize};
+use utoipa::IntoParams;
+
+// How the results of a job are packed when they are downloaded
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+pub enum ArchiveFormat {
+    // As retrieved from the backend, unless the service sets a compression level
+    #[default]
+    Zip,
+    Tar,
+    TarGz,
+    TarZst,
+}
+
+impl std::fmt::Display for ArchiveFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.extension())
+    }
+}
+
+impl ArchiveFormat {
+    pub fn from_string(s: &str) -> Option<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "zip" => Some(ArchiveFormat::Zip),
+            "tar" => Some(ArchiveFormat::Tar),
+            "tar.gz" | "tgz" => Some(ArchiveFormat::TarGz),
+            "tar.zst" | "tzst" => Some(ArchiveFormat::TarZst),
+            _ => None,
+        }
+    }
+
+    pub fn from_media_type(s: &str) -> Option<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "application/zip" => Some(ArchiveFormat::Zip),
+            "application/x-tar" => Some(ArchiveFormat::Tar),
+            "application/gzip" | "application/x-gzip" | "application/x-gtar" => {
+                Some(ArchiveFormat::TarGz)
+            }
+            "application/zstd" => Some(ArchiveFormat::TarZst),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => "zip",
+            ArchiveFormat::Tar => "tar",
+            ArchiveFormat::TarGz => "tar.gz",
+            ArchiveFormat::TarZst => "tar.zst",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => "application/zip",
+            ArchiveFormat::Tar => "application/x-tar",
+            ArchiveFormat::TarGz => "application/gzip",
+            ArchiveFormat::TarZst => "application/zstd",
+        }
+    }
+
+    // The format of an `Accept` header, by quality then order, `default` for wildcards.
+    //  None when none of the types it accepts is an archive format
+    pub fn negotiate(accept: &str, default: Self) -> Option<Self> {
+        let mut types: Vec<(&str, f32)> = accept
+            .split(',')
+            .filter_map(|item| {
+                let mut parts = item.split(';');
+                let media_type = parts.next()?.trim();
+                let quality = parts
+                    .filter_map(|p| p.trim().strip_prefix("q="))
+                    .find_map(|q| q.trim().parse::<f32>().ok())
+                    .unwrap_or(1.0);
+                (quality > 0.0).then_some((media_type, quality))
+            })
+            .collect();
+        // Stable, so types of the same quality keep their order
+        types.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+        types
+            .into_iter()
+            .find_map(|(media_type, _)| match media_type {
+                "*/*" | "application/*" => Some(default),
+                media_type => ArchiveFormat::from_media_type(media_type),
+            })
+    }
+}
+
+// Asks for a format over the `Accept` header of a download
+#[derive(Debug, Default, Deserialize, IntoParams)]
+pub struct ArchiveQuery {
+    // `zip`, `tar`, `tar.gz` or `tar.zst`
+    pub format: Option<String>,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_from_string() {
+        for format in [
+            ArchiveFormat::Zip,
+            ArchiveFormat::Tar,
+            ArchiveFormat::TarGz,
+            ArchiveFormat::TarZst,
+        ] {
+            assert_eq!(
+                ArchiveFormat::from_string(&format.to_string()),
+                Some(format)
+            );
+            assert_eq!(
+                ArchiveFormat::from_media_type(format.content_type()),
+                Some(format)
+            );
+        }
+        assert_eq!(
+            ArchiveFormat::from_string("TGZ"),
+            Some(ArchiveFormat::TarGz)
+        );
+        assert_eq!(ArchiveFormat::from_string("rar"), None);
+    }
+
+    #[test]
+    fn test_negotiate() {
+        let default = ArchiveFormat::TarGz;
+        assert_eq!(
+            ArchiveFormat::negotiate("application/zstd", default),
+            Some(ArchiveFormat::TarZst)
+        );
+        assert_eq!(ArchiveFormat::negotiate("*/*", default), Some(default));
+        assert_eq!(
+            ArchiveFormat::negotiate("text/html, application/x-tar, */*;q=0.8", default),
+            Some(ArchiveFormat::Tar)
+        );
+        assert_eq!(
+            ArchiveFormat::negotiate("application/zip;q=0.5, application/zstd", default),
+            Some(ArchiveFormat::TarZst)
+        );
+        assert_eq!(
+            ArchiveFormat::negotiate("application/zip;q=0, */*", default),
+            Some(default)
+        );
+        assert_eq!(ArchiveFormat::negotiate("text/html", default), None);
+    }
+}
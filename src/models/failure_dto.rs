@@ -0,0 +1,152 @@
+use http::{HeaderMap, HeaderValue};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use utoipa::ToSchema;
+
+// Bytes of stderr kept for the failure of a payload
+pub const STDERR_TAIL_SIZE: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureKind {
+    // There was no `run.sh` to run
+    NoScript,
+    // `run.sh` could not be started
+    Execution,
+    // `run.sh` exited with an error, or was killed
+    Script,
+    // `run.sh` ran longer than the `max_runtime` of its service, and was killed
+    Timeout,
+}
+
+impl fmt::Display for FailureKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FailureKind::NoScript => write!(f, "no_script"),
+            FailureKind::Execution => write!(f, "execution"),
+            FailureKind::Script => write!(f, "script"),
+            FailureKind::Timeout => write!(f, "timeout"),
+        }
+    }
+}
+
+impl FailureKind {
+    pub fn from_string(s: &str) -> Option<Self> {
+        match s {
+            "no_script" => Some(FailureKind::NoScript),
+            "execution" => Some(FailureKind::Execution),
+            "script" => Some(FailureKind::Script),
+            "timeout" => Some(FailureKind::Timeout),
+            _ => None,
+        }
+    }
+}
+
+// Why a payload failed on its client, passed on to the server through `/status`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct Failure {
+    pub kind: FailureKind,
+    pub exit_code: Option<i32>,
+    // Signal that killed `run.sh`, if any
+    pub signal: Option<i32>,
+    // Last bytes `run.sh` wrote to stderr
+    pub stderr_tail: Option<String>,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.kind, self.exit_code, self.signal) {
+            (FailureKind::NoScript, _, _) => write!(f, "No run.sh script found"),
+            (FailureKind::Execution, _, _) => write!(f, "Could not run run.sh"),
+            (FailureKind::Script, Some(code), _) => write!(f, "run.sh exited with code {code}"),
+            (FailureKind::Script, None, Some(signal)) => {
+                write!(f, "run.sh was killed by signal {signal}")
+            }
+            (FailureKind::Script, None, None) => write!(f, "run.sh failed"),
+            (FailureKind::Timeout, _, _) => write!(f, "run.sh exceeded its max runtime"),
+        }
+    }
+}
+
+// Headers of a `204` from `/retrieve` for a failed payload. The stderr tail only goes
+//  through `/status`, it does not fit in a header
+const KIND_HEADER: &str = "x-failure-kind";
+const EXIT_CODE_HEADER: &str = "x-exit-code";
+const SIGNAL_HEADER: &str = "x-signal";
+
+impl Failure {
+    pub fn to_headers(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        let values = [
+            (KIND_HEADER, Some(self.kind.to_string())),
+            (EXIT_CODE_HEADER, self.exit_code.map(|c| c.to_string())),
+            (SIGNAL_HEADER, self.signal.map(|s| s.to_string())),
+        ];
+        for (name, value) in values {
+            if let Some(value) = value.and_then(|v| HeaderValue::from_str(&v).ok()) {
+                headers.insert(name, value);
+            }
+        }
+        headers
+    }
+
+    pub fn from_headers(headers: &HeaderMap) -> Option<Failure> {
+        let get = |name| headers.get(name).and_then(|v| v.to_str().ok());
+        Some(Failure {
+            kind: FailureKind::from_string(get(KIND_HEADER)?)?,
+            exit_code: get(EXIT_CODE_HEADER).and_then(|v| v.parse().ok()),
+            signal: get(SIGNAL_HEADER).and_then(|v| v.parse().ok()),
+            stderr_tail: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let failure = Failure {
+            kind: FailureKind::Script,
+            exit_code: Some(2),
+            signal: None,
+            stderr_tail: None,
+        };
+        assert_eq!(failure.to_string(), "run.sh exited with code 2");
+
+        let failure = Failure {
+            exit_code: None,
+            signal: Some(9),
+            ..failure
+        };
+        assert_eq!(failure.to_string(), "run.sh was killed by signal 9");
+    }
+
+    #[test]
+    fn test_headers_round_trip() {
+        let failure = Failure {
+            kind: FailureKind::Script,
+            exit_code: None,
+            signal: Some(15),
+            stderr_tail: None,
+        };
+        let headers = failure.to_headers();
+        assert_eq!(Failure::from_headers(&headers), Some(failure));
+
+        assert_eq!(Failure::from_headers(&HeaderMap::new()), None);
+    }
+
+    #[test]
+    fn test_kind_round_trip() {
+        for kind in [
+            FailureKind::NoScript,
+            FailureKind::Execution,
+            FailureKind::Script,
+            FailureKind::Timeout,
+        ] {
+            assert_eq!(FailureKind::from_string(&kind.to_string()), Some(kind));
+        }
+    }
+}
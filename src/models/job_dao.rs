@@ -1,10 +1,43 @@
+use crate::config::loader::parse_list;
+use crate::models::failure_dto::Failure;
+use crate::models::job_event_dao::Actor;
+use crate::models::resources_dto::Resources;
+use crate::models::results_dto::RESULTS_ARCHIVE;
 use crate::models::status_dto::Status;
+use crate::utils::io::STATE_DIR;
+use std::collections::BTreeMap;
 use std::fs;
-use std::io::Read;
 use std::path::PathBuf;
+use std::time::Duration;
 use utoipa::ToSchema;
 use uuid::Uuid;
 
+// When a job went through each step of its lifecycle, as `YYYY-MM-DD HH:MM:SS` in UTC
+#[derive(serde::Serialize, Debug, Clone, Default, PartialEq, ToSchema)]
+pub struct Timestamps {
+    pub queued_at: Option<String>,
+    // Uploaded to its backend
+    pub dispatched_at: Option<String>,
+    // Seen running on its backend
+    pub started_at: Option<String>,
+    // Completed, failed or cancelled
+    pub finished_at: Option<String>,
+    pub cleaned_at: Option<String>,
+}
+
+impl Timestamps {
+    pub fn set(&mut self, column: &str, value: String) {
+        match column {
+            "queued_at" => self.queued_at = Some(value),
+            "dispatched_at" => self.dispatched_at = Some(value),
+            "started_at" => self.started_at = Some(value),
+            "finished_at" => self.finished_at = Some(value),
+            "cleaned_at" => self.cleaned_at = Some(value),
+            _ => {}
+        }
+    }
+}
+
 #[derive(serde::Serialize, Debug, ToSchema)]
 pub struct Job {
     pub id: i32,
@@ -14,6 +47,46 @@ pub struct Job {
     #[schema(value_type = String)]
     pub loc: PathBuf,
     pub dest_id: u32,
+    // Name of the service backend the job was dispatched to, empty for the default one
+    pub backend: String,
+    pub labels: Vec<String>,
+    // Total size of the input files, in bytes
+    pub input_size: u64,
+    pub resources: Resources,
+    // Datasets the job needs on the backend, next to the ones of its service
+    pub datasets: Vec<String>,
+    // When the job was uploaded, as `YYYY-MM-DD HH:MM:SS` in UTC
+    pub created_at: String,
+    pub timestamps: Timestamps,
+    // Why the job failed, or was lost
+    pub failure_reason: Option<String>,
+    // Details of the failure, as reported by the client
+    pub failure: Option<Failure>,
+    // Notified once the job is finished, next to the webhook of its service
+    pub callback_url: Option<String>,
+    // Transient errors in a row while dispatching or polling it, it is left alone until
+    //  `next_attempt_at`
+    pub attempts: u32,
+    pub next_attempt_at: Option<String>,
+    // Wall-clock limit of its `run.sh` in seconds, the one of its service when it was
+    //  uploaded
+    pub max_runtime: Option<u64>,
+    // Extra fields of the upload, ie a run name or project id of the portal that sent it
+    pub metadata: BTreeMap<String, String>,
+    // The part of the metadata passed on to `run.sh`, see `Config::get_params`
+    pub params: BTreeMap<String, String>,
+    // Recorded with the status changes made through this job, see `job_event_dto`
+    #[serde(skip)]
+    pub actor: Actor,
+}
+
+// A job as returned by `GET /jobs/{id}`
+#[derive(serde::Serialize, Debug, ToSchema)]
+pub struct JobDetail {
+    #[serde(flatten)]
+    pub job: Job,
+    // When the cleaner removes its files, unless it is gone already
+    pub expires_at: Option<String>,
 }
 
 impl Job {
@@ -26,20 +99,51 @@ impl Job {
             status: Status::Unknown,
             loc,
             dest_id: 0,
+            backend: String::new(),
+            labels: Vec::new(),
+            input_size: 0,
+            // Without an explicit request, a job takes a single core
+            resources: Resources {
+                cores: 1,
+                ..Default::default()
+            },
+            datasets: Vec::new(),
+            created_at: String::new(),
+            timestamps: Timestamps::default(),
+            failure_reason: None,
+            failure: None,
+            callback_url: None,
+            attempts: 0,
+            next_attempt_at: None,
+            max_runtime: None,
+            metadata: BTreeMap::new(),
+            params: BTreeMap::new(),
+            actor: Actor::default(),
         }
     }
 
-    pub fn download(self) -> Vec<u8> {
-        let mut file = fs::File::open(self.loc.join("output.zip")).unwrap();
-        let mut buffer = Vec::new();
-        file.read_to_end(&mut buffer).unwrap();
-        buffer
+    // Where the results of the job are once they have been retrieved from its backend
+    pub fn results_path(&self) -> PathBuf {
+        self.state_dir().join(RESULTS_ARCHIVE)
     }
 
     pub fn remove_from_disk(&self) -> Result<(), std::io::Error> {
         fs::remove_dir_all(&self.loc)
     }
 
+    // Where what was collected from its backend is kept, apart from the inputs
+    pub fn state_dir(&self) -> PathBuf {
+        self.loc.join(STATE_DIR)
+    }
+
+    // Remove what the last run left next to the inputs, so they are uploaded on their own
+    pub fn remove_outputs(&self) -> Result<(), std::io::Error> {
+        match fs::remove_dir_all(self.state_dir()) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
     pub fn set_service(&mut self, service: String) {
         self.service = service
     }
@@ -47,6 +151,40 @@ impl Job {
     pub fn set_user_id(&mut self, user_id: i32) {
         self.user_id = user_id;
     }
+
+    // Labels come as a comma separated list
+    pub fn set_labels(&mut self, labels: &str) {
+        self.labels = parse_list(labels);
+    }
+
+    // Datasets come as a comma separated list
+    pub fn set_datasets(&mut self, datasets: &str) {
+        self.datasets = parse_list(datasets);
+    }
+
+    pub fn set_metadata(&mut self, metadata: BTreeMap<String, String>) {
+        self.metadata = metadata;
+    }
+
+    pub fn set_params(&mut self, params: BTreeMap<String, String>) {
+        self.params = params;
+    }
+
+    pub fn set_callback_url(&mut self, callback_url: String) {
+        self.callback_url = Some(callback_url);
+    }
+
+    pub fn set_max_runtime(&mut self, max_runtime: Option<Duration>) {
+        self.max_runtime = max_runtime.map(|m| m.as_secs());
+    }
+
+    pub fn set_input_size(&mut self, input_size: u64) {
+        self.input_size = input_size;
+    }
+
+    pub fn set_resources(&mut self, resources: Resources) {
+        self.resources = resources;
+    }
 }
 
 #[cfg(test)]
@@ -58,15 +196,15 @@ mod test {
     use tempfile::TempDir;
 
     #[tokio::test]
-    async fn test_download() {
+    async fn test_results_path() {
         let tempdir = TempDir::new().unwrap();
         let job = Job::new(tempdir.path().to_str().unwrap());
 
-        let _ = fs::create_dir_all(&job.loc);
+        let _ = fs::create_dir_all(job.state_dir());
         let test_data = b"test content".to_vec();
-        fs::write(job.loc.join("output.zip"), &test_data).unwrap();
+        fs::write(job.state_dir().join(RESULTS_ARCHIVE), &test_data).unwrap();
 
-        let result = job.download();
+        let result = fs::read(job.results_path()).unwrap();
         assert_eq!(result, test_data);
     }
 
@@ -93,6 +231,13 @@ mod test {
         assert_eq!(job.service, "test".to_string())
     }
 
+    #[test]
+    fn test_set_labels() {
+        let mut job = Job::new("");
+        job.set_labels("gpu, large,,");
+        assert_eq!(job.labels, vec!["gpu".to_string(), "large".to_string()])
+    }
+
     #[test]
     fn test_set_user_id() {
         let mut job = Job::new("");
@@ -1,8 +1,59 @@
+use std::fs;
 use std::path::PathBuf;
+use std::time::{Duration, UNIX_EPOCH};
 
-use crate::models::job_dao::Job;
+use crate::models::failure_dto::{Failure, FailureKind};
+use crate::models::job_dao::{Job, Timestamps};
+use crate::models::job_event_dao::Actor;
+use crate::models::job_event_dto::{create_job_events_table, record_event};
+use crate::models::job_filter_dao::{JobFilter, JobPage, RequeueFilter, SortKey, SortOrder};
+use crate::models::resources_dto::Resources;
 use crate::models::status_dto::Status;
-use sqlx::{Row, SqlitePool};
+use crate::models::webhook_dto::create_webhooks_table;
+use sqlx::sqlite::SqliteRow;
+use sqlx::{QueryBuilder, Row, Sqlite, SqlitePool};
+
+// Columns added after the table was first released, with their definitions.
+// These are added to existing databases by `create_jobs_table`
+const ADDED_COLUMNS: &[(&str, &str)] = &[
+    ("backend", "TEXT NOT NULL DEFAULT ''"),
+    ("labels", "TEXT NOT NULL DEFAULT ''"),
+    ("input_size", "INTEGER NOT NULL DEFAULT 0"),
+    ("cores", "INTEGER NOT NULL DEFAULT 1"),
+    ("memory_mb", "INTEGER NOT NULL DEFAULT 0"),
+    ("scratch_mb", "INTEGER NOT NULL DEFAULT 0"),
+    ("datasets", "TEXT NOT NULL DEFAULT ''"),
+    ("queued_at", "DATETIME"),
+    ("dispatched_at", "DATETIME"),
+    ("started_at", "DATETIME"),
+    ("finished_at", "DATETIME"),
+    ("cleaned_at", "DATETIME"),
+    ("failure_reason", "TEXT"),
+    ("error_kind", "TEXT"),
+    ("exit_code", "INTEGER"),
+    ("signal", "INTEGER"),
+    ("stderr_tail", "TEXT"),
+    ("callback_url", "TEXT"),
+    ("attempts", "INTEGER NOT NULL DEFAULT 0"),
+    ("next_attempt_at", "DATETIME"),
+    ("max_runtime", "INTEGER"),
+    ("metadata", "TEXT NOT NULL DEFAULT '{}'"),
+    ("params", "TEXT NOT NULL DEFAULT '{}'"),
+    ("last_chunk_at", "DATETIME"),
+];
+
+// Lifecycle timestamp set when a job reaches `status`
+fn status_timestamp(status: &Status) -> Option<&'static str> {
+    match status {
+        Status::Queued => Some("queued_at"),
+        Status::Submitted => Some("dispatched_at"),
+        Status::Completed | Status::Failed | Status::Cancelled | Status::TimedOut => {
+            Some("finished_at")
+        }
+        Status::Cleaned => Some("cleaned_at"),
+        _ => None,
+    }
+}
 
 pub async fn create_jobs_table(pool: &SqlitePool) -> Result<(), sqlx::Error> {
     sqlx::query(
@@ -14,28 +65,305 @@ pub async fn create_jobs_table(pool: &SqlitePool) -> Result<(), sqlx::Error> {
             status TEXT NOT NULL,
             loc TEXT NOT NULL,
             dest_id INTEGER,
-            created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+            created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+            backend TEXT NOT NULL DEFAULT '',
+            labels TEXT NOT NULL DEFAULT '',
+            input_size INTEGER NOT NULL DEFAULT 0,
+            cores INTEGER NOT NULL DEFAULT 1,
+            memory_mb INTEGER NOT NULL DEFAULT 0,
+            scratch_mb INTEGER NOT NULL DEFAULT 0,
+            datasets TEXT NOT NULL DEFAULT '',
+            queued_at DATETIME,
+            dispatched_at DATETIME,
+            started_at DATETIME,
+            finished_at DATETIME,
+            cleaned_at DATETIME,
+            failure_reason TEXT,
+            error_kind TEXT,
+            exit_code INTEGER,
+            signal INTEGER,
+            stderr_tail TEXT,
+            callback_url TEXT,
+            attempts INTEGER NOT NULL DEFAULT 0,
+            next_attempt_at DATETIME,
+            max_runtime INTEGER,
+            metadata TEXT NOT NULL DEFAULT '{}',
+            params TEXT NOT NULL DEFAULT '{}',
+            last_chunk_at DATETIME
         )
     "#,
     )
     .execute(pool)
     .await?;
+
+    let existing: Vec<String> = sqlx::query("PRAGMA table_info(jobs)")
+        .fetch_all(pool)
+        .await?
+        .iter()
+        .map(|row| row.get("name"))
+        .collect();
+
+    for (column, definition) in ADDED_COLUMNS {
+        if !existing.iter().any(|c| c == column) {
+            sqlx::query(&format!(
+                "ALTER TABLE jobs ADD COLUMN {column} {definition}"
+            ))
+            .execute(pool)
+            .await?;
+        }
+    }
+
+    // The history of every job goes along with it, and the webhooks telling about it
+    create_job_events_table(pool).await?;
+    create_webhooks_table(pool).await?;
+
     Ok(())
 }
 
+// Turn a date or time into the format of `created_at`, `None` when it is not one
+pub async fn normalize_timestamp(
+    value: &str,
+    pool: &SqlitePool,
+) -> Result<Option<String>, sqlx::Error> {
+    let row = sqlx::query("SELECT datetime(?) AS timestamp")
+        .bind(value)
+        .fetch_one(pool)
+        .await?;
+    Ok(row.get("timestamp"))
+}
+
+// Submitted jobs that have been running on their backend for longer than their
+//  `max_runtime` and `grace` together. Counted from when they were seen running, a job
+//  waiting in the queue of its backend has not started yet, or from when they were
+//  dispatched when they never were, ie without a status url
+pub async fn list_overdue_jobs(
+    grace: Duration,
+    pool: &SqlitePool,
+) -> Result<Vec<Job>, sqlx::Error> {
+    let rows = sqlx::query(
+        "SELECT * FROM jobs WHERE status = ? AND max_runtime IS NOT NULL AND COALESCE(started_at, dispatched_at) <= datetime('now', '-' || (max_runtime + ?) || ' seconds') ORDER BY id",
+    )
+    .bind(Status::Submitted.to_string())
+    .bind(grace.as_secs() as i64)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows.iter().map(Job::from_row).collect())
+}
+
+// Jobs started with `/uploads` that got no chunk for `ttl`, or were never sent any since
+//  they were created
+pub async fn list_abandoned_uploads(
+    ttl: Duration,
+    pool: &SqlitePool,
+) -> Result<Vec<Job>, sqlx::Error> {
+    let rows = sqlx::query(
+        "SELECT * FROM jobs WHERE status = ? AND COALESCE(last_chunk_at, created_at) <= datetime('now', '-' || ? || ' seconds') ORDER BY id",
+    )
+    .bind(Status::Uploading.to_string())
+    .bind(ttl.as_secs() as i64)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows.iter().map(Job::from_row).collect())
+}
+
+// Every job that matches `filter` and can be retried, oldest first. The times are expected
+//  to be validated already, see `normalize_timestamp`
+pub async fn list_retryable_jobs(
+    filter: &RequeueFilter,
+    pool: &SqlitePool,
+) -> Result<Vec<Job>, sqlx::Error> {
+    let statuses = match &filter.status {
+        Some(status) => vec![Status::from_string(status)],
+        None => vec![Status::Failed, Status::TimedOut, Status::Unknown],
+    };
+
+    let mut query: QueryBuilder<Sqlite> = QueryBuilder::new("SELECT * FROM jobs WHERE status IN (");
+    let mut separated = query.separated(", ");
+    for status in statuses.iter().filter(|s| s.is_retryable()) {
+        separated.push_bind(status.to_string());
+    }
+    query.push(")");
+    if let Some(service) = &filter.service {
+        query.push(" AND service = ").push_bind(service);
+    }
+    if let Some(created_after) = &filter.created_after {
+        query.push(" AND created_at >= ").push_bind(created_after);
+    }
+    if let Some(created_before) = &filter.created_before {
+        query.push(" AND created_at < ").push_bind(created_before);
+    }
+    query.push(" ORDER BY id");
+
+    let rows = query.build().fetch_all(pool).await?;
+    Ok(rows.iter().map(Job::from_row).collect())
+}
+
+// A page of the jobs that match `filter`, the status and times are expected to be
+//  validated already, see `normalize_timestamp`
+pub async fn list_jobs(filter: &JobFilter, pool: &SqlitePool) -> Result<JobPage, sqlx::Error> {
+    let order = match filter.order.unwrap_or_default() {
+        SortOrder::Asc => "ASC",
+        SortOrder::Desc => "DESC",
+    };
+    let after = match filter.order.unwrap_or_default() {
+        SortOrder::Asc => ">",
+        SortOrder::Desc => "<",
+    };
+
+    let mut query: QueryBuilder<Sqlite> = QueryBuilder::new("SELECT * FROM jobs WHERE 1 = 1");
+    if let Some(user_id) = filter.user_id {
+        query.push(" AND user_id = ").push_bind(user_id);
+    }
+    if let Some(service) = &filter.service {
+        query.push(" AND service = ").push_bind(service);
+    }
+    if let Some(status) = &filter.status {
+        query
+            .push(" AND status = ")
+            .push_bind(Status::from_string(status).to_string());
+    }
+    if let Some(created_after) = &filter.created_after {
+        query.push(" AND created_at >= ").push_bind(created_after);
+    }
+    if let Some(created_before) = &filter.created_before {
+        query.push(" AND created_at < ").push_bind(created_before);
+    }
+    for (key, value) in filter.metadata_pairs().unwrap_or_default() {
+        query
+            .push(" AND EXISTS (SELECT 1 FROM json_each(jobs.metadata) WHERE key = ")
+            .push_bind(key)
+            .push(" AND value = ")
+            .push_bind(value)
+            .push(")");
+    }
+
+    // Keyset pagination, continue right after the last job of the previous page
+    match (filter.sort.unwrap_or_default(), filter.cursor) {
+        (SortKey::CreatedAt, Some(cursor)) => {
+            query
+                .push(format!(
+                    " AND (created_at, id) {after} (SELECT created_at, id FROM jobs WHERE id = "
+                ))
+                .push_bind(cursor)
+                .push(")");
+        }
+        (SortKey::Id, Some(cursor)) => {
+            query.push(format!(" AND id {after} ")).push_bind(cursor);
+        }
+        (_, None) => {}
+    }
+    match filter.sort.unwrap_or_default() {
+        SortKey::CreatedAt => query.push(format!(" ORDER BY created_at {order}, id {order}")),
+        SortKey::Id => query.push(format!(" ORDER BY id {order}")),
+    };
+
+    // One more than asked, to know if there is a next page
+    let limit = filter.limit();
+    query.push(" LIMIT ").push_bind(limit + 1);
+
+    let rows = query.build().fetch_all(pool).await?;
+    let mut jobs: Vec<Job> = rows.iter().map(Job::from_row).collect();
+
+    let next_cursor = if jobs.len() > limit as usize {
+        jobs.truncate(limit as usize);
+        jobs.last().map(|j| j.id)
+    } else {
+        None
+    };
+
+    Ok(JobPage { jobs, next_cursor })
+}
+
+// What `Job::retry` forgets of the previous run
+const RETRY_RESET: &str = "dest_id = NULL, backend = '', dispatched_at = NULL, started_at = NULL, finished_at = NULL, failure_reason = NULL, error_kind = NULL, exit_code = NULL, signal = NULL, stderr_tail = NULL, attempts = 0, next_attempt_at = NULL";
+
 impl Job {
+    pub fn from_row(row: &SqliteRow) -> Job {
+        let status: String = row.get("status");
+        let loc: String = row.get("loc");
+        let dest_id: Option<u32> = row.get("dest_id");
+        let labels: String = row.get("labels");
+        let datasets: String = row.get("datasets");
+        let input_size: i64 = row.get("input_size");
+        let memory_mb: i64 = row.get("memory_mb");
+        let scratch_mb: i64 = row.get("scratch_mb");
+        let created_at: Option<String> = row.get("created_at");
+        let error_kind: Option<String> = row.get("error_kind");
+        let max_runtime: Option<i64> = row.get("max_runtime");
+        let metadata: String = row.get("metadata");
+        let params: String = row.get("params");
+        let mut job = Job {
+            id: row.get("id"),
+            user_id: row.get("user_id"),
+            service: row.get("service"),
+            status: Status::from_string(&status),
+            loc: PathBuf::from(loc),
+            dest_id: dest_id.unwrap_or(0),
+            backend: row.get("backend"),
+            labels: Vec::new(),
+            input_size: input_size as u64,
+            resources: Resources {
+                cores: row.get("cores"),
+                memory_mb: memory_mb as u64,
+                scratch_mb: scratch_mb as u64,
+            },
+            datasets: Vec::new(),
+            created_at: created_at.unwrap_or_default(),
+            timestamps: Timestamps {
+                queued_at: row.get("queued_at"),
+                dispatched_at: row.get("dispatched_at"),
+                started_at: row.get("started_at"),
+                finished_at: row.get("finished_at"),
+                cleaned_at: row.get("cleaned_at"),
+            },
+            failure_reason: row.get("failure_reason"),
+            failure: error_kind
+                .as_deref()
+                .and_then(FailureKind::from_string)
+                .map(|kind| Failure {
+                    kind,
+                    exit_code: row.get("exit_code"),
+                    signal: row.get("signal"),
+                    stderr_tail: row.get("stderr_tail"),
+                }),
+            callback_url: row.get("callback_url"),
+            attempts: row.get("attempts"),
+            next_attempt_at: row.get("next_attempt_at"),
+            max_runtime: max_runtime.map(|m| m as u64),
+            metadata: serde_json::from_str(&metadata).unwrap_or_default(),
+            params: serde_json::from_str(&params).unwrap_or_default(),
+            actor: Actor::default(),
+        };
+        job.set_labels(&labels);
+        job.set_datasets(&datasets);
+        job
+    }
+
     pub async fn add_to_db(&mut self, pool: &SqlitePool) -> Result<(), sqlx::Error> {
-        let result =
-            sqlx::query("INSERT INTO jobs (user_id, loc, status, service) VALUES (?, ?, ?, ?)")
-                .bind(self.user_id)
-                .bind(self.loc.to_str())
-                .bind(self.status.to_string())
-                .bind(self.service.to_string())
-                .execute(pool)
-                .await?;
+        let row = sqlx::query(
+            "INSERT INTO jobs (user_id, loc, status, service, labels, input_size, cores, memory_mb, scratch_mb, datasets, callback_url, max_runtime, metadata, params) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?) RETURNING id, created_at",
+        )
+        .bind(self.user_id)
+        .bind(self.loc.to_str())
+        .bind(self.status.to_string())
+        .bind(self.service.to_string())
+        .bind(self.labels.join(","))
+        .bind(self.input_size as i64)
+        .bind(self.resources.cores)
+        .bind(self.resources.memory_mb as i64)
+        .bind(self.resources.scratch_mb as i64)
+        .bind(self.datasets.join(","))
+        .bind(&self.callback_url)
+        .bind(self.max_runtime.map(|m| m as i64))
+        .bind(serde_json::to_string(&self.metadata).unwrap_or_else(|_| "{}".to_string()))
+        .bind(serde_json::to_string(&self.params).unwrap_or_else(|_| "{}".to_string()))
+        .fetch_one(pool)
+        .await?;
 
-        let job_id = result.last_insert_rowid();
-        self.id = job_id as i32;
+        self.id = row.get("id");
+        self.created_at = row.get("created_at");
 
         Ok(())
     }
@@ -45,17 +373,257 @@ impl Job {
         status: Status,
         pool: &SqlitePool,
     ) -> Result<(), sqlx::Error> {
-        let _result = sqlx::query("UPDATE jobs SET status = ? WHERE id = ?")
-            .bind(status.to_string())
+        self.set_status(None, status, None, pool).await?;
+        Ok(())
+    }
+
+    // Only moves to `status` if the job is still in `from`, returns whether it did.
+    //  This keeps the tasks and a cancellation from both acting on the same job
+    pub async fn transition_status(
+        &mut self,
+        from: Status,
+        status: Status,
+        pool: &SqlitePool,
+    ) -> Result<bool, sqlx::Error> {
+        self.set_status(Some(from), status, None, pool).await
+    }
+
+    // Moves a job that failed, or was lost, to `status` and keeps the reason. Like
+    //  `transition_status` when `from` is given
+    pub async fn update_failure(
+        &mut self,
+        from: Option<Status>,
+        status: Status,
+        reason: &str,
+        pool: &SqlitePool,
+    ) -> Result<bool, sqlx::Error> {
+        let moved = self.set_status(from, status, Some(reason), pool).await?;
+        if moved {
+            sqlx::query("UPDATE jobs SET failure_reason = ? WHERE id = ?")
+                .bind(reason)
+                .bind(self.id)
+                .execute(pool)
+                .await?;
+            self.failure_reason = Some(reason.to_string());
+        }
+        Ok(moved)
+    }
+
+    // Count a transient error, the tasks leave the job alone for `retry_in`
+    pub async fn record_failed_attempt(
+        &mut self,
+        retry_in: Duration,
+        pool: &SqlitePool,
+    ) -> Result<(), sqlx::Error> {
+        let row = sqlx::query(
+            "UPDATE jobs SET attempts = attempts + 1, next_attempt_at = datetime('now', ?) WHERE id = ? RETURNING attempts, next_attempt_at",
+        )
+        .bind(format!("+{} seconds", retry_in.as_secs()))
+        .bind(self.id)
+        .fetch_optional(pool)
+        .await?;
+
+        if let Some(row) = row {
+            self.attempts = row.get("attempts");
+            self.next_attempt_at = row.get("next_attempt_at");
+        }
+
+        Ok(())
+    }
+
+    // The backend answered again
+    pub async fn reset_attempts(&mut self, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE jobs SET attempts = 0, next_attempt_at = NULL WHERE id = ?")
             .bind(self.id)
             .execute(pool)
             .await?;
 
+        self.attempts = 0;
+        self.next_attempt_at = None;
+
+        Ok(())
+    }
+
+    // Back to the queue to be dispatched again, if it is still in `from`
+    pub async fn requeue(
+        &mut self,
+        from: Status,
+        reason: &str,
+        pool: &SqlitePool,
+    ) -> Result<bool, sqlx::Error> {
+        self.set_status(Some(from), Status::Queued, Some(reason), pool)
+            .await
+    }
+
+    // Back to the queue to run again from the inputs in `loc`, forgetting where and how it
+    //  ran before. Only when it is still in a status it can be retried from
+    pub async fn retry(&mut self, reason: &str, pool: &SqlitePool) -> Result<bool, sqlx::Error> {
+        let from = self.status.clone();
+        if !from.is_retryable() {
+            return Ok(false);
+        }
+
+        let moved = self
+            .set_status_and(
+                Some(from),
+                Status::Queued,
+                Some(reason),
+                Some(RETRY_RESET),
+                pool,
+            )
+            .await?;
+        if moved {
+            self.dest_id = 0;
+            self.backend = String::new();
+            self.timestamps.dispatched_at = None;
+            self.timestamps.started_at = None;
+            self.timestamps.finished_at = None;
+            self.failure_reason = None;
+            self.failure = None;
+            self.attempts = 0;
+            self.next_attempt_at = None;
+        }
+        Ok(moved)
+    }
+
+    // Keep what the client reported about the failure of this job
+    pub async fn update_failure_details(
+        &mut self,
+        failure: Failure,
+        pool: &SqlitePool,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "UPDATE jobs SET error_kind = ?, exit_code = ?, signal = ?, stderr_tail = ? WHERE id = ?",
+        )
+        .bind(failure.kind.to_string())
+        .bind(failure.exit_code)
+        .bind(failure.signal)
+        .bind(&failure.stderr_tail)
+        .bind(self.id)
+        .execute(pool)
+        .await?;
+
+        self.failure = Some(failure);
+
+        Ok(())
+    }
+
+    // Also stamps the lifecycle timestamp of the new status, see `status_timestamp`, and
+    //  records the change in the `job_events`
+    async fn set_status(
+        &mut self,
+        from: Option<Status>,
+        status: Status,
+        message: Option<&str>,
+        pool: &SqlitePool,
+    ) -> Result<bool, sqlx::Error> {
+        self.set_status_and(from, status, message, None, pool).await
+    }
+
+    // Like `set_status`, with more `column = value` assignments made in the same update
+    async fn set_status_and(
+        &mut self,
+        from: Option<Status>,
+        status: Status,
+        message: Option<&str>,
+        assignments: Option<&str>,
+        pool: &SqlitePool,
+    ) -> Result<bool, sqlx::Error> {
+        // Takes the write lock straight away, a deferred transaction that reads first
+        //  fails with SQLITE_BUSY when another one writes in between
+        let mut tx = pool.begin_with("BEGIN IMMEDIATE").await?;
+
+        let previous = sqlx::query("SELECT status FROM jobs WHERE id = ?")
+            .bind(self.id)
+            .fetch_optional(&mut *tx)
+            .await?
+            .map(|row| Status::from_string(row.get("status")));
+
+        let Some(previous) = previous else {
+            // Without the `from` check a missing job is not an error
+            if from.is_none() {
+                self.status = status;
+            }
+            return Ok(false);
+        };
+        if from.as_ref().is_some_and(|from| *from != previous) {
+            return Ok(false);
+        }
+
+        let stamp = status_timestamp(&status);
+        let mut query: QueryBuilder<Sqlite> = QueryBuilder::new("UPDATE jobs SET status = ");
+        query.push_bind(status.to_string());
+        if let Some(column) = stamp {
+            query.push(format!(", {column} = CURRENT_TIMESTAMP"));
+        }
+        if let Some(assignments) = assignments {
+            query.push(format!(", {assignments}"));
+        }
+        query.push(" WHERE id = ").push_bind(self.id);
+        query.push(" AND status = ").push_bind(previous.to_string());
+        query.push(format!(" RETURNING {}", stamp.unwrap_or("status")));
+        let Some(row) = query.build().fetch_optional(&mut *tx).await? else {
+            return Ok(false);
+        };
+
+        record_event(&mut tx, self.id, self.actor, &previous, &status, message).await?;
+        tx.commit().await?;
+
+        if let Some(column) = stamp {
+            self.timestamps.set(column, row.get(column));
+        }
         self.status = status;
 
+        Ok(true)
+    }
+
+    // The job was seen running on its backend, only the first time counts
+    pub async fn update_started(&mut self, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+        let row = sqlx::query(
+            "UPDATE jobs SET started_at = COALESCE(started_at, CURRENT_TIMESTAMP) WHERE id = ? RETURNING started_at",
+        )
+        .bind(self.id)
+        .fetch_optional(pool)
+        .await?;
+
+        if let Some(row) = row {
+            self.timestamps.set("started_at", row.get("started_at"));
+        }
+
+        Ok(())
+    }
+
+    // A chunk of its inputs came in, it is not abandoned, see `list_abandoned_uploads`
+    pub async fn update_last_chunk(&self, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE jobs SET last_chunk_at = CURRENT_TIMESTAMP WHERE id = ?")
+            .bind(self.id)
+            .execute(pool)
+            .await?;
         Ok(())
     }
 
+    // When the cleaner removes the files of this job, it goes by the last time its
+    //  directory was modified. `None` when the files are gone already
+    pub async fn expires_at(
+        &self,
+        max_age: Duration,
+        pool: &SqlitePool,
+    ) -> Result<Option<String>, sqlx::Error> {
+        let Some(modified) = fs::metadata(&self.loc)
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        else {
+            return Ok(None);
+        };
+
+        let row = sqlx::query("SELECT datetime(?, 'unixepoch') AS expires_at")
+            .bind((modified + max_age).as_secs() as i64)
+            .fetch_one(pool)
+            .await?;
+        Ok(row.get("expires_at"))
+    }
+
     pub async fn update_dest_id(
         &mut self,
         dest_id: u32,
@@ -72,6 +640,39 @@ impl Job {
         Ok(())
     }
 
+    // Once all of its inputs are in, see `/uploads`
+    pub async fn update_input_size(
+        &mut self,
+        input_size: u64,
+        pool: &SqlitePool,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE jobs SET input_size = ? WHERE id = ?")
+            .bind(input_size as i64)
+            .bind(self.id)
+            .execute(pool)
+            .await?;
+
+        self.input_size = input_size;
+
+        Ok(())
+    }
+
+    pub async fn update_backend(
+        &mut self,
+        backend: &str,
+        pool: &SqlitePool,
+    ) -> Result<(), sqlx::Error> {
+        let _result = sqlx::query("UPDATE jobs SET backend = ? WHERE id = ?")
+            .bind(backend)
+            .bind(self.id)
+            .execute(pool)
+            .await?;
+
+        self.backend = backend.to_string();
+
+        Ok(())
+    }
+
     pub async fn retrieve_id(&mut self, id: i32, pool: &SqlitePool) -> Result<(), sqlx::Error> {
         let row = sqlx::query("SELECT * FROM jobs WHERE id = ?")
             .bind(id)
@@ -79,14 +680,7 @@ impl Job {
             .await?
             .ok_or(sqlx::Error::RowNotFound)?;
 
-        let status: String = row.get("status");
-        let loc: String = row.get("loc");
-
-        self.id = row.get("id");
-        self.user_id = row.get("user_id");
-        self.status = Status::from_string(&status);
-        self.loc = PathBuf::from(loc);
-        self.dest_id = row.get("dest_id");
+        *self = Job::from_row(&row);
 
         Ok(())
     }
@@ -102,15 +696,237 @@ impl Job {
             .await?
             .ok_or(sqlx::Error::RowNotFound)?;
 
-        let status: String = row.get("status");
-        let loc: String = row.get("loc");
-
-        self.id = row.get("id");
-        self.user_id = row.get("user_id");
-        self.status = Status::from_string(&status);
-        self.loc = PathBuf::from(loc);
-        self.dest_id = row.get("dest_id");
+        *self = Job::from_row(&row);
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::models::job_event_dto::list_events;
+
+    async fn insert(pool: &SqlitePool, user_id: i32, service: &str, created_at: &str) -> i32 {
+        let row = sqlx::query("INSERT INTO jobs (user_id, service, status, loc, created_at) VALUES (?, ?, 'queued', 'loc', ?) RETURNING id")
+            .bind(user_id)
+            .bind(service)
+            .bind(created_at)
+            .fetch_one(pool)
+            .await
+            .unwrap();
+        row.get("id")
+    }
+
+    #[tokio::test]
+    async fn test_list_jobs_filters() {
+        let pool = SqlitePool::connect(":memory:").await.unwrap();
+        create_jobs_table(&pool).await.unwrap();
+
+        insert(&pool, 1, "A", "2025-01-01 10:00:00").await;
+        insert(&pool, 1, "B", "2025-01-02 10:00:00").await;
+        let id = insert(&pool, 2, "A", "2025-01-03 10:00:00").await;
+        sqlx::query("UPDATE jobs SET status = 'completed' WHERE id = ?")
+            .bind(id)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let filter = JobFilter {
+            user_id: Some(1),
+            ..Default::default()
+        };
+        let page = list_jobs(&filter, &pool).await.unwrap();
+        assert_eq!(page.jobs.len(), 2);
+        // Newest first by default
+        assert_eq!(page.jobs[0].service, "B");
+        assert_eq!(page.jobs[0].created_at, "2025-01-02 10:00:00");
+        assert_eq!(page.next_cursor, None);
+
+        let filter = JobFilter {
+            service: Some("A".to_string()),
+            status: Some("Completed".to_string()),
+            ..Default::default()
+        };
+        let page = list_jobs(&filter, &pool).await.unwrap();
+        assert_eq!(page.jobs.len(), 1);
+        assert_eq!(page.jobs[0].id, id);
+
+        let filter = JobFilter {
+            created_after: Some("2025-01-02 00:00:00".to_string()),
+            created_before: Some("2025-01-03 00:00:00".to_string()),
+            ..Default::default()
+        };
+        let page = list_jobs(&filter, &pool).await.unwrap();
+        assert_eq!(page.jobs.len(), 1);
+        assert_eq!(page.jobs[0].service, "B");
+    }
+
+    #[tokio::test]
+    async fn test_list_jobs_pagination() {
+        let pool = SqlitePool::connect(":memory:").await.unwrap();
+        create_jobs_table(&pool).await.unwrap();
+
+        // Same creation time, the id breaks the tie
+        let mut ids = Vec::new();
+        for _ in 0..5 {
+            ids.push(insert(&pool, 1, "A", "2025-01-01 10:00:00").await);
+        }
+
+        let mut filter = JobFilter {
+            limit: Some(2),
+            order: Some(SortOrder::Asc),
+            ..Default::default()
+        };
+        let mut seen = Vec::new();
+        loop {
+            let page = list_jobs(&filter, &pool).await.unwrap();
+            assert!(page.jobs.len() <= 2);
+            seen.extend(page.jobs.iter().map(|j| j.id));
+            match page.next_cursor {
+                Some(cursor) => filter.cursor = Some(cursor),
+                None => break,
+            }
+        }
+        assert_eq!(seen, ids);
+
+        let filter = JobFilter {
+            sort: Some(SortKey::Id),
+            cursor: Some(ids[2]),
+            ..Default::default()
+        };
+        let page = list_jobs(&filter, &pool).await.unwrap();
+        assert_eq!(
+            page.jobs.iter().map(|j| j.id).collect::<Vec<_>>(),
+            vec![ids[1], ids[0]]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_status_timestamps() {
+        let pool = SqlitePool::connect(":memory:").await.unwrap();
+        create_jobs_table(&pool).await.unwrap();
+
+        let mut job = Job::new("");
+        job.add_to_db(&pool).await.unwrap();
+        assert!(!job.created_at.is_empty());
+
+        job.update_status(Status::Queued, &pool).await.unwrap();
+        assert!(job.timestamps.queued_at.is_some());
+        assert!(job.timestamps.dispatched_at.is_none());
+
+        // Statuses without a timestamp leave them as they are
+        job.update_status(Status::Processing, &pool).await.unwrap();
+        job.transition_status(Status::Processing, Status::Submitted, &pool)
+            .await
+            .unwrap();
+        job.update_started(&pool).await.unwrap();
+        let started_at = job.timestamps.started_at.clone();
+        assert!(started_at.is_some());
+        job.actor = Actor::Getter;
+        job.update_failure(None, Status::Failed, "Failed on its backend", &pool)
+            .await
+            .unwrap();
+
+        let mut stored = Job::new("");
+        stored.retrieve_id(job.id, &pool).await.unwrap();
+        assert_eq!(stored.timestamps, job.timestamps);
+        assert!(stored.timestamps.dispatched_at.is_some());
+        assert!(stored.timestamps.finished_at.is_some());
+        assert_eq!(stored.timestamps.started_at, started_at);
+        assert_eq!(
+            stored.failure_reason.as_deref(),
+            Some("Failed on its backend")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_status_events() {
+        let pool = SqlitePool::connect(":memory:").await.unwrap();
+        create_jobs_table(&pool).await.unwrap();
+
+        let mut job = Job::new("");
+        job.add_to_db(&pool).await.unwrap();
+        job.update_status(Status::Queued, &pool).await.unwrap();
+        job.actor = Actor::Sender;
+        job.transition_status(Status::Queued, Status::Processing, &pool)
+            .await
+            .unwrap();
+        // Not in `Queued` anymore, nothing happens and nothing is recorded
+        let moved = job
+            .transition_status(Status::Queued, Status::Processing, &pool)
+            .await
+            .unwrap();
+        assert!(!moved);
+        job.update_failure(
+            Some(Status::Processing),
+            Status::Failed,
+            "Upload failed",
+            &pool,
+        )
+        .await
+        .unwrap();
+
+        let events = list_events(job.id, &pool).await.unwrap();
+        let transitions: Vec<_> = events
+            .iter()
+            .map(|e| (e.actor, e.from_status.clone(), e.to_status.clone()))
+            .collect();
+        assert_eq!(
+            transitions,
+            vec![
+                (Actor::Api, Status::Unknown, Status::Queued),
+                (Actor::Sender, Status::Queued, Status::Processing),
+                (Actor::Sender, Status::Processing, Status::Failed),
+            ]
+        );
+        assert_eq!(events[2].message.as_deref(), Some("Upload failed"));
+        assert!(events[0].message.is_none());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_transitions() {
+        // A file, in-memory databases don't lock the same way
+        let temp_dir = tempfile::tempdir().unwrap();
+        let url = format!("sqlite://{}?mode=rwc", temp_dir.path().join("db").display());
+        let pool = SqlitePool::connect(&url).await.unwrap();
+        create_jobs_table(&pool).await.unwrap();
+
+        for _ in 0..20 {
+            let mut job = Job::new("");
+            job.add_to_db(&pool).await.unwrap();
+            job.update_status(Status::Submitted, &pool).await.unwrap();
+
+            let mut getter = Job::new("");
+            getter.retrieve_id(job.id, &pool).await.unwrap();
+            let (timed_out, cancelled) = tokio::join!(
+                job.transition_status(Status::Submitted, Status::TimedOut, &pool),
+                getter.transition_status(Status::Submitted, Status::Cancelled, &pool),
+            );
+            // Exactly one of them moved the job, the other one saw it had moved
+            assert!(timed_out.unwrap() ^ cancelled.unwrap());
+
+            let events = list_events(job.id, &pool).await.unwrap();
+            let from_submitted = events
+                .iter()
+                .filter(|e| e.from_status == Status::Submitted)
+                .count();
+            assert_eq!(from_submitted, 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_normalize_timestamp() {
+        let pool = SqlitePool::connect(":memory:").await.unwrap();
+
+        let result = normalize_timestamp("2025-01-31T12:00:00Z", &pool).await;
+        assert_eq!(result.unwrap().as_deref(), Some("2025-01-31 12:00:00"));
+
+        let result = normalize_timestamp("2025-01-31", &pool).await;
+        assert_eq!(result.unwrap().as_deref(), Some("2025-01-31 00:00:00"));
+
+        let result = normalize_timestamp("yesterday", &pool).await;
+        assert_eq!(result.unwrap(), None);
+    }
+}
@@ -0,0 +1,54 @@
+use crate::models::status_dto::Status;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use utoipa::ToSchema;
+
+// What moved a job to another status
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Actor {
+    Sender,
+    Getter,
+    Cleaner,
+    // Timed out a job that outlived its `max_runtime`
+    Timekeeper,
+    #[default]
+    Api,
+}
+
+impl fmt::Display for Actor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Actor::Sender => write!(f, "sender"),
+            Actor::Getter => write!(f, "getter"),
+            Actor::Cleaner => write!(f, "cleaner"),
+            Actor::Timekeeper => write!(f, "timekeeper"),
+            Actor::Api => write!(f, "api"),
+        }
+    }
+}
+
+impl Actor {
+    pub fn from_string(s: &str) -> Self {
+        match s {
+            "sender" => Actor::Sender,
+            "getter" => Actor::Getter,
+            "cleaner" => Actor::Cleaner,
+            "timekeeper" => Actor::Timekeeper,
+            _ => Actor::Api,
+        }
+    }
+}
+
+// A status transition of a job, see `job_event_dto`
+#[derive(Debug, Serialize, ToSchema)]
+pub struct JobEvent {
+    pub id: i64,
+    pub job_id: i32,
+    pub actor: Actor,
+    pub from_status: Status,
+    pub to_status: Status,
+    pub message: Option<String>,
+    // As `YYYY-MM-DD HH:MM:SS` in UTC
+    pub created_at: String,
+}
@@ -0,0 +1,140 @@
+use crate::models::job_event_dao::{Actor, JobEvent};
+use crate::models::status_dto::Status;
+use sqlx::sqlite::SqliteRow;
+use sqlx::{Row, Sqlite, SqlitePool, Transaction};
+
+// The transitions that are announced, into a finished status. Other events are never
+//  marked as notified, the same condition keeps them out of `job_events_unnotified`
+const ANNOUNCED: &str = "notified_at IS NULL
+            AND to_status IN ('completed', 'failed', 'cancelled', 'timed_out', 'cleaned')";
+
+pub async fn create_job_events_table(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS job_events (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            job_id INTEGER NOT NULL,
+            actor TEXT NOT NULL,
+            from_status TEXT NOT NULL,
+            to_status TEXT NOT NULL,
+            message TEXT,
+            created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+            notified_at DATETIME
+        )
+    "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // Tables from before the webhooks, their events are not announced anymore
+    let existing: Vec<String> = sqlx::query("PRAGMA table_info(job_events)")
+        .fetch_all(pool)
+        .await?
+        .iter()
+        .map(|row| row.get("name"))
+        .collect();
+    if !existing.iter().any(|c| c == "notified_at") {
+        sqlx::query("ALTER TABLE job_events ADD COLUMN notified_at DATETIME")
+            .execute(pool)
+            .await?;
+        sqlx::query("UPDATE job_events SET notified_at = created_at")
+            .execute(pool)
+            .await?;
+    }
+
+    sqlx::query("CREATE INDEX IF NOT EXISTS job_events_job_id ON job_events (job_id)")
+        .execute(pool)
+        .await?;
+    sqlx::query(&format!(
+        "CREATE INDEX IF NOT EXISTS job_events_unnotified ON job_events (id) WHERE {ANNOUNCED}"
+    ))
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+// Recorded in the same transaction as the status change itself
+pub async fn record_event(
+    tx: &mut Transaction<'_, Sqlite>,
+    job_id: i32,
+    actor: Actor,
+    from: &Status,
+    to: &Status,
+    message: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO job_events (job_id, actor, from_status, to_status, message) VALUES (?, ?, ?, ?, ?)",
+    )
+    .bind(job_id)
+    .bind(actor.to_string())
+    .bind(from.to_string())
+    .bind(to.to_string())
+    .bind(message)
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+// Events of a job, oldest first
+pub async fn list_events(job_id: i32, pool: &SqlitePool) -> Result<Vec<JobEvent>, sqlx::Error> {
+    let rows = sqlx::query("SELECT * FROM job_events WHERE job_id = ? ORDER BY id")
+        .bind(job_id)
+        .fetch_all(pool)
+        .await?;
+
+    Ok(rows.iter().map(event_from_row).collect())
+}
+
+// Transitions into a finished status that were not announced yet, see `webhook_dto`
+pub async fn list_unnotified_events(pool: &SqlitePool) -> Result<Vec<JobEvent>, sqlx::Error> {
+    let rows = sqlx::query(&format!(
+        "SELECT * FROM job_events WHERE {ANNOUNCED} AND from_status != to_status ORDER BY id"
+    ))
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows.iter().map(event_from_row).collect())
+}
+
+fn event_from_row(row: &SqliteRow) -> JobEvent {
+    let actor: String = row.get("actor");
+    let from_status: String = row.get("from_status");
+    let to_status: String = row.get("to_status");
+    JobEvent {
+        id: row.get("id"),
+        job_id: row.get("job_id"),
+        actor: Actor::from_string(&actor),
+        from_status: Status::from_string(&from_status),
+        to_status: Status::from_string(&to_status),
+        message: row.get("message"),
+        created_at: row.get("created_at"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_unnotified_events_index() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        create_job_events_table(&pool).await.unwrap();
+
+        // Only the events still to announce are looked at, not every one ever recorded
+        let plan: Vec<String> = sqlx::query(&format!(
+            "EXPLAIN QUERY PLAN SELECT * FROM job_events WHERE {ANNOUNCED} AND from_status != to_status ORDER BY id"
+        ))
+        .fetch_all(&pool)
+        .await
+        .unwrap()
+        .iter()
+        .map(|row| row.get("detail"))
+        .collect();
+        assert!(
+            plan.iter().any(|d| d.contains("job_events_unnotified")),
+            "{plan:?}"
+        );
+    }
+}
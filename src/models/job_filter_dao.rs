@@ -0,0 +1,122 @@
+use crate::models::job_dao::Job;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+// Jobs returned per page when the request does not say, and at most
+pub const DEFAULT_LIMIT: u32 = 50;
+pub const MAX_LIMIT: u32 = 500;
+
+// Query of `GET /jobs`, every filter is optional
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct JobFilter {
+    pub user_id: Option<i32>,
+    pub service: Option<String>,
+    // Name of the status, ie `queued`
+    pub status: Option<String>,
+    // Jobs created at or after this time, ie `2025-01-31` or `2025-01-31T12:00:00Z`
+    pub created_after: Option<String>,
+    // Jobs created before this time
+    pub created_before: Option<String>,
+    // Metadata the jobs must have, as comma separated `key:value` pairs, ie `project:42`
+    pub metadata: Option<String>,
+    // The `next_cursor` of the previous page
+    pub cursor: Option<i32>,
+    pub limit: Option<u32>,
+    #[param(inline)]
+    pub sort: Option<SortKey>,
+    #[param(inline)]
+    pub order: Option<SortOrder>,
+}
+
+// Body of `POST /admin/requeue`, every filter is optional
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct RequeueFilter {
+    pub service: Option<String>,
+    // One of `failed`, `timed_out` or `unknown`, all of them when missing
+    pub status: Option<String>,
+    // Jobs created at or after this time, as in `JobFilter`
+    pub created_after: Option<String>,
+    pub created_before: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct RequeueResult {
+    // Jobs that are queued again
+    pub requeued: Vec<i32>,
+    // Jobs that matched but whose inputs are gone, or whose last run could not be
+    //  cancelled on its backend
+    pub skipped: Vec<i32>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SortKey {
+    #[default]
+    CreatedAt,
+    Id,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
+}
+
+impl JobFilter {
+    pub fn limit(&self) -> u32 {
+        self.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT)
+    }
+
+    // `None` when one of the pairs has no `:`
+    pub fn metadata_pairs(&self) -> Option<Vec<(String, String)>> {
+        let Some(metadata) = &self.metadata else {
+            return Some(Vec::new());
+        };
+        metadata
+            .split(',')
+            .filter(|pair| !pair.trim().is_empty())
+            .map(|pair| {
+                let (key, value) = pair.split_once(':')?;
+                Some((key.trim().to_string(), value.trim().to_string()))
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct JobPage {
+    pub jobs: Vec<Job>,
+    // Pass it as `cursor` to get the next page, there are no more jobs when missing
+    pub next_cursor: Option<i32>,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_metadata_pairs() {
+        let filter = JobFilter {
+            metadata: Some("project:42, run:a:b".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            filter.metadata_pairs(),
+            Some(vec![
+                ("project".to_string(), "42".to_string()),
+                ("run".to_string(), "a:b".to_string()),
+            ])
+        );
+
+        assert_eq!(JobFilter::default().metadata_pairs(), Some(Vec::new()));
+
+        let filter = JobFilter {
+            metadata: Some("project".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(filter.metadata_pairs(), None);
+    }
+}
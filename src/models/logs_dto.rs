@@ -0,0 +1,151 @@
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use utoipa::ToSchema;
+
+// Where `run.sh` writes its output, in the state directory of the payload
+pub const STDOUT_LOG: &str = "stdout.log";
+pub const STDERR_LOG: &str = "stderr.log";
+
+// Bytes of each log that are served, the end of it when there is more
+pub const LOG_LIMIT: u64 = 1024 * 1024;
+
+// How often a stream looks for new output and status changes
+pub const STREAM_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct Logs {
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl Logs {
+    // The logs in `dir`, `NotFound` if neither is there
+    pub fn read(dir: &Path) -> std::io::Result<Logs> {
+        let stdout = read_tail(&dir.join(STDOUT_LOG), LOG_LIMIT);
+        let stderr = read_tail(&dir.join(STDERR_LOG), LOG_LIMIT);
+        match (stdout, stderr) {
+            (Err(e), Err(_)) => Err(e),
+            (stdout, stderr) => Ok(Logs {
+                stdout: stdout.unwrap_or_default(),
+                stderr: stderr.unwrap_or_default(),
+            }),
+        }
+    }
+
+    pub fn write(&self, dir: &Path) -> std::io::Result<()> {
+        std::fs::create_dir_all(dir)?;
+        std::fs::write(dir.join(STDOUT_LOG), &self.stdout)?;
+        std::fs::write(dir.join(STDERR_LOG), &self.stderr)?;
+        Ok(())
+    }
+}
+
+// The last `limit` bytes of a file, as text
+pub fn read_tail(path: &Path, limit: u64) -> std::io::Result<String> {
+    let mut file = File::open(path)?;
+    let size = file.metadata()?.len();
+    file.seek(SeekFrom::Start(size.saturating_sub(limit)))?;
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer)?;
+    Ok(String::from_utf8_lossy(&buffer).into_owned())
+}
+
+// Follows a log that is still being written, handing out the lines that were completed
+//  since the last call
+pub struct LogTail {
+    path: PathBuf,
+    offset: u64,
+    partial: Vec<u8>,
+}
+
+impl LogTail {
+    pub fn new(path: PathBuf) -> LogTail {
+        LogTail {
+            path,
+            offset: 0,
+            partial: Vec::new(),
+        }
+    }
+
+    // Nothing new, also when the log was not created yet
+    pub fn read_lines(&mut self) -> std::io::Result<Vec<String>> {
+        let mut file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        file.seek(SeekFrom::Start(self.offset))?;
+        let read = file.read_to_end(&mut self.partial)?;
+        self.offset += read as u64;
+
+        let mut lines = Vec::new();
+        while let Some(end) = self.partial.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.partial.drain(..=end).collect();
+            lines.push(String::from_utf8_lossy(&line[..end]).into_owned());
+        }
+        Ok(lines)
+    }
+
+    // Whatever is left after the last newline, once the log is complete
+    pub fn rest(&mut self) -> Option<String> {
+        if self.partial.is_empty() {
+            return None;
+        }
+        let rest = String::from_utf8_lossy(&self.partial).into_owned();
+        self.partial.clear();
+        Some(rest)
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn test_read_tail() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log");
+        std::fs::write(&path, "0123456789").unwrap();
+
+        assert_eq!(read_tail(&path, 4).unwrap(), "6789");
+        assert_eq!(read_tail(&path, 100).unwrap(), "0123456789");
+    }
+
+    #[test]
+    fn test_read_write() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(Logs::read(dir.path()).is_err());
+
+        let logs = Logs {
+            stdout: "out".to_string(),
+            stderr: "err".to_string(),
+        };
+        logs.write(dir.path()).unwrap();
+        assert_eq!(Logs::read(dir.path()).unwrap(), logs);
+
+        // One of the two is enough
+        std::fs::remove_file(dir.path().join(STDERR_LOG)).unwrap();
+        assert_eq!(Logs::read(dir.path()).unwrap().stdout, "out");
+    }
+
+    #[test]
+    fn test_log_tail() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(STDOUT_LOG);
+        let mut tail = LogTail::new(path.clone());
+        assert!(tail.read_lines().unwrap().is_empty());
+
+        std::fs::write(&path, "one\ntw").unwrap();
+        assert_eq!(tail.read_lines().unwrap(), vec!["one"]);
+
+        std::fs::write(&path, "one\ntwo\nthree").unwrap();
+        assert_eq!(tail.read_lines().unwrap(), vec!["two"]);
+        assert!(tail.read_lines().unwrap().is_empty());
+        assert_eq!(tail.rest().as_deref(), Some("three"));
+        assert_eq!(tail.rest(), None);
+    }
+}
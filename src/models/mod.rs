@@ -1,9 +1,22 @@
+pub mod archive_dto;
+pub mod failure_dto;
 pub mod health_dto;
 pub mod job_dao;
 pub mod job_dto;
+pub mod job_event_dao;
+pub mod job_event_dto;
+pub mod job_filter_dao;
+pub mod logs_dto;
 pub mod payload_dao;
 pub mod payload_dto;
 pub mod ping_dto;
 pub mod queue_dao;
 pub mod queue_dto;
+pub mod registration_dao;
+pub mod registration_dto;
+pub mod resources_dto;
+pub mod results_dto;
 pub mod status_dto;
+pub mod upload_dao;
+pub mod webhook_dao;
+pub mod webhook_dto;
@@ -1,10 +1,20 @@
+use crate::models::failure_dto::Failure;
 use crate::models::status_dto::Status;
 use crate::utils;
-use std::collections::HashMap;
+use crate::utils::io::{create_input_dirs, create_input_file, STATE_DIR};
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
+use std::io::Write;
 use std::path::PathBuf;
 use utoipa::ToSchema;
 
+// Parameters of a payload, as a JSON object of strings
+pub const PARAMS_FILE: &str = "params.json";
+
+// Everything in `loc` once the payload is done, as sent back to the server. Kept in the
+//  state directory, which is left out of it
+pub const OUTPUT_ARCHIVE: &str = "output.zip";
+
 #[derive(serde::Serialize, serde::Deserialize, Debug, ToSchema)]
 pub struct Payload {
     pub id: u32,
@@ -12,6 +22,18 @@ pub struct Payload {
     pub status: Status,
     #[schema(value_type = String)]
     pub loc: PathBuf,
+    // Process group of the running `run.sh`, only meaningful on this host
+    #[serde(skip)]
+    pub pid: Option<u32>,
+    // Why it failed, when it did
+    #[serde(default)]
+    pub failure: Option<Failure>,
+    // Seconds `run.sh` may run before it is killed, as sent by the server
+    #[serde(default)]
+    pub max_runtime: Option<u64>,
+    // Passed to `run.sh`, in `params.json` and as `JOB_PARAM_<NAME>` variables
+    #[serde(default)]
+    pub params: BTreeMap<String, String>,
 }
 
 impl Payload {
@@ -21,6 +43,10 @@ impl Payload {
             input: HashMap::new(),
             status: Status::Unknown,
             loc: PathBuf::new(),
+            pid: None,
+            failure: None,
+            max_runtime: None,
+            params: BTreeMap::new(),
         }
     }
 
@@ -36,36 +62,66 @@ impl Payload {
         self.status = status;
     }
 
+    pub fn set_max_runtime(&mut self, max_runtime: u64) {
+        self.max_runtime = Some(max_runtime);
+    }
+
+    pub fn set_params(&mut self, params: BTreeMap<String, String>) {
+        self.params = params;
+    }
+
     pub fn set_loc(&mut self, loc: PathBuf) {
         self.loc = loc;
     }
 
+    // Where its logs and archive are kept, apart from the inputs and outputs of `run.sh`
+    pub fn state_dir(&self) -> PathBuf {
+        self.loc.join(STATE_DIR)
+    }
+
     pub fn prepare(&mut self, data_path: &str) -> Result<(), std::io::Error> {
         self.loc = std::path::Path::new(&data_path).join(self.id.to_string());
 
         // Create directory for this payload
         fs::create_dir_all(&self.loc)?;
 
-        // Dump data to this directory
-        self.input.iter_mut().for_each(|(filename, data)| {
-            fs::write(self.loc.join(filename), data).expect("Unable to write file")
-        });
+        // Dump data to this directory, in the subdirectories of their relative paths
+        for (filename, data) in &self.input {
+            let path = create_input_dirs(&self.loc, std::path::Path::new(filename))
+                .map_err(std::io::Error::other)?;
+            create_input_file(&path)?.write_all(data)?;
+        }
+
+        // The server refuses inputs with the same name, see `validate_inputs`
+        if !self.params.is_empty() {
+            fs::write(
+                self.loc.join(PARAMS_FILE),
+                serde_json::json!(self.params).to_string(),
+            )?;
+        }
 
         Ok(())
     }
 
-    pub fn zip_directory(self) -> Vec<u8> {
-        // Get everything from the `loc` and return it
-        let result = self.loc.join("output.zip");
+    // Zip everything in `loc` once, but its state directory, and give where the archive is
+    pub fn zip_directory(&self) -> std::io::Result<PathBuf> {
+        let result = self.state_dir().join(OUTPUT_ARCHIVE);
 
         // Check if output.zip exists to avoid re-zipping
         if !result.exists() {
-            // Not exists, create it by zipping the directory
-            let _ = utils::io::zip_directory(&self.loc, &result);
+            fs::create_dir_all(self.state_dir())?;
+            // Built next to `loc` so it does not end up in itself, and only shows up
+            //  once complete for a request coming in the meantime. Each request builds its
+            //  own, the last one to finish replaces the others with the same content
+            let parent = self.loc.parent().unwrap_or(&self.loc);
+            let partial = tempfile::Builder::new()
+                .suffix(".zip.part")
+                .tempfile_in(parent)?;
+            utils::io::zip_directory(&self.loc, &partial.path().to_path_buf(), &[STATE_DIR])?;
+            partial.persist(&result).map_err(|e| e.error)?;
         }
 
-        // Read the output.zip file and return its content
-        std::fs::read(result).expect("Unable to read output.zip")
+        Ok(result)
     }
 }
 
@@ -89,6 +145,7 @@ mod test {
         let mut p = Payload::new();
         p.id = 1;
         p.add_input("test.txt".to_string(), b"Test data".to_vec());
+        p.add_input("data/ligands/test.txt".to_string(), b"Nested".to_vec());
 
         let temp_dir = tempfile::tempdir().unwrap();
         let data_path = temp_dir.path().to_str().unwrap();
@@ -101,5 +158,52 @@ mod test {
 
         let content = fs::read_to_string(expected_path).unwrap();
         assert_eq!(content, "Test data");
+
+        let nested = temp_dir.path().join("1/data/ligands/test.txt");
+        assert_eq!(fs::read_to_string(nested).unwrap(), "Nested");
+    }
+
+    #[tokio::test]
+    async fn test_prepare_params() {
+        let mut p = Payload::new();
+        p.id = 1;
+        p.set_params(BTreeMap::from([("project".to_string(), "42".to_string())]));
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        p.prepare(temp_dir.path().to_str().unwrap()).unwrap();
+
+        let content = fs::read_to_string(temp_dir.path().join("1").join(PARAMS_FILE)).unwrap();
+        let params: BTreeMap<String, String> = serde_json::from_str(&content).unwrap();
+        assert_eq!(params, p.params);
+    }
+
+    #[test]
+    fn test_zip_directory_concurrent() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut p = Payload::new();
+        p.set_loc(temp_dir.path().join("1"));
+        fs::create_dir_all(&p.loc).unwrap();
+        fs::write(p.loc.join("output.txt"), vec![b'x'; 1 << 20]).unwrap();
+        fs::create_dir(p.state_dir()).unwrap();
+        fs::write(p.state_dir().join("stdout.log"), "out").unwrap();
+        let p = std::sync::Arc::new(p);
+
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let p = p.clone();
+                std::thread::spawn(move || p.zip_directory().unwrap())
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        // Neither the logs nor the archive itself are in it
+        let archive = fs::File::open(p.state_dir().join(OUTPUT_ARCHIVE)).unwrap();
+        let mut zip = zip::ZipArchive::new(archive).unwrap();
+        assert_eq!(zip.by_name("output.txt").unwrap().size(), 1 << 20);
+        assert_eq!(zip.len(), 1);
+        // No partial archive is left behind
+        assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 1);
     }
 }
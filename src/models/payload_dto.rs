@@ -1,28 +1,68 @@
+use crate::models::failure_dto::{Failure, FailureKind};
 use crate::models::payload_dao::Payload;
 use crate::models::status_dto::Status;
 use sqlx::{Row, SqlitePool};
 
+// Columns added after the table was first released, see `job_dto::create_jobs_table`
+const ADDED_COLUMNS: &[(&str, &str)] = &[
+    ("pid", "INTEGER"),
+    ("error_kind", "TEXT"),
+    ("exit_code", "INTEGER"),
+    ("signal", "INTEGER"),
+    ("stderr_tail", "TEXT"),
+    ("max_runtime", "INTEGER"),
+    ("params", "TEXT NOT NULL DEFAULT '{}'"),
+];
+
 pub async fn create_payload_table(pool: &SqlitePool) -> Result<(), sqlx::Error> {
     sqlx::query(
         r#"
         CREATE TABLE IF NOT EXISTS payloads (
             id INTEGER PRIMARY KEY AUTOINCREMENT,
             status TEXT NOT NULL,
-            created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+            created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+            pid INTEGER,
+            error_kind TEXT,
+            exit_code INTEGER,
+            signal INTEGER,
+            stderr_tail TEXT,
+            max_runtime INTEGER,
+            params TEXT NOT NULL DEFAULT '{}'
         )
     "#,
     )
     .execute(pool)
     .await?;
+
+    let existing: Vec<String> = sqlx::query("PRAGMA table_info(payloads)")
+        .fetch_all(pool)
+        .await?
+        .iter()
+        .map(|row| row.get("name"))
+        .collect();
+
+    for (column, definition) in ADDED_COLUMNS {
+        if !existing.iter().any(|c| c == column) {
+            sqlx::query(&format!(
+                "ALTER TABLE payloads ADD COLUMN {column} {definition}"
+            ))
+            .execute(pool)
+            .await?;
+        }
+    }
+
     Ok(())
 }
 
 impl Payload {
     pub async fn add_to_db(&mut self, pool: &SqlitePool) -> Result<(), sqlx::Error> {
-        let result = sqlx::query("INSERT INTO payloads (status) VALUES (?)")
-            .bind(self.status.to_string())
-            .execute(pool)
-            .await?;
+        let result =
+            sqlx::query("INSERT INTO payloads (status, max_runtime, params) VALUES (?, ?, ?)")
+                .bind(self.status.to_string())
+                .bind(self.max_runtime.map(|m| m as i64))
+                .bind(serde_json::json!(self.params).to_string())
+                .execute(pool)
+                .await?;
 
         let id = result.last_insert_rowid();
         self.id = id as u32;
@@ -46,6 +86,63 @@ impl Payload {
         Ok(())
     }
 
+    // Only moves to `status` if the payload is still in `from`, returns whether it did.
+    //  This keeps the runner and a cancellation from both acting on the same payload
+    pub async fn transition_status(
+        &mut self,
+        from: Status,
+        status: Status,
+        pool: &SqlitePool,
+    ) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query("UPDATE payloads SET status = ? WHERE id = ? AND status = ?")
+            .bind(status.to_string())
+            .bind(self.id)
+            .bind(from.to_string())
+            .execute(pool)
+            .await?;
+
+        if result.rows_affected() == 0 {
+            return Ok(false);
+        }
+
+        self.status = status;
+
+        Ok(true)
+    }
+
+    pub async fn update_pid(&mut self, pid: u32, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE payloads SET pid = ? WHERE id = ?")
+            .bind(pid)
+            .bind(self.id)
+            .execute(pool)
+            .await?;
+
+        self.pid = Some(pid);
+
+        Ok(())
+    }
+
+    pub async fn update_failure(
+        &mut self,
+        failure: Failure,
+        pool: &SqlitePool,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "UPDATE payloads SET error_kind = ?, exit_code = ?, signal = ?, stderr_tail = ? WHERE id = ?",
+        )
+        .bind(failure.kind.to_string())
+        .bind(failure.exit_code)
+        .bind(failure.signal)
+        .bind(&failure.stderr_tail)
+        .bind(self.id)
+        .execute(pool)
+        .await?;
+
+        self.failure = Some(failure);
+
+        Ok(())
+    }
+
     pub async fn retrieve_id(&mut self, id: u32, pool: &SqlitePool) -> Result<(), sqlx::Error> {
         let row = sqlx::query("SELECT * FROM payloads WHERE id = ?")
             .bind(id)
@@ -57,6 +154,21 @@ impl Payload {
 
         self.id = row.get("id");
         self.status = Status::from_string(&status);
+        self.pid = row.get("pid");
+        let max_runtime: Option<i64> = row.get("max_runtime");
+        self.max_runtime = max_runtime.map(|m| m as u64);
+        let params: String = row.get("params");
+        self.params = serde_json::from_str(&params).unwrap_or_default();
+        let error_kind: Option<String> = row.get("error_kind");
+        self.failure = error_kind
+            .as_deref()
+            .and_then(FailureKind::from_string)
+            .map(|kind| Failure {
+                kind,
+                exit_code: row.get("exit_code"),
+                signal: row.get("signal"),
+                stderr_tail: row.get("stderr_tail"),
+            });
 
         Ok(())
     }
@@ -99,6 +211,36 @@ mod test {
         assert_eq!(payload.status, Status::Prepared);
     }
 
+    #[tokio::test]
+    async fn test_transition_status() {
+        let pool = crate::datasource::db::init_payload_db().await;
+
+        let mut payload = Payload::new();
+        payload
+            .add_to_db(&pool)
+            .await
+            .expect("Failed to add payload to DB");
+        payload
+            .update_status(Status::Prepared, &pool)
+            .await
+            .expect("Failed to update payload status");
+
+        let moved = payload
+            .transition_status(Status::Prepared, Status::Processing, &pool)
+            .await
+            .expect("Failed to transition payload status");
+        assert!(moved);
+        assert_eq!(payload.status, Status::Processing);
+
+        // It is no longer `Prepared`, so it cannot move again
+        let moved = payload
+            .transition_status(Status::Prepared, Status::Cancelled, &pool)
+            .await
+            .expect("Failed to transition payload status");
+        assert!(!moved);
+        assert_eq!(payload.status, Status::Processing);
+    }
+
     #[tokio::test]
     async fn test_retrieve_id() {
         let pool = crate::datasource::db::init_payload_db().await;
@@ -120,5 +262,33 @@ mod test {
 
         assert_eq!(retrieved_payload.id, id);
         assert_eq!(retrieved_payload.status, Status::Unknown);
+        assert_eq!(retrieved_payload.pid, None);
+
+        payload
+            .update_pid(4242, &pool)
+            .await
+            .expect("Failed to update payload pid");
+        retrieved_payload
+            .retrieve_id(id, &pool)
+            .await
+            .expect("Failed to retrieve payload by ID");
+        assert_eq!(retrieved_payload.pid, Some(4242));
+        assert_eq!(retrieved_payload.failure, None);
+
+        let failure = Failure {
+            kind: FailureKind::Script,
+            exit_code: Some(1),
+            signal: None,
+            stderr_tail: Some("error".to_string()),
+        };
+        payload
+            .update_failure(failure.clone(), &pool)
+            .await
+            .expect("Failed to update payload failure");
+        retrieved_payload
+            .retrieve_id(id, &pool)
+            .await
+            .expect("Failed to retrieve payload by ID");
+        assert_eq!(retrieved_payload.failure, Some(failure));
     }
 }
@@ -1,9 +1,14 @@
-use std::path::{Path, PathBuf};
+use std::path::Path;
 
 use super::{queue_dao::Queue, status_dto::Status};
+use crate::models::registration_dto::list_registrations;
+use crate::models::resources_dto::Resources;
 use crate::models::{job_dao::Job, payload_dao::Payload, queue_dao::PayloadQueue};
+use crate::services::routing;
 use sqlx::{Row, SqlitePool};
 use std::collections::HashMap;
+use std::time::SystemTime;
+use tracing::warn;
 
 impl Queue<'_> {
     pub async fn list_per_status(
@@ -11,115 +16,109 @@ impl Queue<'_> {
         status: Status,
         pool: &SqlitePool,
     ) -> Result<(), sqlx::Error> {
-        let rows = sqlx::query("SELECT * FROM jobs WHERE status = ?")
-            .bind(status.to_string())
-            .fetch_all(pool)
-            .await?;
+        // Jobs waiting to be retried are left out until it is time
+        let rows = sqlx::query(
+            "SELECT * FROM jobs WHERE status = ? AND (next_attempt_at IS NULL OR next_attempt_at <= CURRENT_TIMESTAMP)",
+        )
+        .bind(status.to_string())
+        .fetch_all(pool)
+        .await?;
 
-        let jobs: Vec<Job> = rows
-            .into_iter()
-            .map(|row| {
-                let status: String = row.get("status");
-                let loc: String = row.get("loc");
-                let dest_id: u32 = row.get("dest_id");
-                Job {
-                    id: row.get("id"),
-                    user_id: row.get("user_id"),
-                    service: row.get("service"),
-                    status: Status::from_string(&status),
-                    loc: PathBuf::from(loc),
-                    dest_id,
-                }
-            })
-            .collect();
+        let jobs: Vec<Job> = rows.iter().map(Job::from_row).collect();
         self.jobs = jobs;
         Ok(())
     }
     pub async fn load(&mut self, pool: &SqlitePool) -> Result<(), sqlx::Error> {
         // ===========================================================================================
-        // Step 1: Get all QUEUED jobs
-        let rows = sqlx::query("SELECT * FROM jobs WHERE status = ?")
-            .bind(Status::Queued.to_string())
-            .fetch_all(pool)
-            .await?;
+        // Step 1: Get all QUEUED jobs, except the ones waiting to be retried
+        let rows = sqlx::query(
+            "SELECT * FROM jobs WHERE status = ? AND (next_attempt_at IS NULL OR next_attempt_at <= CURRENT_TIMESTAMP)",
+        )
+        .bind(Status::Queued.to_string())
+        .fetch_all(pool)
+        .await?;
 
         // ===========================================================================================
-        // Step 2: Get submitted job counts per user/service
+        // Step 2: Get submitted job counts per user/service/backend
         let submitted_rows = sqlx::query(
-            "SELECT user_id, service, COUNT(*) as count FROM jobs WHERE status = 'submitted' GROUP BY user_id, service"
+            "SELECT user_id, service, backend, COUNT(*) as count FROM jobs WHERE status = 'submitted' GROUP BY user_id, service, backend"
         )
         .fetch_all(pool)
         .await?;
-        let mut submitted_counts: HashMap<(i64, String), u16> = HashMap::new();
+        let mut submitted_counts: HashMap<(i32, String, String), u16> = HashMap::new();
         for row in submitted_rows {
-            let user_id: i64 = row.get("user_id");
+            let user_id: i32 = row.get("user_id");
             let service: String = row.get("service");
+            let backend: String = row.get("backend");
             let count: i64 = row.get("count");
-            submitted_counts.insert((user_id, service), count as u16);
+            submitted_counts.insert((user_id, service, backend), count as u16);
+        }
+
+        // ===========================================================================================
+        // Step 2b: Get the resources advertised by the backends, and what is in use there
+        let registrations = list_registrations(pool).await?;
+        let used_rows = sqlx::query(
+            "SELECT service, backend, SUM(cores) as cores, SUM(memory_mb) as memory_mb, SUM(scratch_mb) as scratch_mb FROM jobs WHERE status IN ('submitted', 'processing') GROUP BY service, backend"
+        )
+        .fetch_all(pool)
+        .await?;
+        let mut used_resources: HashMap<(String, String), Resources> = HashMap::new();
+        for row in used_rows {
+            let cores: i64 = row.get("cores");
+            let memory_mb: i64 = row.get("memory_mb");
+            let scratch_mb: i64 = row.get("scratch_mb");
+            used_resources.insert(
+                (row.get("service"), row.get("backend")),
+                Resources {
+                    cores: cores as u32,
+                    memory_mb: memory_mb as u64,
+                    scratch_mb: scratch_mb as u64,
+                },
+            );
         }
 
         // ===========================================================================================
         // Step 3: Filter jobs according to config limits
-        // jobs_by_user_service will hold the jobs to be processed
-        let mut jobs_by_user_service: HashMap<(i64, String), Vec<Job>> = HashMap::new();
-        // service_limits will cache the limits per service, so we don't have to look them up
-        // multiple times
-        let mut service_limits: HashMap<String, u16> = HashMap::new();
+        // Each job goes to the first of its candidate backends where the user has not reached
+        //  its quota, see `routing::candidates`, and that has enough free resources if it
+        //  advertised them. Jobs that do not fit anywhere stay queued.
+        let now = SystemTime::now();
+        let mut jobs = Vec::new();
         for row in rows {
-            let user_id: i64 = row.get("user_id");
-            let service: String = row.get("service");
-            // let status: String = row.get("status");
-            // info!(
-            //     "DB Row: user_id={}, service={}, status={}",
-            //     user_id, service, status
-            // );
-
-            // Check what is the limit for this service
-            let limit = *service_limits.entry(service.clone()).or_insert_with(|| {
-                self.config
-                    .services
-                    .get(&service)
-                    .map(|s| s.runs_per_user)
-                    .unwrap()
-            });
-            // let limit = 5;
-            let submitted = *submitted_counts
-                .get(&(user_id, service.clone()))
-                .unwrap_or(&0);
-            // info!(
-            //     "User: {}, Service: {}, Submitted: {}, Limit: {}",
-            //     user_id, service, submitted, limit
-            // );
-            // Check if this user/service combo can take more jobs
-            let key = (user_id, service.clone());
-            let user_queue = jobs_by_user_service.entry(key).or_default();
-            let remaining_slots = (limit - submitted) as usize;
-            // if submitted < limit, we can add more jobs, it has not yet reached the limit
-            // if user_queue.len() < remaining_slots, we can still add to this user's queue
-            // info!(
-            //     "User: {}, Service: {}, Current Queue Length: {}, Remaining Slots: {}",
-            //     user_id,
-            //     service,
-            //     user_queue.len(),
-            //     remaining_slots
-            // );
-            if submitted < limit && user_queue.len() < remaining_slots {
-                let status: String = row.get("status");
-                let loc: String = row.get("loc");
-                user_queue.push(Job {
-                    id: row.get("id"),
-                    user_id: user_id.try_into().unwrap(),
-                    service: service.clone(),
-                    status: Status::from_string(&status),
-                    loc: PathBuf::from(loc),
-                    dest_id: row.get("dest_id"),
-                });
+            let mut job = Job::from_row(&row);
+
+            if !self.config.services.contains_key(&job.service) {
+                warn!("Job {} has an unknown service {:?}", job.id, job.service);
+                continue;
+            }
+
+            for backend in routing::candidates(&job, self.config, &registrations, now) {
+                let Some(limit) = self.config.get_runs_per_user(&job.service, &backend) else {
+                    continue;
+                };
+                // Jobs selected in this pass count towards the quota as well
+                let key = (job.user_id, job.service.clone(), backend.clone());
+                if *submitted_counts.get(&key).unwrap_or(&0) >= limit {
+                    continue;
+                }
+
+                let resources_key = (job.service.clone(), backend.clone());
+                if let Some(advertised) = registrations.get(&resources_key).map(|r| &r.resources) {
+                    let used = used_resources.entry(resources_key).or_default();
+                    if !advertised.can_take(used, &job.resources) {
+                        continue;
+                    }
+                    *used = used.add(&job.resources);
+                }
+
+                *submitted_counts.entry(key).or_insert(0) += 1;
+                job.backend = backend;
+                jobs.push(job);
+                break;
             }
         }
 
-        // ===========================================================================================
-        // Step 4: Flatten the jobs_by_user_service into self.jobs
-        self.jobs = jobs_by_user_service.into_values().flatten().collect();
+        self.jobs = jobs;
         Ok(())
     }
 }
@@ -141,11 +140,17 @@ impl PayloadQueue<'_> {
                 let status: String = row.get("status");
                 let id: u32 = row.get("id");
                 let loc = Path::new(&self.config.data_path).join(id.to_string());
+                let max_runtime: Option<i64> = row.get("max_runtime");
 
                 let mut payload = Payload::new();
                 payload.set_id(id);
                 payload.set_status(Status::from_string(&status));
                 payload.set_loc(loc);
+                if let Some(max_runtime) = max_runtime {
+                    payload.set_max_runtime(max_runtime as u64);
+                }
+                let params: String = row.get("params");
+                payload.set_params(serde_json::from_str(&params).unwrap_or_default());
 
                 payload
             })
@@ -158,9 +163,12 @@ impl PayloadQueue<'_> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config::loader::{Config, Service};
+    use crate::config::loader::{Backend, BackendKind, Config, Service};
     use crate::models::job_dto::create_jobs_table;
     use crate::models::payload_dto::create_payload_table;
+    use crate::models::registration_dao::Registration;
+    use crate::models::registration_dto::create_registrations_table;
+    use crate::models::resources_dto::Advertisement;
 
     #[tokio::test]
     async fn test_load_limits_jobs_per_user_per_service() {
@@ -176,6 +184,7 @@ mod tests {
                 upload_url: "http://example.com/upload_a".to_string(),
                 download_url: "http://example.com/download_a".to_string(),
                 runs_per_user: 5,
+                ..Default::default()
             },
         );
         config.services.insert(
@@ -185,6 +194,7 @@ mod tests {
                 upload_url: "http://example.com/upload_b".to_string(),
                 download_url: "http://example.com/download_b".to_string(),
                 runs_per_user: 5,
+                ..Default::default()
             },
         );
         config.services.insert(
@@ -194,10 +204,12 @@ mod tests {
                 upload_url: "http://example.com/upload_c".to_string(),
                 download_url: "http://example.com/download_c".to_string(),
                 runs_per_user: 1,
+                ..Default::default()
             },
         );
 
         create_jobs_table(&pool).await.unwrap();
+        create_registrations_table(&pool).await.unwrap();
 
         // Insert 5 submitted jobs for user 1 - service A
         for _ in 0..5 {
@@ -296,6 +308,138 @@ mod tests {
         assert_eq!(jobs_for_user3.len(), expected_user3);
     }
 
+    #[tokio::test]
+    async fn test_load_spills_over_to_backends() {
+        let pool = SqlitePool::connect(":memory:")
+            .await
+            .unwrap_or_else(|e| panic!("Database connection failed: {e}"));
+        let mut config = Config::new().unwrap();
+        let backend = Backend {
+            name: "partner".to_string(),
+            kind: BackendKind::Orchestrator,
+            upload_url: "http://partner.example.com/upload".to_string(),
+            download_url: "http://partner.example.com/download".to_string(),
+            runs_per_user: 2,
+            ..Default::default()
+        };
+        config.services.insert(
+            "A".to_string(),
+            Service {
+                name: "A".to_string(),
+                upload_url: "http://example.com/upload_a".to_string(),
+                download_url: "http://example.com/download_a".to_string(),
+                runs_per_user: 1,
+                backends: HashMap::from([("partner".to_string(), backend)]),
+                ..Default::default()
+            },
+        );
+
+        create_jobs_table(&pool).await.unwrap();
+        create_registrations_table(&pool).await.unwrap();
+
+        // User 1 already has a job running on the default backend
+        sqlx::query(
+            "INSERT INTO jobs (user_id, service, status, loc) VALUES (1, 'A', 'submitted', 'loc')",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        for _ in 0..4 {
+            sqlx::query(
+                "INSERT INTO jobs (user_id, service, status, loc) VALUES (1, 'A', 'queued', 'loc')",
+            )
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+
+        let mut queue = Queue::new(&config);
+        queue.load(&pool).await.unwrap();
+
+        // The default backend is full, so only the 2 slots of the partner can be used
+        assert_eq!(queue.jobs.len(), 2);
+        assert!(queue.jobs.iter().all(|j| j.backend == "partner"));
+
+        // Free the default backend, now it takes one job and the partner the other two
+        sqlx::query("UPDATE jobs SET status = 'completed' WHERE status = 'submitted'")
+            .execute(&pool)
+            .await
+            .unwrap();
+        queue.load(&pool).await.unwrap();
+
+        assert_eq!(queue.jobs.len(), 3);
+        assert_eq!(
+            queue.jobs.iter().filter(|j| j.backend.is_empty()).count(),
+            1
+        );
+        assert_eq!(
+            queue.jobs.iter().filter(|j| j.backend == "partner").count(),
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn test_load_matches_resources() {
+        let pool = SqlitePool::connect(":memory:")
+            .await
+            .unwrap_or_else(|e| panic!("Database connection failed: {e}"));
+        let mut config = Config::new().unwrap();
+        config.services.insert(
+            "A".to_string(),
+            Service {
+                name: "A".to_string(),
+                runs_per_user: 10,
+                ..Default::default()
+            },
+        );
+
+        create_jobs_table(&pool).await.unwrap();
+        create_registrations_table(&pool).await.unwrap();
+
+        // The default backend has 8 cores and 16GB of memory
+        Registration::new(
+            "A",
+            "",
+            Advertisement {
+                resources: Resources {
+                    cores: 8,
+                    memory_mb: 16000,
+                    scratch_mb: 0,
+                },
+                ..Default::default()
+            },
+        )
+        .save(&pool)
+        .await
+        .unwrap();
+
+        // 4 cores are already in use
+        sqlx::query("INSERT INTO jobs (user_id, service, status, loc, cores) VALUES (1, 'A', 'submitted', 'loc', 4)")
+            .execute(&pool).await.unwrap();
+        // Does not fit, needs too much memory
+        sqlx::query("INSERT INTO jobs (user_id, service, status, loc, cores, memory_mb) VALUES (1, 'A', 'queued', 'loc', 1, 20000)")
+            .execute(&pool).await.unwrap();
+        // Only one of these two fits in the 4 free cores
+        for _ in 0..2 {
+            sqlx::query("INSERT INTO jobs (user_id, service, status, loc, cores) VALUES (2, 'A', 'queued', 'loc', 3)")
+                .execute(&pool).await.unwrap();
+        }
+        // Uses the default of one core, fits in the remaining one
+        sqlx::query(
+            "INSERT INTO jobs (user_id, service, status, loc) VALUES (3, 'A', 'queued', 'loc')",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let mut queue = Queue::new(&config);
+        queue.load(&pool).await.unwrap();
+
+        assert_eq!(queue.jobs.len(), 2);
+        assert_eq!(queue.jobs.iter().filter(|j| j.user_id == 2).count(), 1);
+        assert_eq!(queue.jobs.iter().filter(|j| j.user_id == 3).count(), 1);
+    }
+
     #[tokio::test]
     async fn test_list_per_status_payloads() {
         // Setup in-memory SQLite database
@@ -0,0 +1,25 @@
+use crate::models::resources_dto::{Advertisement, Resources};
+
+// What a backend advertised the last time it was contacted, see `services::tasks::registrar`
+#[derive(Debug, Clone)]
+pub struct Registration {
+    pub service: String,
+    pub backend: String,
+    pub resources: Resources,
+    pub datasets: Vec<String>,
+}
+
+impl Registration {
+    pub fn new(service: &str, backend: &str, advertisement: Advertisement) -> Registration {
+        Registration {
+            service: service.to_string(),
+            backend: backend.to_string(),
+            resources: advertisement.resources,
+            datasets: advertisement.datasets,
+        }
+    }
+
+    pub fn has_datasets(&self, datasets: &[String]) -> bool {
+        datasets.iter().all(|d| self.datasets.contains(d))
+    }
+}
@@ -0,0 +1,119 @@
+use crate::models::registration_dao::Registration;
+use crate::models::resources_dto::Resources;
+use sqlx::{Row, SqlitePool};
+use std::collections::HashMap;
+
+pub async fn create_registrations_table(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS registrations (
+            service TEXT NOT NULL,
+            backend TEXT NOT NULL,
+            cores INTEGER NOT NULL,
+            memory_mb INTEGER NOT NULL,
+            scratch_mb INTEGER NOT NULL,
+            datasets TEXT NOT NULL DEFAULT '',
+            updated_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+            PRIMARY KEY (service, backend)
+        )
+    "#,
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+impl Registration {
+    pub async fn save(&self, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "INSERT INTO registrations (service, backend, cores, memory_mb, scratch_mb, datasets) VALUES (?, ?, ?, ?, ?, ?)
+            ON CONFLICT (service, backend) DO UPDATE SET
+                cores = excluded.cores,
+                memory_mb = excluded.memory_mb,
+                scratch_mb = excluded.scratch_mb,
+                datasets = excluded.datasets,
+                updated_at = CURRENT_TIMESTAMP",
+        )
+        .bind(&self.service)
+        .bind(&self.backend)
+        .bind(self.resources.cores)
+        .bind(self.resources.memory_mb as i64)
+        .bind(self.resources.scratch_mb as i64)
+        .bind(self.datasets.join(","))
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+}
+
+// Registrations per service/backend
+pub async fn list_registrations(
+    pool: &SqlitePool,
+) -> Result<HashMap<(String, String), Registration>, sqlx::Error> {
+    let rows = sqlx::query("SELECT * FROM registrations")
+        .fetch_all(pool)
+        .await?;
+
+    Ok(rows
+        .iter()
+        .map(|row| {
+            let service: String = row.get("service");
+            let backend: String = row.get("backend");
+            let memory_mb: i64 = row.get("memory_mb");
+            let scratch_mb: i64 = row.get("scratch_mb");
+            let datasets: String = row.get("datasets");
+            let registration = Registration {
+                service: service.clone(),
+                backend: backend.clone(),
+                resources: Resources {
+                    cores: row.get("cores"),
+                    memory_mb: memory_mb as u64,
+                    scratch_mb: scratch_mb as u64,
+                },
+                datasets: datasets
+                    .split(',')
+                    .filter(|d| !d.is_empty())
+                    .map(String::from)
+                    .collect(),
+            };
+            ((service, backend), registration)
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::models::resources_dto::Advertisement;
+
+    #[tokio::test]
+    async fn test_save() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        create_registrations_table(&pool).await.unwrap();
+
+        let mut registration = Registration::new(
+            "A",
+            "",
+            Advertisement {
+                resources: Resources {
+                    cores: 4,
+                    ..Default::default()
+                },
+                datasets: vec!["maps".to_string()],
+            },
+        );
+        registration.save(&pool).await.unwrap();
+
+        // Registering again replaces the advertised resources
+        registration.resources.cores = 8;
+        registration.save(&pool).await.unwrap();
+
+        let registrations = list_registrations(&pool).await.unwrap();
+        assert_eq!(registrations.len(), 1);
+        let saved = &registrations[&("A".to_string(), "".to_string())];
+        assert_eq!(saved.resources.cores, 8);
+        assert_eq!(saved.datasets, vec!["maps"]);
+    }
+}
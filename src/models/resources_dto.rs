@@ -0,0 +1,118 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+// Resources requested by a job, or advertised by a client.
+//  When advertised, a value of 0 means that resource is not tracked
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct Resources {
+    pub cores: u32,
+    pub memory_mb: u64,
+    pub scratch_mb: u64,
+}
+
+// What a client advertises, its resources and the datasets it holds
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct Advertisement {
+    #[serde(flatten)]
+    pub resources: Resources,
+    #[serde(default)]
+    pub datasets: Vec<String>,
+}
+
+// Of the memory or scratch a job can request, in MB, so that what is in use on a backend
+//  can always be added up
+pub const MAX_REQUEST_MB: u64 = 1 << 32;
+
+impl Resources {
+    // Whether `request` fits in what is advertised here, given what is already `used`
+    pub fn can_take(&self, used: &Resources, request: &Resources) -> bool {
+        let fits = |advertised: u64, used: u64, request: u64| {
+            advertised == 0
+                || used
+                    .checked_add(request)
+                    .is_some_and(|sum| sum <= advertised)
+        };
+        fits(self.cores.into(), used.cores.into(), request.cores.into())
+            && fits(self.memory_mb, used.memory_mb, request.memory_mb)
+            && fits(self.scratch_mb, used.scratch_mb, request.scratch_mb)
+    }
+
+    pub fn add(&self, other: &Resources) -> Resources {
+        Resources {
+            cores: self.cores.saturating_add(other.cores),
+            memory_mb: self.memory_mb.saturating_add(other.memory_mb),
+            scratch_mb: self.scratch_mb.saturating_add(other.scratch_mb),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn test_can_take() {
+        let advertised = Resources {
+            cores: 8,
+            memory_mb: 16000,
+            scratch_mb: 0,
+        };
+        let used = Resources {
+            cores: 6,
+            memory_mb: 4000,
+            scratch_mb: 50000,
+        };
+
+        let request = Resources {
+            cores: 2,
+            memory_mb: 12000,
+            scratch_mb: 1000000,
+        };
+        // Scratch is not tracked, so any amount fits
+        assert!(advertised.can_take(&used, &request));
+
+        let request = Resources {
+            cores: 3,
+            ..Default::default()
+        };
+        assert!(!advertised.can_take(&used, &request));
+
+        let request = Resources {
+            cores: 1,
+            memory_mb: 12001,
+            ..Default::default()
+        };
+        assert!(!advertised.can_take(&used, &request));
+
+        // Too much to add up does not fit either
+        let request = Resources {
+            memory_mb: u64::MAX,
+            ..Default::default()
+        };
+        assert!(!advertised.can_take(&used, &request));
+    }
+
+    #[test]
+    fn test_add() {
+        let a = Resources {
+            cores: 1,
+            memory_mb: 2,
+            scratch_mb: 3,
+        };
+        assert_eq!(
+            a.add(&a),
+            Resources {
+                cores: 2,
+                memory_mb: 4,
+                scratch_mb: 6
+            }
+        );
+        let max = Resources {
+            cores: u32::MAX,
+            memory_mb: u64::MAX,
+            scratch_mb: u64::MAX,
+        };
+        assert_eq!(max.add(&a), max);
+    }
+}
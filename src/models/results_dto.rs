@@ -0,0 +1,413 @@
+use crate::models::archive_dto::ArchiveFormat;
+use crate::utils::io::{hex, is_compressed};
+use bytes::Bytes;
+use flate2::{write::GzEncoder, Compression};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use tokio::sync::{mpsc, oneshot};
+use tokio_stream::wrappers::ReceiverStream;
+use utoipa::ToSchema;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+// The results of a job as retrieved from its backend, in its state directory
+pub const RESULTS_ARCHIVE: &str = "download.zip";
+
+// What is in the results, built the first time they are listed
+pub const RESULTS_INDEX: &str = "files.json";
+
+// Bytes read from the archive at a time when a file is served
+const CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct ResultFile {
+    // Relative to the root of the results, ie `scores/best.csv`
+    pub path: String,
+    pub size: u64,
+    // Hex SHA-256 of the content
+    pub sha256: String,
+}
+
+fn open_archive(archive: &Path) -> io::Result<ZipArchive<File>> {
+    ZipArchive::new(File::open(archive)?).map_err(io::Error::other)
+}
+
+// Every file in `archive` with its checksum. Entries with a path that would end up
+//  outside of the results are left out
+pub fn index_archive(archive: &Path) -> io::Result<Vec<ResultFile>> {
+    let mut zip = open_archive(archive)?;
+    let mut files = Vec::new();
+    for i in 0..zip.len() {
+        let mut entry = zip.by_index(i).map_err(io::Error::other)?;
+        if entry.is_dir() || entry.enclosed_name().is_none() {
+            continue;
+        }
+        let mut hasher = Sha256::new();
+        io::copy(&mut entry, &mut hasher)?;
+        files.push(ResultFile {
+            path: entry.name().to_string(),
+            size: entry.size(),
+            sha256: hex(&hasher.finalize()),
+        });
+    }
+    Ok(files)
+}
+
+// The files in the results kept in `dir`, `NotFound` when there are none
+pub fn list_results(dir: &Path) -> io::Result<Vec<ResultFile>> {
+    let index = dir.join(RESULTS_INDEX);
+    if let Some(files) = fs::read(&index)
+        .ok()
+        .and_then(|content| serde_json::from_slice(&content).ok())
+    {
+        return Ok(files);
+    }
+
+    // Checksums take a while on large results, they are only computed once
+    let files = index_archive(&dir.join(RESULTS_ARCHIVE))?;
+    if let Ok(content) = serde_json::to_vec(&files) {
+        fs::write(&index, content).ok();
+    }
+    Ok(files)
+}
+
+// A single file of `archive`, with its size, read as it is sent. `NotFound` when it is
+//  not one of the files listed by `index_archive`
+pub async fn open_result(
+    archive: PathBuf,
+    path: String,
+) -> io::Result<(u64, ReceiverStream<io::Result<Bytes>>)> {
+    let (size_tx, size_rx) = oneshot::channel();
+    let (tx, rx) = mpsc::channel(4);
+    tokio::task::spawn_blocking(move || send_result(&archive, &path, size_tx, tx));
+
+    let size = size_rx
+        .await
+        .map_err(|_| io::Error::other("reading the results stopped"))??;
+    Ok((size, ReceiverStream::new(rx)))
+}
+
+fn send_result(
+    archive: &Path,
+    path: &str,
+    size_tx: oneshot::Sender<io::Result<u64>>,
+    tx: mpsc::Sender<io::Result<Bytes>>,
+) {
+    let mut zip = match open_archive(archive) {
+        Ok(zip) => zip,
+        Err(e) => {
+            size_tx.send(Err(e)).ok();
+            return;
+        }
+    };
+    let entry = zip
+        .index_for_name(path)
+        .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))
+        .and_then(|i| zip.by_index(i).map_err(io::Error::other));
+    let mut entry = match entry {
+        Ok(entry) if !entry.is_dir() && entry.enclosed_name().is_some() => entry,
+        Ok(_) => {
+            size_tx.send(Err(io::ErrorKind::NotFound.into())).ok();
+            return;
+        }
+        Err(e) => {
+            size_tx.send(Err(e)).ok();
+            return;
+        }
+    };
+    if size_tx.send(Ok(entry.size())).is_err() {
+        return;
+    }
+
+    let mut buffer = vec![0; CHUNK_SIZE];
+    loop {
+        let chunk = match entry.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => Ok(Bytes::copy_from_slice(&buffer[..n])),
+            Err(e) => Err(e),
+        };
+        let failed = chunk.is_err();
+        // Stop once nobody is listening anymore
+        if tx.blocking_send(chunk).is_err() || failed {
+            break;
+        }
+    }
+}
+
+// Sends what is written to it down a channel, in chunks, as the body of a response
+struct ChannelWriter {
+    tx: mpsc::Sender<io::Result<Bytes>>,
+    buffer: Vec<u8>,
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if self.buffer.len() >= CHUNK_SIZE {
+            self.flush()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let chunk = Bytes::from(std::mem::take(&mut self.buffer));
+        // Nobody is listening anymore, stop building the archive
+        self.tx
+            .blocking_send(Ok(chunk))
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))
+    }
+}
+
+// The results in `archive` packed as `format`, built while they are sent. `level` is the
+//  compression level of `zip`, `tar.gz` and `tar.zst`, the default of each when None
+pub fn repack(
+    archive: PathBuf,
+    format: ArchiveFormat,
+    level: Option<i32>,
+) -> ReceiverStream<io::Result<Bytes>> {
+    let (tx, rx) = mpsc::channel(4);
+    tokio::task::spawn_blocking(move || {
+        let writer = ChannelWriter {
+            tx: tx.clone(),
+            buffer: Vec::with_capacity(CHUNK_SIZE),
+        };
+        if let Err(e) = write_repacked(&archive, format, level, writer) {
+            // Cut the response short so it is not taken for a complete archive
+            tx.blocking_send(Err(e)).ok();
+        }
+    });
+    ReceiverStream::new(rx)
+}
+
+fn write_repacked<W: Write>(
+    archive: &Path,
+    format: ArchiveFormat,
+    level: Option<i32>,
+    out: W,
+) -> io::Result<()> {
+    let mut zip = open_archive(archive)?;
+    let mut out = match format {
+        ArchiveFormat::Zip => write_zip(&mut zip, level, out)?,
+        ArchiveFormat::Tar => write_tar(&mut zip, archive, out)?,
+        ArchiveFormat::TarGz => {
+            let level = level.unwrap_or(6).clamp(0, 9) as u32;
+            let gz = GzEncoder::new(out, Compression::new(level));
+            write_tar(&mut zip, archive, gz)?.finish()?
+        }
+        ArchiveFormat::TarZst => {
+            let level = level.unwrap_or(zstd::DEFAULT_COMPRESSION_LEVEL);
+            let zst = zstd::Encoder::new(out, level)?;
+            write_tar(&mut zip, archive, zst)?.finish()?
+        }
+    };
+    out.flush()
+}
+
+fn write_zip<W: Write>(zip: &mut ZipArchive<File>, level: Option<i32>, out: W) -> io::Result<W> {
+    let mut writer = ZipWriter::new_stream(out);
+    for i in 0..zip.len() {
+        let mut entry = zip.by_index(i).map_err(io::Error::other)?;
+        if entry.enclosed_name().is_none() {
+            continue;
+        }
+        let name = entry.name().to_string();
+        let mut options = SimpleFileOptions::default()
+            .unix_permissions(entry.unix_mode().unwrap_or(0o644))
+            .large_file(entry.size() >= u32::MAX as u64);
+        // Compressing again what is already compressed takes time for nothing
+        options = match is_compressed(&name) {
+            true => options.compression_method(CompressionMethod::Stored),
+            false => options
+                .compression_method(CompressionMethod::Deflated)
+                .compression_level(level.map(|l| l.clamp(0, 9) as i64)),
+        };
+        if entry.is_dir() {
+            writer
+                .add_directory(name, options)
+                .map_err(io::Error::other)?;
+            continue;
+        }
+        writer.start_file(name, options).map_err(io::Error::other)?;
+        io::copy(&mut entry, &mut writer)?;
+    }
+    Ok(writer.finish().map_err(io::Error::other)?.into_inner())
+}
+
+fn write_tar<W: Write>(zip: &mut ZipArchive<File>, archive: &Path, out: W) -> io::Result<W> {
+    // Files are dated from when the results were retrieved
+    let mtime = fs::metadata(archive)?
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+
+    let mut builder = tar::Builder::new(out);
+    for i in 0..zip.len() {
+        let mut entry = zip.by_index(i).map_err(io::Error::other)?;
+        if entry.enclosed_name().is_none() {
+            continue;
+        }
+        let name = entry.name().to_string();
+        let mut header = tar::Header::new_gnu();
+        header.set_mtime(mtime);
+        if entry.is_dir() {
+            header.set_entry_type(tar::EntryType::Directory);
+            header.set_mode(0o755);
+            header.set_size(0);
+            builder.append_data(&mut header, name, io::empty())?;
+        } else {
+            header.set_entry_type(tar::EntryType::Regular);
+            header.set_mode(entry.unix_mode().unwrap_or(0o644) & 0o7777);
+            header.set_size(entry.size());
+            builder.append_data(&mut header, name, &mut entry)?;
+        }
+    }
+    builder.into_inner()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::StreamExt;
+
+    fn write_archive(loc: &Path) {
+        let mut zip = ZipWriter::new(File::create(loc.join(RESULTS_ARCHIVE)).unwrap());
+        zip.add_directory("scores/", SimpleFileOptions::default())
+            .unwrap();
+        zip.start_file("scores/best.csv", SimpleFileOptions::default())
+            .unwrap();
+        zip.write_all(b"abc").unwrap();
+        zip.start_file("../escape.txt", SimpleFileOptions::default())
+            .unwrap();
+        zip.write_all(b"nope").unwrap();
+        zip.finish().unwrap();
+    }
+
+    #[test]
+    fn test_list_results() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        write_archive(temp_dir.path());
+
+        let files = list_results(temp_dir.path()).unwrap();
+        assert_eq!(
+            files,
+            vec![ResultFile {
+                path: "scores/best.csv".to_string(),
+                size: 3,
+                sha256: "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+                    .to_string(),
+            }]
+        );
+
+        // Listed from the index from now on
+        assert!(temp_dir.path().join(RESULTS_INDEX).exists());
+        fs::remove_file(temp_dir.path().join(RESULTS_ARCHIVE)).unwrap();
+        assert_eq!(list_results(temp_dir.path()).unwrap(), files);
+    }
+
+    #[test]
+    fn test_list_results_without_archive() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let result = list_results(temp_dir.path());
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+
+    #[tokio::test]
+    async fn test_open_result() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        write_archive(temp_dir.path());
+        let archive = temp_dir.path().join(RESULTS_ARCHIVE);
+
+        let (size, stream) = open_result(archive.clone(), "scores/best.csv".to_string())
+            .await
+            .unwrap();
+        assert_eq!(size, 3);
+        let chunks: Vec<_> = stream.collect().await;
+        let content: Vec<u8> = chunks
+            .into_iter()
+            .flat_map(|c| c.unwrap().to_vec())
+            .collect();
+        assert_eq!(content, b"abc");
+
+        for path in ["scores/", "../escape.txt", "missing.txt"] {
+            let result = open_result(archive.clone(), path.to_string()).await;
+            assert_eq!(
+                result.unwrap_err().kind(),
+                io::ErrorKind::NotFound,
+                "{path}"
+            );
+        }
+    }
+
+    async fn collect(stream: ReceiverStream<io::Result<Bytes>>) -> Vec<u8> {
+        let chunks: Vec<_> = stream.collect().await;
+        chunks
+            .into_iter()
+            .flat_map(|c| c.unwrap().to_vec())
+            .collect()
+    }
+
+    // Path and content of each file of a tar archive
+    fn untar<R: Read>(reader: R) -> Vec<(String, Vec<u8>)> {
+        let mut tar = tar::Archive::new(reader);
+        tar.entries()
+            .unwrap()
+            .map(|entry| {
+                let mut entry = entry.unwrap();
+                let path = entry.path().unwrap().display().to_string();
+                let mut content = Vec::new();
+                entry.read_to_end(&mut content).unwrap();
+                (path, content)
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_repack() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        write_archive(temp_dir.path());
+        let archive = temp_dir.path().join(RESULTS_ARCHIVE);
+
+        let expected = vec![
+            ("scores/".to_string(), vec![]),
+            ("scores/best.csv".to_string(), b"abc".to_vec()),
+        ];
+
+        let tar = collect(repack(archive.clone(), ArchiveFormat::Tar, None)).await;
+        assert_eq!(untar(&tar[..]), expected);
+
+        let gz = collect(repack(archive.clone(), ArchiveFormat::TarGz, Some(9))).await;
+        assert_eq!(untar(flate2::read::GzDecoder::new(&gz[..])), expected);
+
+        let zst = collect(repack(archive.clone(), ArchiveFormat::TarZst, None)).await;
+        assert_eq!(untar(zstd::Decoder::new(&zst[..]).unwrap()), expected);
+
+        let repacked = collect(repack(archive.clone(), ArchiveFormat::Zip, Some(1))).await;
+        let mut zip = ZipArchive::new(io::Cursor::new(repacked)).unwrap();
+        let mut content = String::new();
+        zip.by_name("scores/best.csv")
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content, "abc");
+        assert!(zip.by_name("../escape.txt").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_repack_without_archive() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let archive = temp_dir.path().join(RESULTS_ARCHIVE);
+
+        let chunks: Vec<_> = repack(archive, ArchiveFormat::Tar, None).collect().await;
+        assert_eq!(chunks.len(), 1);
+        assert!(chunks[0].is_err());
+    }
+}
@@ -13,6 +13,10 @@ pub enum Status {
     Unknown,
     Cleaned,
     Prepared,
+    Cancelled,
+    TimedOut,
+    // Created with `/uploads`, its inputs are still coming in chunks
+    Uploading,
 }
 
 impl fmt::Display for Status {
@@ -27,11 +31,32 @@ impl fmt::Display for Status {
             Status::Submitted => write!(f, "submitted"),
             Status::Unknown => write!(f, "unknown"),
             Status::Cleaned => write!(f, "cleaned"),
+            Status::Cancelled => write!(f, "cancelled"),
+            Status::TimedOut => write!(f, "timed_out"),
+            Status::Uploading => write!(f, "uploading"),
         }
     }
 }
 
 impl Status {
+    // Nothing happens to a job anymore once it is in one of these
+    pub fn is_finished(&self) -> bool {
+        matches!(
+            self,
+            Status::Completed
+                | Status::Failed
+                | Status::Cancelled
+                | Status::TimedOut
+                | Status::Cleaned
+                | Status::Unknown
+        )
+    }
+
+    // A job in one of these can run again from its inputs, see `Job::retry`
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, Status::Failed | Status::TimedOut | Status::Unknown)
+    }
+
     pub fn from_string(s: &str) -> Self {
         match s.to_lowercase().as_str() {
             "pending" => Status::Pending,
@@ -41,6 +66,10 @@ impl Status {
             "queued" => Status::Queued,
             "submitted" => Status::Submitted,
             "cleaned" => Status::Cleaned,
+            "prepared" => Status::Prepared,
+            "cancelled" => Status::Cancelled,
+            "timed_out" => Status::TimedOut,
+            "uploading" => Status::Uploading,
             _ => Status::Unknown,
         }
     }
@@ -0,0 +1,27 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use utoipa::ToSchema;
+
+// Ends an upload started with `/uploads`, the job is queued once the files check out
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct FinalizeUpload {
+    // Hex SHA-256 of every uploaded file, by its path in the job directory
+    pub files: BTreeMap<String, String>,
+    // Unpack the archives among the files, like `extract=true` on `/upload`
+    #[serde(default)]
+    pub extract: bool,
+}
+
+// Something wrong with the inputs of a job, see `services::validation`
+#[derive(Debug, Serialize, ToSchema, PartialEq)]
+pub struct InputError {
+    // The file or metadata field at fault, none for the inputs as a whole
+    pub input: Option<String>,
+    pub message: String,
+}
+
+// Body of the `400` of an upload whose inputs do not fit its service
+#[derive(Debug, Serialize, ToSchema)]
+pub struct InputErrors {
+    pub errors: Vec<InputError>,
+}
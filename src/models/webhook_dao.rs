@@ -0,0 +1,38 @@
+use crate::models::job_dao::Job;
+use crate::models::job_event_dao::JobEvent;
+use utoipa::ToSchema;
+
+// A notification on its way to one url, retried until it is delivered, see `services::webhooks`
+#[derive(Debug)]
+pub struct Webhook {
+    pub id: i64,
+    pub job_id: i32,
+    pub url: String,
+    // The json that is posted, a `Notification`
+    pub payload: String,
+    pub attempts: u32,
+    pub last_error: Option<String>,
+    pub delivered_at: Option<String>,
+}
+
+// What is posted once a job is finished
+#[derive(serde::Serialize, Debug, ToSchema)]
+pub struct Notification {
+    // `job.completed`, `job.failed`, `job.cancelled` or `job.cleaned`
+    pub event: String,
+    // Id of the status transition in `/jobs/{id}/events`, the same for every attempt
+    pub event_id: i64,
+    pub occurred_at: String,
+    pub job: Job,
+}
+
+impl Notification {
+    pub fn new(event: &JobEvent, job: Job) -> Notification {
+        Notification {
+            event: format!("job.{}", event.to_status),
+            event_id: event.id,
+            occurred_at: event.created_at.clone(),
+            job,
+        }
+    }
+}
@@ -0,0 +1,239 @@
+use crate::config::loader::Config;
+use crate::models::job_dao::Job;
+use crate::models::job_event_dto::list_unnotified_events;
+use crate::models::webhook_dao::{Notification, Webhook};
+use sqlx::sqlite::SqliteRow;
+use sqlx::{Row, SqlitePool};
+use std::time::Duration;
+
+pub async fn create_webhooks_table(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS webhooks (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            job_id INTEGER NOT NULL,
+            event_id INTEGER NOT NULL,
+            url TEXT NOT NULL,
+            payload TEXT NOT NULL,
+            attempts INTEGER NOT NULL DEFAULT 0,
+            next_attempt_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+            last_error TEXT,
+            delivered_at DATETIME,
+            created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+        )
+    "#,
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+// Turn the finished jobs into webhooks, one for the `callback_url` of the job and one for
+//  the webhook of its service. Returns how many were queued
+pub async fn queue_webhooks(config: &Config, pool: &SqlitePool) -> Result<usize, sqlx::Error> {
+    let mut queued = 0;
+
+    for event in list_unnotified_events(pool).await? {
+        let mut tx = pool.begin().await?;
+
+        let row = sqlx::query("SELECT * FROM jobs WHERE id = ?")
+            .bind(event.job_id)
+            .fetch_optional(&mut *tx)
+            .await?;
+
+        if let Some(row) = row {
+            let job = Job::from_row(&row);
+            let mut urls: Vec<String> = job.callback_url.iter().cloned().collect();
+            if let Some(url) = config.get_webhook_url(&job.service) {
+                if !urls.iter().any(|u| u == url) {
+                    urls.push(url.to_string());
+                }
+            }
+
+            let payload = serde_json::to_string(&Notification::new(&event, job))
+                .map_err(|e| sqlx::Error::Encode(Box::new(e)))?;
+            for url in urls {
+                sqlx::query(
+                    "INSERT INTO webhooks (job_id, event_id, url, payload) VALUES (?, ?, ?, ?)",
+                )
+                .bind(event.job_id)
+                .bind(event.id)
+                .bind(url)
+                .bind(&payload)
+                .execute(&mut *tx)
+                .await?;
+                queued += 1;
+            }
+        }
+
+        sqlx::query("UPDATE job_events SET notified_at = CURRENT_TIMESTAMP WHERE id = ?")
+            .bind(event.id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+    }
+
+    Ok(queued)
+}
+
+// Webhooks that are waiting for their next attempt, the ones that ran out of attempts are left
+pub async fn list_due_webhooks(
+    max_attempts: u32,
+    pool: &SqlitePool,
+) -> Result<Vec<Webhook>, sqlx::Error> {
+    let rows = sqlx::query(
+        "SELECT * FROM webhooks WHERE delivered_at IS NULL AND attempts < ?
+            AND next_attempt_at <= CURRENT_TIMESTAMP ORDER BY id LIMIT 100",
+    )
+    .bind(max_attempts)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows.iter().map(Webhook::from_row).collect())
+}
+
+impl Webhook {
+    pub fn from_row(row: &SqliteRow) -> Webhook {
+        Webhook {
+            id: row.get("id"),
+            job_id: row.get("job_id"),
+            url: row.get("url"),
+            payload: row.get("payload"),
+            attempts: row.get("attempts"),
+            last_error: row.get("last_error"),
+            delivered_at: row.get("delivered_at"),
+        }
+    }
+
+    pub async fn mark_delivered(&mut self, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+        let row = sqlx::query(
+            "UPDATE webhooks SET attempts = attempts + 1, delivered_at = CURRENT_TIMESTAMP WHERE id = ? RETURNING attempts, delivered_at",
+        )
+        .bind(self.id)
+        .fetch_one(pool)
+        .await?;
+
+        self.attempts = row.get("attempts");
+        self.delivered_at = row.get("delivered_at");
+        Ok(())
+    }
+
+    // Try again in `retry_in`
+    pub async fn mark_failed(
+        &mut self,
+        error: &str,
+        retry_in: Duration,
+        pool: &SqlitePool,
+    ) -> Result<(), sqlx::Error> {
+        let row = sqlx::query(
+            "UPDATE webhooks SET attempts = attempts + 1, last_error = ?, next_attempt_at = datetime('now', ?) WHERE id = ? RETURNING attempts",
+        )
+        .bind(error)
+        .bind(format!("+{} seconds", retry_in.as_secs()))
+        .bind(self.id)
+        .fetch_one(pool)
+        .await?;
+
+        self.attempts = row.get("attempts");
+        self.last_error = Some(error.to_string());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::config::loader::Service;
+    use crate::models::job_dto::create_jobs_table;
+    use crate::models::status_dto::Status;
+
+    async fn list_webhooks(job_id: i32, pool: &SqlitePool) -> Vec<Webhook> {
+        sqlx::query("SELECT * FROM webhooks WHERE job_id = ? ORDER BY id")
+            .bind(job_id)
+            .fetch_all(pool)
+            .await
+            .unwrap()
+            .iter()
+            .map(Webhook::from_row)
+            .collect()
+    }
+
+    async fn init_db() -> SqlitePool {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        create_jobs_table(&pool).await.unwrap();
+        pool
+    }
+
+    #[tokio::test]
+    async fn test_queue_webhooks() {
+        let pool = init_db().await;
+        let mut config = Config::default();
+        config.services.insert(
+            "A".to_string(),
+            Service {
+                name: "A".to_string(),
+                webhook_url: "http://portal/hook".to_string(),
+                ..Default::default()
+            },
+        );
+
+        let mut job = Job::new("");
+        job.set_service("A".to_string());
+        job.set_callback_url("http://user/hook".to_string());
+        job.add_to_db(&pool).await.unwrap();
+        job.update_status(Status::Queued, &pool).await.unwrap();
+        assert_eq!(queue_webhooks(&config, &pool).await.unwrap(), 0);
+
+        job.update_status(Status::Completed, &pool).await.unwrap();
+        assert_eq!(queue_webhooks(&config, &pool).await.unwrap(), 2);
+        // Each transition is announced once
+        assert_eq!(queue_webhooks(&config, &pool).await.unwrap(), 0);
+
+        let webhooks = list_webhooks(job.id, &pool).await;
+        let urls: Vec<&str> = webhooks.iter().map(|w| w.url.as_str()).collect();
+        assert_eq!(urls, vec!["http://user/hook", "http://portal/hook"]);
+        let payload: serde_json::Value = serde_json::from_str(&webhooks[0].payload).unwrap();
+        assert_eq!(payload["event"], "job.completed");
+        assert_eq!(payload["job"]["id"], job.id);
+        assert_eq!(payload["job"]["status"], "Completed");
+
+        // Cleaning up is announced as well
+        job.update_status(Status::Cleaned, &pool).await.unwrap();
+        assert_eq!(queue_webhooks(&config, &pool).await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_due_webhooks() {
+        let pool = init_db().await;
+        let config = Config::default();
+        let mut job = Job::new("");
+        job.set_callback_url("http://user/hook".to_string());
+        job.add_to_db(&pool).await.unwrap();
+        job.update_status(Status::Failed, &pool).await.unwrap();
+        queue_webhooks(&config, &pool).await.unwrap();
+
+        let mut due = list_due_webhooks(3, &pool).await.unwrap();
+        assert_eq!(due.len(), 1);
+
+        // Not due again before its retry
+        due[0]
+            .mark_failed("refused", Duration::from_secs(60), &pool)
+            .await
+            .unwrap();
+        assert_eq!(due[0].attempts, 1);
+        assert!(list_due_webhooks(3, &pool).await.unwrap().is_empty());
+
+        due[0]
+            .mark_failed("refused", Duration::ZERO, &pool)
+            .await
+            .unwrap();
+        assert_eq!(list_due_webhooks(3, &pool).await.unwrap().len(), 1);
+        // Out of attempts
+        assert!(list_due_webhooks(2, &pool).await.unwrap().is_empty());
+
+        due[0].mark_delivered(&pool).await.unwrap();
+        assert!(due[0].delivered_at.is_some());
+        assert!(list_due_webhooks(10, &pool).await.unwrap().is_empty());
+    }
+}
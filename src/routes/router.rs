@@ -1,16 +1,36 @@
 use crate::config::loader::Config;
-use crate::controllers::client::{retrieve, submit};
+use crate::controllers::client::{
+    cancel, logs as payload_logs, resources, retrieve, status, stream as payload_stream, submit,
+};
 use crate::controllers::health::__path_health;
 use crate::controllers::health::health;
+use crate::controllers::orchestrator::__path_cancel;
+use crate::controllers::orchestrator::__path_create_upload;
+use crate::controllers::orchestrator::__path_detail;
 use crate::controllers::orchestrator::__path_download;
+use crate::controllers::orchestrator::__path_events;
+use crate::controllers::orchestrator::__path_file;
+use crate::controllers::orchestrator::__path_files;
+use crate::controllers::orchestrator::__path_finalize_upload;
+use crate::controllers::orchestrator::__path_list;
+use crate::controllers::orchestrator::__path_logs;
+use crate::controllers::orchestrator::__path_requeue;
+use crate::controllers::orchestrator::__path_retry;
+use crate::controllers::orchestrator::__path_stream;
 use crate::controllers::orchestrator::__path_upload;
-use crate::controllers::orchestrator::{download, upload};
+use crate::controllers::orchestrator::__path_upload_chunk;
+use crate::controllers::orchestrator::__path_upload_offset;
+use crate::controllers::orchestrator::{
+    cancel as cancel_job, create_upload, detail, download, events, file, files, finalize_upload,
+    list, logs, requeue, retry, stream, upload, upload_chunk, upload_offset,
+};
 use crate::controllers::ping::ping;
 use crate::models::health_dto::Health;
 use crate::models::job_dao::Job;
+use crate::models::upload_dao::{FinalizeUpload, InputError, InputErrors};
 use axum::extract::DefaultBodyLimit;
 use axum::{
-    routing::{get, post},
+    routing::{delete, get, patch, post},
     Router,
 };
 use sqlx::SqlitePool;
@@ -31,14 +51,30 @@ pub struct AppState {
 #[openapi(
     paths(
         upload,
+        create_upload,
+        upload_chunk,
+        upload_offset,
+        finalize_upload,
         download,
+        list,
+        detail,
+        events,
+        logs,
+        stream,
+        files,
+        file,
+        retry,
+        cancel,
+        requeue,
         health
     ),
     components(
-        schemas(Job, Health)
+        schemas(Job, Health, FinalizeUpload, InputError, InputErrors)
     ),
     tags(
         (name = "files", description = "File management endpoints"),
+        (name = "jobs", description = "Job management endpoints"),
+        (name = "admin", description = "Operator endpoints"),
         (name = "health", description = "Health check endpoints")
     )
 )]
@@ -50,7 +86,22 @@ pub fn create_routes(pool: SqlitePool, config: Config) -> Router {
         .route("/", get(ping))
         .route("/health", get(health))
         .route("/upload", post(upload))
+        .route("/uploads", post(create_upload))
+        .route(
+            "/uploads/{id}/files/{*path}",
+            patch(upload_chunk).head(upload_offset),
+        )
+        .route("/uploads/{id}/finalize", post(finalize_upload))
         .route("/download/{id}", get(download))
+        .route("/jobs", get(list))
+        .route("/jobs/{id}", get(detail).delete(cancel_job))
+        .route("/jobs/{id}/events", get(events))
+        .route("/jobs/{id}/logs", get(logs))
+        .route("/jobs/{id}/stream", get(stream))
+        .route("/jobs/{id}/files", get(files))
+        .route("/jobs/{id}/files/{*path}", get(file))
+        .route("/jobs/{id}/retry", post(retry))
+        .route("/admin/requeue", post(requeue))
         .merge(SwaggerUi::new("/swagger").url("/api-docs/openapi.json", ApiDoc::openapi()))
         .with_state(state)
         .layer(
@@ -77,6 +128,11 @@ pub fn create_client_routes(pool: SqlitePool, config: Config) -> Router {
         .route("/", get(ping))
         .route("/submit", post(submit))
         .route("/retrieve/{id}", get(retrieve))
+        .route("/status/{id}", get(status))
+        .route("/payload/{id}", delete(cancel))
+        .route("/logs/{id}", get(payload_logs))
+        .route("/logs/{id}/stream", get(payload_stream))
+        .route("/resources", get(resources))
         .with_state(state)
         .layer(
             TraceLayer::new_for_http()
@@ -1,26 +1,79 @@
-use std::process::Command;
-
+use crate::models::failure_dto::{Failure, FailureKind, STDERR_TAIL_SIZE};
 use crate::models::job_dao::Job;
+use crate::models::logs_dto::{read_tail, Logs, STDERR_LOG, STDOUT_LOG};
 use crate::models::payload_dao::Payload;
+use crate::models::resources_dto::Advertisement;
 use crate::services::orchestrator::Endpoint;
-use crate::services::orchestrator::{DownloadError, UploadError};
+use crate::services::orchestrator::{
+    CancelError, DownloadError, LogsError, StatusError, StatusReport, StreamError, UploadError,
+};
+use crate::utils::io::STATE_DIR;
 use futures_util::StreamExt;
 use http::StatusCode;
 use reqwest::multipart::{Form, Part};
+use std::os::unix::process::ExitStatusExt;
+use std::time::Duration;
 use tokio::fs::File;
 use tokio::io::AsyncWriteExt;
+use tokio::process::{Child, Command};
+use tokio::sync::mpsc;
 use tokio_util::io::ReaderStream;
 use tracing::info;
 use walkdir::WalkDir;
 
 #[derive(Debug, thiserror::Error)]
 pub enum ClientError {
-    #[error("Execution error")]
-    Execution,
+    #[error("Execution error: {0}")]
+    Execution(std::io::Error),
     #[error("Script error")]
-    Script,
+    Script {
+        exit_code: Option<i32>,
+        signal: Option<i32>,
+        stderr_tail: String,
+    },
     #[error("No execution script found")]
     NoExecScript,
+    #[error("Script exceeded its max runtime of {max_runtime}s")]
+    Timeout {
+        max_runtime: u64,
+        stderr_tail: String,
+    },
+}
+
+impl ClientError {
+    // What is kept on the payload, and passed on to the server
+    pub fn to_failure(&self) -> Failure {
+        match self {
+            ClientError::Execution(_) => Failure {
+                kind: FailureKind::Execution,
+                exit_code: None,
+                signal: None,
+                stderr_tail: None,
+            },
+            ClientError::Script {
+                exit_code,
+                signal,
+                stderr_tail,
+            } => Failure {
+                kind: FailureKind::Script,
+                exit_code: *exit_code,
+                signal: *signal,
+                stderr_tail: Some(stderr_tail.clone()).filter(|s| !s.is_empty()),
+            },
+            ClientError::NoExecScript => Failure {
+                kind: FailureKind::NoScript,
+                exit_code: None,
+                signal: None,
+                stderr_tail: None,
+            },
+            ClientError::Timeout { stderr_tail, .. } => Failure {
+                kind: FailureKind::Timeout,
+                exit_code: None,
+                signal: None,
+                stderr_tail: Some(stderr_tail.clone()).filter(|s| !s.is_empty()),
+            },
+        }
+    }
 }
 
 pub struct Client;
@@ -28,59 +81,14 @@ pub struct Client;
 // Server side
 impl Endpoint for Client {
     async fn upload(&self, job: &Job, url: &str) -> Result<u32, UploadError> {
-        // Create multipart form
-        let mut form = Form::new();
-
-        // Walk the directory
-        let walkdir = WalkDir::new(&job.loc);
-        let entries: Vec<_> = walkdir
-            .into_iter()
-            // Filter out errors, this means permissions and etc
-            .filter_map(|e| e.ok())
-            .filter(|e| e.file_type().is_file())
-            .collect();
-
-        // Process files
-        for entry in entries {
-            let path = entry.path();
-
-            // Get metadata
-            let metadata = tokio::fs::metadata(path)
-                .await
-                .map_err(|e| UploadError::FileRead {
-                    path: path.display().to_string(),
-                    source: e,
-                })?;
-            let file_size = metadata.len();
-
-            // Open file but don't read it so it does not go into memory
-            let file = File::open(path).await.map_err(|e| UploadError::FileRead {
-                path: path.display().to_string(),
-                source: e,
-            })?;
-
-            // Convert absolute paths to relative paths to preserve directory structure
-            let relative_path = path
-                .strip_prefix(&job.loc)
-                .unwrap_or(path)
-                .to_string_lossy()
-                .to_string();
-
-            // Get filename
-            let filename = path
-                .file_name()
-                .and_then(|n| n.to_str())
-                .unwrap_or("file")
-                .to_string();
-
-            // Create stream
-            let stream = ReaderStream::new(file);
-            let body = reqwest::Body::wrap_stream(stream);
-
-            // Create the part with stream
-            let part = Part::stream_with_length(body, file_size).file_name(filename);
-
-            form = form.part(relative_path, part);
+        let mut form = build_form(job).await?;
+        // The client kills `run.sh` once it runs longer than this
+        if let Some(max_runtime) = job.max_runtime {
+            form = form.text("max_runtime", max_runtime.to_string());
+        }
+        // Written to `params.json` next to the inputs, and passed to `run.sh` in its environment
+        if !job.params.is_empty() {
+            form = form.text("params", serde_json::json!(job.params).to_string());
         }
 
         let client = reqwest::Client::new();
@@ -112,11 +120,48 @@ impl Endpoint for Client {
         }
     }
 
+    async fn status(&self, j: &Job, url: &str) -> Result<StatusReport, StatusError> {
+        let client = reqwest::Client::new();
+        // Append the job id to the url
+        let response = client
+            .get(format!("{url}/{0}", j.dest_id))
+            .send()
+            .await
+            .map_err(StatusError::RequestFailed)?;
+
+        let status = response.status();
+
+        match status {
+            StatusCode::OK => {
+                let body = response
+                    .text()
+                    .await
+                    .map_err(StatusError::ResponseReadFailed)?;
+                let payload: Payload =
+                    serde_json::from_str(&body).map_err(StatusError::DeserializationFailed)?;
+                Ok(StatusReport {
+                    status: payload.status,
+                    failure: payload.failure,
+                })
+            }
+            StatusCode::NOT_FOUND => Err(StatusError::JobNotFound),
+            _ => {
+                let body = response
+                    .text()
+                    .await
+                    .unwrap_or_else(|_| "Unable to read response body".to_string());
+                Err(StatusError::UnexpectedStatus { status, body })
+            }
+        }
+    }
+
     async fn download(&self, j: &Job, url: &str) -> Result<(), DownloadError> {
         let client = reqwest::Client::new();
         // Append the job id to the url
+        // Results are kept as zip, another orchestrator could default to something else
         let response = client
             .get(format!("{url}/{0}", j.dest_id))
+            .header(reqwest::header::ACCEPT, "application/zip")
             .send()
             .await
             .map_err(DownloadError::RequestFailed)?;
@@ -125,7 +170,13 @@ impl Endpoint for Client {
 
         match status {
             StatusCode::OK => {
-                let output_path = j.loc.join("download.zip");
+                let output_path = j.results_path();
+                tokio::fs::create_dir_all(j.state_dir())
+                    .await
+                    .map_err(|e| DownloadError::FileCreate {
+                        path: output_path.display().to_string(),
+                        source: e,
+                    })?;
                 let mut file =
                     File::create(&output_path)
                         .await
@@ -152,7 +203,9 @@ impl Endpoint for Client {
                 Ok(())
             }
             StatusCode::ACCEPTED => Err(DownloadError::JobNotReady),
-            StatusCode::NO_CONTENT => Err(DownloadError::JobFailedOrCleaned),
+            StatusCode::NO_CONTENT => Err(DownloadError::JobFailedOrCleaned(
+                Failure::from_headers(response.headers()),
+            )),
             StatusCode::NOT_FOUND => Err(DownloadError::JobNotFound),
             _ => {
                 let body = response
@@ -163,10 +216,214 @@ impl Endpoint for Client {
             }
         }
     }
+
+    async fn cancel(&self, j: &Job, url: &str) -> Result<(), CancelError> {
+        let client = reqwest::Client::new();
+        // Append the job id to the url
+        let response = client
+            .delete(format!("{url}/{0}", j.dest_id))
+            .send()
+            .await
+            .map_err(CancelError::RequestFailed)?;
+
+        let status = response.status();
+
+        match status {
+            StatusCode::OK => Ok(()),
+            StatusCode::NOT_FOUND => Err(CancelError::JobNotFound),
+            StatusCode::CONFLICT => Err(CancelError::NotCancellable),
+            _ => {
+                let body = response
+                    .text()
+                    .await
+                    .unwrap_or_else(|_| "Unable to read response body".to_string());
+                Err(CancelError::UnexpectedStatus { status, body })
+            }
+        }
+    }
+
+    async fn logs(&self, j: &Job, url: &str) -> Result<Logs, LogsError> {
+        // Append the job id to the url
+        fetch_logs(&format!("{url}/{0}", j.dest_id)).await
+    }
+
+    async fn stream(
+        &self,
+        j: &Job,
+        url: &str,
+        tx: mpsc::Sender<String>,
+    ) -> Result<(), StreamError> {
+        // Append the job id to the url
+        relay_stdout(&format!("{url}/{0}", j.dest_id), tx).await
+    }
+}
+
+// Build a multipart form with every file in the job directory, streamed from disk
+pub async fn build_form(job: &Job) -> Result<Form, UploadError> {
+    // Create multipart form
+    let mut form = Form::new();
+
+    // Walk the directory
+    let walkdir = WalkDir::new(&job.loc);
+    let entries: Vec<_> = walkdir
+        .into_iter()
+        // Only the inputs, not what was kept of an earlier run
+        .filter_entry(|e| e.depth() != 1 || e.file_name() != STATE_DIR)
+        // Filter out errors, this means permissions and etc
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .collect();
+
+    // Process files
+    for entry in entries {
+        let path = entry.path();
+
+        // Get metadata
+        let metadata = tokio::fs::metadata(path)
+            .await
+            .map_err(|e| UploadError::FileRead {
+                path: path.display().to_string(),
+                source: e,
+            })?;
+        let file_size = metadata.len();
+
+        // Open file but don't read it so it does not go into memory
+        let file = File::open(path).await.map_err(|e| UploadError::FileRead {
+            path: path.display().to_string(),
+            source: e,
+        })?;
+
+        // Convert absolute paths to relative paths to preserve directory structure
+        let relative_path = path
+            .strip_prefix(&job.loc)
+            .unwrap_or(path)
+            .to_string_lossy()
+            .to_string();
+
+        // Get filename
+        let filename = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("file")
+            .to_string();
+
+        // Create stream
+        let stream = ReaderStream::new(file);
+        let body = reqwest::Body::wrap_stream(stream);
+
+        // Create the part with stream
+        let part = Part::stream_with_length(body, file_size).file_name(filename);
+
+        form = form.part(relative_path, part);
+    }
+
+    Ok(form)
+}
+
+// Ask a client for the resources and datasets it advertises, at its `/resources`
+pub async fn fetch_resources(url: &str) -> Result<Advertisement, reqwest::Error> {
+    let client = reqwest::Client::new();
+    client
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<Advertisement>()
+        .await
+}
+
+// Get the logs of a job, both clients and partners answer with the same json
+pub async fn fetch_logs(url: &str) -> Result<Logs, LogsError> {
+    let client = reqwest::Client::new();
+    let response = client
+        .get(url)
+        .send()
+        .await
+        .map_err(LogsError::RequestFailed)?;
+
+    let status = response.status();
+
+    match status {
+        StatusCode::OK => {
+            let body = response
+                .text()
+                .await
+                .map_err(LogsError::ResponseReadFailed)?;
+            Ok(serde_json::from_str(&body).map_err(LogsError::DeserializationFailed)?)
+        }
+        StatusCode::NOT_FOUND => Err(LogsError::JobNotFound),
+        _ => {
+            let body = response
+                .text()
+                .await
+                .unwrap_or_else(|_| "Unable to read response body".to_string());
+            Err(LogsError::UnexpectedStatus { status, body })
+        }
+    }
+}
+
+// Follow the event stream of a job, both clients and partners send its output as
+//  `stdout` events. Stops early when nobody listens to `tx` anymore
+pub async fn relay_stdout(url: &str, tx: mpsc::Sender<String>) -> Result<(), StreamError> {
+    let client = reqwest::Client::new();
+    let response = client
+        .get(url)
+        .header(http::header::ACCEPT, "text/event-stream")
+        .send()
+        .await
+        .map_err(StreamError::RequestFailed)?;
+
+    let status = response.status();
+
+    match status {
+        StatusCode::OK => {}
+        StatusCode::NOT_FOUND => return Err(StreamError::JobNotFound),
+        _ => {
+            let body = response
+                .text()
+                .await
+                .unwrap_or_else(|_| "Unable to read response body".to_string());
+            return Err(StreamError::UnexpectedStatus { status, body });
+        }
+    }
+
+    let mut body = response.bytes_stream();
+    let mut buffer: Vec<u8> = Vec::new();
+    while let Some(chunk) = body.next().await {
+        buffer.extend_from_slice(&chunk?);
+        // Events are separated by an empty line
+        while let Some(end) = buffer.windows(2).position(|w| w == b"\n\n") {
+            let event: Vec<u8> = buffer.drain(..end + 2).collect();
+            if let Some(line) = stdout_data(&String::from_utf8_lossy(&event)) {
+                if tx.send(line).await.is_err() {
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// The data of a `stdout` event, nothing for any other event
+fn stdout_data(event: &str) -> Option<String> {
+    let mut name = None;
+    let mut data: Vec<&str> = Vec::new();
+    for line in event.lines() {
+        if let Some(value) = line.strip_prefix("event:") {
+            name = Some(value.trim_start());
+        } else if let Some(value) = line.strip_prefix("data:") {
+            data.push(value.strip_prefix(' ').unwrap_or(value));
+        }
+    }
+    (name == Some("stdout")).then(|| data.join("\n"))
 }
 
 // Client side
-pub fn execute_payload(payload: &Payload) -> Result<(), ClientError> {
+// Start the `run.sh` of a payload in its own process group, so `kill_payload` can stop
+//  it together with everything it spawned. Its output goes to the logs of the payload,
+//  and its parameters are in its environment
+pub fn spawn_payload(payload: &Payload) -> Result<Child, ClientError> {
     info!("{:?}", payload);
 
     // Expect the payload.loc to contain a `run.sh` script
@@ -177,17 +434,124 @@ pub fn execute_payload(payload: &Payload) -> Result<(), ClientError> {
         return Err(ClientError::NoExecScript);
     }
 
-    // Execute script and wait for it to finish
-    let exit_status = Command::new("bash")
+    // Apart from the inputs, which may have the same names, and left out of the results
+    std::fs::create_dir_all(payload.state_dir()).map_err(ClientError::Execution)?;
+    let stdout = std::fs::File::create(payload.state_dir().join(STDOUT_LOG))
+        .map_err(ClientError::Execution)?;
+    let stderr = std::fs::File::create(payload.state_dir().join(STDERR_LOG))
+        .map_err(ClientError::Execution)?;
+
+    Command::new("bash")
         .arg(run_script)
+        .envs(
+            payload
+                .params
+                .iter()
+                .map(|(key, value)| (param_env_name(key), value)),
+        )
         .current_dir(&payload.loc)
-        .status()
-        .map_err(|_| ClientError::Execution)?;
+        .stdout(stdout)
+        .stderr(stderr)
+        .process_group(0)
+        .spawn()
+        .map_err(ClientError::Execution)
+}
+
+// Variable a parameter is passed in, ie `run-name` goes in `JOB_PARAM_RUN_NAME`
+pub fn param_env_name(key: &str) -> String {
+    let name: String = key
+        .chars()
+        .map(|c| match c.is_ascii_alphanumeric() {
+            true => c.to_ascii_uppercase(),
+            false => '_',
+        })
+        .collect();
+    format!("JOB_PARAM_{name}")
+}
+
+// Wait for a script started by `spawn_payload` to finish. One that runs longer than the
+//  `max_runtime` of the payload is killed together with its process group
+pub async fn wait_payload(payload: &Payload, mut child: Child) -> Result<(), ClientError> {
+    let wait = match payload.max_runtime {
+        Some(max_runtime) => {
+            tokio::time::timeout(Duration::from_secs(max_runtime), child.wait()).await
+        }
+        None => Ok(child.wait().await),
+    };
+
+    let exit_status = match wait {
+        Ok(exit_status) => exit_status.map_err(ClientError::Execution)?,
+        Err(_) => {
+            if let Some(pid) = child.id() {
+                kill_payload(pid, KILL_GRACE).ok();
+            }
+            // Reap it, so it does not linger as a zombie. It is killed once the grace period
+            //  is over, past that it is left to the runtime rather than blocking the runner
+            let reaped = tokio::time::timeout(KILL_GRACE * 2, child.wait()).await;
+            if reaped.is_err() {
+                child.start_kill().ok();
+            }
+            let stderr_tail = read_tail(
+                &payload.state_dir().join(STDERR_LOG),
+                STDERR_TAIL_SIZE as u64,
+            );
+            return Err(ClientError::Timeout {
+                max_runtime: payload.max_runtime.unwrap_or_default(),
+                stderr_tail: stderr_tail.unwrap_or_default(),
+            });
+        }
+    };
 
     if !exit_status.success() {
-        return Err(ClientError::Script);
+        let stderr_tail = read_tail(
+            &payload.state_dir().join(STDERR_LOG),
+            STDERR_TAIL_SIZE as u64,
+        );
+        return Err(ClientError::Script {
+            exit_code: exit_status.code(),
+            signal: exit_status.signal(),
+            stderr_tail: stderr_tail.unwrap_or_default(),
+        });
+    }
+
+    Ok(())
+}
+
+// Time a payload has to stop after SIGTERM, before its process group is killed
+pub const KILL_GRACE: Duration = Duration::from_secs(10);
+
+// Send `signal` to the process group led by `pid`
+pub fn signal_group(pid: u32, signal: libc::c_int) -> std::io::Result<()> {
+    // A negative pid targets the whole group
+    let result = unsafe { libc::kill(-(pid as libc::pid_t), signal) };
+    if result != 0 {
+        return Err(std::io::Error::last_os_error());
     }
+    Ok(())
+}
+
+// Whether `pid`, a child of this process, has not been reaped yet, without reaping it.
+//  Until then neither its pid nor its process group can be taken by another process
+fn is_unreaped(pid: u32) -> bool {
+    let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+    let flags = libc::WEXITED | libc::WNOHANG | libc::WNOWAIT;
+    unsafe { libc::waitid(libc::P_PID, pid as libc::id_t, &mut info, flags) == 0 }
+}
 
+// Terminate the process group of a payload started by `spawn_payload`. Scripts that
+//  ignore or trap SIGTERM get SIGKILL once `grace` is over, unless `run.sh` was reaped
+//  in the meantime and its group id may belong to someone else by then
+pub fn kill_payload(pid: u32, grace: Duration) -> std::io::Result<()> {
+    if !is_unreaped(pid) {
+        return Err(std::io::Error::from_raw_os_error(libc::ESRCH));
+    }
+    signal_group(pid, libc::SIGTERM)?;
+    tokio::spawn(async move {
+        tokio::time::sleep(grace).await;
+        if is_unreaped(pid) {
+            signal_group(pid, libc::SIGKILL).ok();
+        }
+    });
     Ok(())
 }
 
@@ -195,9 +559,10 @@ pub fn execute_payload(payload: &Payload) -> Result<(), ClientError> {
 mod test {
 
     use super::*;
+    use std::collections::BTreeMap;
 
-    #[test]
-    fn test_execute_payload() {
+    #[tokio::test]
+    async fn test_execute_payload() {
         // Prepare a temporary payload
         let temp_dir = tempfile::tempdir().unwrap();
         let mut payload = Payload::new();
@@ -206,25 +571,79 @@ mod test {
         // Add a simple run.sh script
         std::fs::write(payload.loc.join("run.sh"), b"#!/bin/bash").unwrap();
 
-        let result = execute_payload(&payload);
+        let child = spawn_payload(&payload).unwrap();
+        let result = wait_payload(&payload, child).await;
 
         assert!(result.is_ok());
     }
 
+    #[tokio::test]
+    async fn test_execute_payload_logs() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut payload = Payload::new();
+        payload.set_loc(temp_dir.path().to_path_buf());
+        std::fs::write(
+            payload.loc.join("run.sh"),
+            b"#!/bin/bash\necho out\necho err >&2",
+        )
+        .unwrap();
+        // An input with the name of a log is left alone
+        std::fs::write(payload.loc.join(STDOUT_LOG), b"input").unwrap();
+
+        let child = spawn_payload(&payload).unwrap();
+        wait_payload(&payload, child).await.unwrap();
+
+        let logs = Logs::read(&payload.state_dir()).unwrap();
+        assert_eq!(logs.stdout, "out\n");
+        assert_eq!(logs.stderr, "err\n");
+        assert_eq!(
+            std::fs::read_to_string(payload.loc.join(STDOUT_LOG)).unwrap(),
+            "input"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_execute_payload_params() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut payload = Payload::new();
+        payload.set_loc(temp_dir.path().to_path_buf());
+        payload.set_params(BTreeMap::from([(
+            "run-name".to_string(),
+            "first run".to_string(),
+        )]));
+        std::fs::write(
+            payload.loc.join("run.sh"),
+            b"#!/bin/bash\necho \"$JOB_PARAM_RUN_NAME\"",
+        )
+        .unwrap();
+
+        let child = spawn_payload(&payload).unwrap();
+        wait_payload(&payload, child).await.unwrap();
+
+        let logs = Logs::read(&payload.state_dir()).unwrap();
+        assert_eq!(logs.stdout, "first run\n");
+    }
+
     #[test]
-    fn test_execute_payload_no_script() {
+    fn test_param_env_name() {
+        assert_eq!(param_env_name("project_id"), "JOB_PARAM_PROJECT_ID");
+        assert_eq!(param_env_name("run-name.v2"), "JOB_PARAM_RUN_NAME_V2");
+    }
+
+    #[tokio::test]
+    async fn test_execute_payload_no_script() {
         // Prepare a temporary payload
         let temp_dir = tempfile::tempdir().unwrap();
         let mut payload = Payload::new();
         payload.set_loc(temp_dir.path().to_path_buf());
 
-        let result = execute_payload(&payload);
+        let result = spawn_payload(&payload);
 
         assert!(matches!(result, Err(ClientError::NoExecScript)));
     }
 
-    #[test]
-    fn test_execute_payload_script_error() {
+    #[tokio::test]
+    async fn test_execute_payload_script_error() {
         // Prepare a temporary payload
         let temp_dir = tempfile::tempdir().unwrap();
         let mut payload = Payload::new();
@@ -233,8 +652,88 @@ mod test {
         // Add a run.sh script that fails
         std::fs::write(payload.loc.join("run.sh"), b"#!/bin/bash\nexit 1").unwrap();
 
-        let result = execute_payload(&payload);
+        let child = spawn_payload(&payload).unwrap();
+        let result = wait_payload(&payload, child).await;
+
+        assert!(matches!(result, Err(ClientError::Script { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_execute_payload_failure_details() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut payload = Payload::new();
+        payload.set_loc(temp_dir.path().to_path_buf());
+
+        // Only the end of a long stderr is kept
+        std::fs::write(
+            payload.loc.join("run.sh"),
+            b"#!/bin/bash\nhead -c 10000 /dev/zero | tr '\\0' x >&2\necho done >&2\nexit 3",
+        )
+        .unwrap();
+
+        let child = spawn_payload(&payload).unwrap();
+        let failure = wait_payload(&payload, child)
+            .await
+            .unwrap_err()
+            .to_failure();
+
+        assert_eq!(failure.kind, FailureKind::Script);
+        assert_eq!(failure.exit_code, Some(3));
+        assert_eq!(failure.signal, None);
+        let stderr_tail = failure.stderr_tail.unwrap();
+        assert_eq!(stderr_tail.len(), STDERR_TAIL_SIZE);
+        assert!(stderr_tail.ends_with("xdone\n"));
+    }
+
+    #[tokio::test]
+    async fn test_kill_payload() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut payload = Payload::new();
+        payload.set_loc(temp_dir.path().to_path_buf());
+
+        // The script starts a child of its own, which has to go as well
+        std::fs::write(payload.loc.join("run.sh"), b"#!/bin/bash\nsleep 30 &\nwait").unwrap();
+
+        let child = spawn_payload(&payload).unwrap();
+        kill_payload(child.id().unwrap(), KILL_GRACE).unwrap();
+        let result = wait_payload(&payload, child).await;
+
+        assert!(matches!(
+            result,
+            Err(ClientError::Script {
+                signal: Some(libc::SIGTERM),
+                ..
+            })
+        ));
+
+        // One that ignores SIGTERM is killed after the grace period
+        std::fs::write(
+            payload.loc.join("run.sh"),
+            b"#!/bin/bash\ntrap '' TERM\nsleep 30",
+        )
+        .unwrap();
+        let child = spawn_payload(&payload).unwrap();
+        // Give bash the time to set the trap
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        kill_payload(child.id().unwrap(), Duration::from_millis(200)).unwrap();
+        let result = tokio::time::timeout(Duration::from_secs(5), wait_payload(&payload, child))
+            .await
+            .unwrap();
 
-        assert!(matches!(result, Err(ClientError::Script)));
+        assert!(matches!(
+            result,
+            Err(ClientError::Script {
+                signal: Some(libc::SIGKILL),
+                ..
+            })
+        ));
+
+        // Once reaped its group is left alone, the id may have been taken again
+        std::fs::write(payload.loc.join("run.sh"), b"#!/bin/bash").unwrap();
+        let child = spawn_payload(&payload).unwrap();
+        let pid = child.id().unwrap();
+        wait_payload(&payload, child).await.unwrap();
+        let result = kill_payload(pid, KILL_GRACE);
+        assert_eq!(result.unwrap_err().raw_os_error(), Some(libc::ESRCH));
     }
 }
@@ -1,3 +1,7 @@
 pub mod client;
 pub mod orchestrator;
+pub mod partner;
+pub mod routing;
 pub mod tasks;
+pub mod validation;
+pub mod webhooks;
@@ -1,7 +1,13 @@
-use crate::config::loader::Config;
+use crate::config::loader::{BackendKind, Config};
+use crate::models::failure_dto::Failure;
 use crate::models::job_dao::Job;
+use crate::models::logs_dto::Logs;
+use crate::models::status_dto::Status;
+use crate::services::client::Client;
+use crate::services::partner::Partner;
 use anyhow::Result;
 use axum::http::StatusCode;
+use tokio::sync::mpsc;
 use tracing::info;
 
 #[derive(Debug, thiserror::Error)]
@@ -26,6 +32,28 @@ pub enum UploadError {
     },
 }
 
+// Connection problems and backends that are restarting or overloaded, worth another try
+fn is_transient_request(e: &reqwest::Error) -> bool {
+    e.is_connect() || e.is_timeout() || e.is_request() || e.is_body()
+}
+
+fn is_transient_status(status: StatusCode) -> bool {
+    status.is_server_error()
+        || status == StatusCode::TOO_MANY_REQUESTS
+        || status == StatusCode::REQUEST_TIMEOUT
+}
+
+impl UploadError {
+    pub fn is_transient(&self) -> bool {
+        match self {
+            UploadError::RequestFailed(e) => is_transient_request(e),
+            UploadError::ResponseReadFailed(_) => true,
+            UploadError::UnexpectedStatus { status, .. } => is_transient_status(*status),
+            _ => false,
+        }
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum DownloadError {
     #[error("Request failed: {0}")]
@@ -41,7 +69,7 @@ pub enum DownloadError {
     JobNotReady,
 
     #[error("Job failed or was cleaned")]
-    JobFailedOrCleaned,
+    JobFailedOrCleaned(Option<Failure>),
 
     #[error("Server returned error status {status}: {body}")]
     UnexpectedStatus { status: StatusCode, body: String },
@@ -67,13 +95,122 @@ pub enum DownloadError {
     InvalidService,
 }
 
+impl DownloadError {
+    pub fn is_transient(&self) -> bool {
+        match self {
+            DownloadError::RequestFailed(e) => is_transient_request(e),
+            DownloadError::ResponseReadFailed(_) => true,
+            DownloadError::UnexpectedStatus { status, .. } => is_transient_status(*status),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum StatusError {
+    #[error("Request failed: {0}")]
+    RequestFailed(#[from] reqwest::Error),
+
+    #[error("Failed to read response: {0}")]
+    ResponseReadFailed(reqwest::Error),
+
+    #[error("Failed to deserialize response: {0}")]
+    DeserializationFailed(#[from] serde_json::Error),
+
+    #[error("Job not found")]
+    JobNotFound,
+
+    #[error("Server returned error status {status}: {body}")]
+    UnexpectedStatus { status: StatusCode, body: String },
+
+    #[error("Not found")]
+    NotFound,
+
+    #[error("No status url configured")]
+    NotConfigured,
+}
+
+impl StatusError {
+    pub fn is_transient(&self) -> bool {
+        match self {
+            StatusError::RequestFailed(e) => is_transient_request(e),
+            StatusError::ResponseReadFailed(_) => true,
+            StatusError::UnexpectedStatus { status, .. } => is_transient_status(*status),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum CancelError {
+    #[error("Request failed: {0}")]
+    RequestFailed(#[from] reqwest::Error),
+
+    #[error("Job not found")]
+    JobNotFound,
+
+    #[error("Job can no longer be cancelled")]
+    NotCancellable,
+
+    #[error("Server returned error status {status}: {body}")]
+    UnexpectedStatus { status: StatusCode, body: String },
+
+    #[error("Not found")]
+    NotFound,
+
+    #[error("No cancel url configured")]
+    NotConfigured,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum LogsError {
+    #[error("Request failed: {0}")]
+    RequestFailed(#[from] reqwest::Error),
+
+    #[error("Failed to read response: {0}")]
+    ResponseReadFailed(reqwest::Error),
+
+    #[error("Failed to deserialize response: {0}")]
+    DeserializationFailed(#[from] serde_json::Error),
+
+    #[error("Job not found")]
+    JobNotFound,
+
+    #[error("Server returned error status {status}: {body}")]
+    UnexpectedStatus { status: StatusCode, body: String },
+
+    #[error("Not found")]
+    NotFound,
+
+    #[error("No logs url configured")]
+    NotConfigured,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum StreamError {
+    #[error("Request failed: {0}")]
+    RequestFailed(#[from] reqwest::Error),
+
+    #[error("Job not found")]
+    JobNotFound,
+
+    #[error("Server returned error status {status}: {body}")]
+    UnexpectedStatus { status: StatusCode, body: String },
+
+    #[error("Not found")]
+    NotFound,
+
+    #[error("No stream url configured")]
+    NotConfigured,
+}
+
 pub async fn send<T>(job: &Job, config: &Config, target: T) -> Result<u32, UploadError>
 where
     T: Endpoint,
 {
     info!("{:?}", job);
 
-    match config.get_upload_url(&job.service) {
+    match config.get_upload_url(&job.service, &job.backend) {
         Some(url) => Ok(target.upload(job, url).await?),
         None => Err(UploadError::InvalidService),
     }
@@ -87,20 +224,141 @@ where
         Err(DownloadError::NotFound)
     } else {
         // target.download(job).await
-        match config.get_download_url(&job.service) {
+        match config.get_download_url(&job.service, &job.backend) {
             Some(url) => Ok(target.download(job, url).await?),
             None => Err(DownloadError::InvalidService),
         }
     }
 }
 
-// pub async fn status() {}
+// What a backend tells about a job, with the details when it failed
+#[derive(Debug, PartialEq)]
+pub struct StatusReport {
+    pub status: Status,
+    pub failure: Option<Failure>,
+}
+
+pub async fn status<T>(job: &Job, config: &Config, target: T) -> Result<StatusReport, StatusError>
+where
+    T: Endpoint,
+{
+    if job.id == 0 {
+        Err(StatusError::NotFound)
+    } else {
+        match config.get_status_url(&job.service, &job.backend) {
+            Some(url) => Ok(target.status(job, url).await?),
+            None => Err(StatusError::NotConfigured),
+        }
+    }
+}
+
+pub async fn cancel<T>(job: &Job, config: &Config, target: T) -> Result<(), CancelError>
+where
+    T: Endpoint,
+{
+    if job.id == 0 {
+        Err(CancelError::NotFound)
+    } else {
+        match config.get_cancel_url(&job.service, &job.backend) {
+            Some(url) => Ok(target.cancel(job, url).await?),
+            None => Err(CancelError::NotConfigured),
+        }
+    }
+}
+
+pub async fn logs<T>(job: &Job, config: &Config, target: T) -> Result<Logs, LogsError>
+where
+    T: Endpoint,
+{
+    if job.id == 0 {
+        Err(LogsError::NotFound)
+    } else {
+        match config.get_logs_url(&job.service, &job.backend) {
+            Some(url) => Ok(target.logs(job, url).await?),
+            None => Err(LogsError::NotConfigured),
+        }
+    }
+}
+
+// Send the stdout lines of a running job to `tx`, until the backend closes the stream
+pub async fn stream<T>(
+    job: &Job,
+    config: &Config,
+    target: T,
+    tx: mpsc::Sender<String>,
+) -> Result<(), StreamError>
+where
+    T: Endpoint,
+{
+    if job.id == 0 {
+        Err(StreamError::NotFound)
+    } else {
+        match config.get_stream_url(&job.service, &job.backend) {
+            Some(url) => Ok(target.stream(job, url, tx).await?),
+            None => Err(StreamError::NotConfigured),
+        }
+    }
+}
 
 // These are traits that all Desinations need to have
 pub trait Endpoint {
     async fn upload(&self, j: &Job, url: &str) -> Result<u32, UploadError>;
-    // async fn status(&self, j: &Job) -> Result<reqwest::Response, reqwest::Error>;
+    async fn status(&self, j: &Job, url: &str) -> Result<StatusReport, StatusError>;
     async fn download(&self, j: &Job, url: &str) -> Result<(), DownloadError>;
+    async fn cancel(&self, j: &Job, url: &str) -> Result<(), CancelError>;
+    async fn logs(&self, j: &Job, url: &str) -> Result<Logs, LogsError>;
+    async fn stream(&self, j: &Job, url: &str, tx: mpsc::Sender<String>)
+        -> Result<(), StreamError>;
+}
+
+// Dispatch to the `Endpoint` that handles this kind of backend
+impl Endpoint for BackendKind {
+    async fn upload(&self, j: &Job, url: &str) -> Result<u32, UploadError> {
+        match self {
+            BackendKind::Client => Client.upload(j, url).await,
+            BackendKind::Orchestrator => Partner.upload(j, url).await,
+        }
+    }
+
+    async fn status(&self, j: &Job, url: &str) -> Result<StatusReport, StatusError> {
+        match self {
+            BackendKind::Client => Client.status(j, url).await,
+            BackendKind::Orchestrator => Partner.status(j, url).await,
+        }
+    }
+
+    async fn download(&self, j: &Job, url: &str) -> Result<(), DownloadError> {
+        match self {
+            BackendKind::Client => Client.download(j, url).await,
+            BackendKind::Orchestrator => Partner.download(j, url).await,
+        }
+    }
+
+    async fn cancel(&self, j: &Job, url: &str) -> Result<(), CancelError> {
+        match self {
+            BackendKind::Client => Client.cancel(j, url).await,
+            BackendKind::Orchestrator => Partner.cancel(j, url).await,
+        }
+    }
+
+    async fn logs(&self, j: &Job, url: &str) -> Result<Logs, LogsError> {
+        match self {
+            BackendKind::Client => Client.logs(j, url).await,
+            BackendKind::Orchestrator => Partner.logs(j, url).await,
+        }
+    }
+
+    async fn stream(
+        &self,
+        j: &Job,
+        url: &str,
+        tx: mpsc::Sender<String>,
+    ) -> Result<(), StreamError> {
+        match self {
+            BackendKind::Client => Client.stream(j, url, tx).await,
+            BackendKind::Orchestrator => Partner.stream(j, url, tx).await,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -114,9 +372,9 @@ mod test {
     use tempfile::TempDir;
     use uuid::Uuid;
 
-    // Mock `upload` and `download`,
+    // Mock the `Endpoint` methods,
     //  the tests below are testing just the logic of the
-    //  `send` and `retrieve` logic
+    //  `send`, `retrieve`, `status` and `cancel` logic
     struct OkMockDestination;
     struct ErrMockDestination;
 
@@ -124,18 +382,59 @@ mod test {
         async fn upload(&self, _j: &Job, _u: &str) -> Result<u32, UploadError> {
             Ok(0)
         }
+        async fn status(&self, _j: &Job, _u: &str) -> Result<StatusReport, StatusError> {
+            Ok(StatusReport {
+                status: Status::Completed,
+                failure: None,
+            })
+        }
         async fn download(&self, _j: &Job, _u: &str) -> Result<(), DownloadError> {
             Ok(())
         }
+        async fn cancel(&self, _j: &Job, _u: &str) -> Result<(), CancelError> {
+            Ok(())
+        }
+        async fn logs(&self, _j: &Job, _u: &str) -> Result<Logs, LogsError> {
+            Ok(Logs {
+                stdout: "done".to_string(),
+                stderr: String::new(),
+            })
+        }
+        async fn stream(
+            &self,
+            _j: &Job,
+            _u: &str,
+            tx: mpsc::Sender<String>,
+        ) -> Result<(), StreamError> {
+            tx.send("line".to_string()).await.ok();
+            Ok(())
+        }
     }
 
     impl Endpoint for ErrMockDestination {
         async fn upload(&self, _j: &Job, _u: &str) -> Result<u32, UploadError> {
             Err(UploadError::InvalidService)
         }
+        async fn status(&self, _j: &Job, _u: &str) -> Result<StatusReport, StatusError> {
+            Err(StatusError::JobNotFound)
+        }
         async fn download(&self, _j: &Job, _u: &str) -> Result<(), DownloadError> {
             Err(DownloadError::InvalidService)
         }
+        async fn cancel(&self, _j: &Job, _u: &str) -> Result<(), CancelError> {
+            Err(CancelError::JobNotFound)
+        }
+        async fn logs(&self, _j: &Job, _u: &str) -> Result<Logs, LogsError> {
+            Err(LogsError::JobNotFound)
+        }
+        async fn stream(
+            &self,
+            _j: &Job,
+            _u: &str,
+            _tx: mpsc::Sender<String>,
+        ) -> Result<(), StreamError> {
+            Err(StreamError::JobNotFound)
+        }
     }
 
     #[tokio::test]
@@ -153,6 +452,7 @@ mod test {
                 upload_url: "".to_string(),
                 download_url: "".to_string(),
                 runs_per_user: 5,
+                ..Default::default()
             },
         );
         let config = Config {
@@ -160,6 +460,7 @@ mod test {
             data_path: "".to_string(),
             db_path: "".to_string(),
             max_age: Duration::from_secs(1),
+            ..Default::default()
         };
         let target = OkMockDestination;
 
@@ -186,6 +487,7 @@ mod test {
                 upload_url: "".to_string(),
                 download_url: "".to_string(),
                 runs_per_user: 5,
+                ..Default::default()
             },
         );
         let config = Config {
@@ -193,6 +495,7 @@ mod test {
             data_path: "".to_string(),
             db_path: "".to_string(),
             max_age: Duration::from_secs(1),
+            ..Default::default()
         };
 
         let target = ErrMockDestination;
@@ -216,6 +519,7 @@ mod test {
                 upload_url: "".to_string(),
                 download_url: "".to_string(),
                 runs_per_user: 5,
+                ..Default::default()
             },
         );
         let config = Config {
@@ -223,6 +527,7 @@ mod test {
             data_path: "".to_string(),
             db_path: "".to_string(),
             max_age: Duration::from_secs(1),
+            ..Default::default()
         };
         let target = OkMockDestination;
 
@@ -246,6 +551,7 @@ mod test {
                 upload_url: "".to_string(),
                 download_url: "".to_string(),
                 runs_per_user: 5,
+                ..Default::default()
             },
         );
         let config = Config {
@@ -253,6 +559,7 @@ mod test {
             data_path: "".to_string(),
             db_path: "".to_string(),
             max_age: Duration::from_secs(1),
+            ..Default::default()
         };
         let target = ErrMockDestination;
 
@@ -273,6 +580,7 @@ mod test {
                 upload_url: "".to_string(),
                 download_url: "".to_string(),
                 runs_per_user: 5,
+                ..Default::default()
             },
         );
         let config = Config {
@@ -280,6 +588,7 @@ mod test {
             data_path: "".to_string(),
             db_path: "".to_string(),
             max_age: Duration::from_secs(1),
+            ..Default::default()
         };
         let target = ErrMockDestination;
 
@@ -287,4 +596,206 @@ mod test {
         assert!(result.is_err());
         assert!(matches!(result, Err(DownloadError::NotFound)))
     }
+
+    #[tokio::test]
+    async fn test_status() {
+        let service_name = Uuid::new_v4().to_string();
+        let tempdir = TempDir::new().unwrap();
+        let mut job = Job::new(tempdir.path().to_str().unwrap());
+        job.service = service_name.clone();
+        job.id = 42;
+
+        let mut services = HashMap::new();
+        services.insert(
+            service_name.clone(),
+            Service {
+                name: service_name,
+                status_url: "http://localhost/status".to_string(),
+                ..Default::default()
+            },
+        );
+        let config = Config {
+            services,
+            data_path: "".to_string(),
+            db_path: "".to_string(),
+            max_age: Duration::from_secs(1),
+            ..Default::default()
+        };
+
+        let result = status(&job, &config, OkMockDestination).await;
+        assert_eq!(result.unwrap().status, Status::Completed);
+
+        let result = status(&job, &config, ErrMockDestination).await;
+        assert!(matches!(result, Err(StatusError::JobNotFound)));
+    }
+
+    #[tokio::test]
+    async fn test_status_not_configured() {
+        let service_name = Uuid::new_v4().to_string();
+        let tempdir = TempDir::new().unwrap();
+        let mut job = Job::new(tempdir.path().to_str().unwrap());
+        job.service = service_name.clone();
+        job.id = 42;
+
+        let mut services = HashMap::new();
+        services.insert(
+            service_name.clone(),
+            Service {
+                name: service_name,
+                ..Default::default()
+            },
+        );
+        let config = Config {
+            services,
+            data_path: "".to_string(),
+            db_path: "".to_string(),
+            max_age: Duration::from_secs(1),
+            ..Default::default()
+        };
+
+        let result = status(&job, &config, OkMockDestination).await;
+        assert!(matches!(result, Err(StatusError::NotConfigured)));
+
+        let result = cancel(&job, &config, OkMockDestination).await;
+        assert!(matches!(result, Err(CancelError::NotConfigured)));
+    }
+
+    #[tokio::test]
+    async fn test_cancel() {
+        let service_name = Uuid::new_v4().to_string();
+        let tempdir = TempDir::new().unwrap();
+        let mut job = Job::new(tempdir.path().to_str().unwrap());
+        job.service = service_name.clone();
+        job.id = 42;
+
+        let mut services = HashMap::new();
+        services.insert(
+            service_name.clone(),
+            Service {
+                name: service_name,
+                cancel_url: "http://localhost/payload".to_string(),
+                ..Default::default()
+            },
+        );
+        let config = Config {
+            services,
+            data_path: "".to_string(),
+            db_path: "".to_string(),
+            max_age: Duration::from_secs(1),
+            ..Default::default()
+        };
+
+        let result = cancel(&job, &config, OkMockDestination).await;
+        assert!(result.is_ok());
+
+        let result = cancel(&job, &config, ErrMockDestination).await;
+        assert!(matches!(result, Err(CancelError::JobNotFound)));
+    }
+
+    #[tokio::test]
+    async fn test_logs() {
+        let service_name = Uuid::new_v4().to_string();
+        let tempdir = TempDir::new().unwrap();
+        let mut job = Job::new(tempdir.path().to_str().unwrap());
+        job.service = service_name.clone();
+
+        let mut services = HashMap::new();
+        services.insert(
+            service_name.clone(),
+            Service {
+                name: service_name,
+                logs_url: "http://localhost/logs".to_string(),
+                ..Default::default()
+            },
+        );
+        let config = Config {
+            services,
+            data_path: "".to_string(),
+            db_path: "".to_string(),
+            max_age: Duration::from_secs(1),
+            ..Default::default()
+        };
+
+        let result = logs(&job, &config, OkMockDestination).await;
+        assert!(matches!(result, Err(LogsError::NotFound)));
+
+        job.id = 42;
+        let result = logs(&job, &config, OkMockDestination).await;
+        assert_eq!(result.unwrap().stdout, "done");
+
+        let result = logs(&job, &config, ErrMockDestination).await;
+        assert!(matches!(result, Err(LogsError::JobNotFound)));
+
+        let config = Config::default();
+        let result = logs(&job, &config, OkMockDestination).await;
+        assert!(matches!(result, Err(LogsError::NotConfigured)));
+    }
+
+    #[tokio::test]
+    async fn test_stream() {
+        let service_name = Uuid::new_v4().to_string();
+        let tempdir = TempDir::new().unwrap();
+        let mut job = Job::new(tempdir.path().to_str().unwrap());
+        job.service = service_name.clone();
+        job.id = 42;
+
+        let mut services = HashMap::new();
+        services.insert(
+            service_name.clone(),
+            Service {
+                name: service_name,
+                stream_url: "http://localhost/logs".to_string(),
+                ..Default::default()
+            },
+        );
+        let config = Config {
+            services,
+            data_path: "".to_string(),
+            db_path: "".to_string(),
+            max_age: Duration::from_secs(1),
+            ..Default::default()
+        };
+
+        let (tx, mut rx) = mpsc::channel(10);
+        let result = stream(&job, &config, OkMockDestination, tx.clone()).await;
+        assert!(result.is_ok());
+        assert_eq!(rx.recv().await.as_deref(), Some("line"));
+
+        let result = stream(&job, &config, ErrMockDestination, tx.clone()).await;
+        assert!(matches!(result, Err(StreamError::JobNotFound)));
+
+        let result = stream(&job, &Config::default(), OkMockDestination, tx).await;
+        assert!(matches!(result, Err(StreamError::NotConfigured)));
+    }
+
+    #[test]
+    fn test_is_transient() {
+        let unexpected = |status| UploadError::UnexpectedStatus {
+            status,
+            body: String::new(),
+        };
+        assert!(unexpected(StatusCode::SERVICE_UNAVAILABLE).is_transient());
+        assert!(unexpected(StatusCode::TOO_MANY_REQUESTS).is_transient());
+        assert!(!unexpected(StatusCode::BAD_REQUEST).is_transient());
+        assert!(!UploadError::InvalidService.is_transient());
+
+        assert!(!StatusError::JobNotFound.is_transient());
+        assert!(StatusError::UnexpectedStatus {
+            status: StatusCode::BAD_GATEWAY,
+            body: String::new()
+        }
+        .is_transient());
+        assert!(!DownloadError::JobNotFound.is_transient());
+    }
+
+    #[tokio::test]
+    async fn test_connection_refused_is_transient() {
+        // Nothing listens on a port that was just freed
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        drop(listener);
+
+        let error = reqwest::get(&url).await.unwrap_err();
+        assert!(StatusError::RequestFailed(error).is_transient());
+    }
 }
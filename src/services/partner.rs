@@ -0,0 +1,350 @@
+use crate::models::failure_dto::Failure;
+use crate::models::job_dao::Job;
+use crate::models::logs_dto::Logs;
+use crate::models::status_dto::Status;
+use crate::services::client::{build_form, fetch_logs, relay_stdout, Client};
+use crate::services::orchestrator::Endpoint;
+use crate::services::orchestrator::{
+    CancelError, DownloadError, LogsError, StatusError, StatusReport, StreamError, UploadError,
+};
+use http::StatusCode;
+use serde::Deserialize;
+use tokio::sync::mpsc;
+
+// Another orchestrator server, jobs are forwarded to its `/upload` and the results
+//  collected from its `/download/{id}`
+pub struct Partner;
+
+// Only the id of the remote job is needed, everything else is ignored so partners
+//  running a different version keep working
+#[derive(Deserialize)]
+struct RemoteJob {
+    id: u32,
+}
+
+// The part of `/jobs/{id}` the status of the remote job is read from
+#[derive(Deserialize)]
+struct RemoteStatus {
+    status: Status,
+    failure: Option<Failure>,
+}
+
+impl Endpoint for Partner {
+    async fn upload(&self, job: &Job, url: &str) -> Result<u32, UploadError> {
+        // The remote server expects the same fields as our own `/upload`
+        let form = build_form(job)
+            .await?
+            .text("user_id", job.user_id.to_string())
+            .text("service", job.service.clone())
+            .text("labels", job.labels.join(","))
+            .text("datasets", job.datasets.join(","))
+            .text("cores", job.resources.cores.to_string())
+            .text("memory_mb", job.resources.memory_mb.to_string())
+            .text("scratch_mb", job.resources.scratch_mb.to_string());
+        let form = job.metadata.iter().fold(form, |form, (key, value)| {
+            form.text(key.clone(), value.clone())
+        });
+
+        let client = reqwest::Client::new();
+        let response = client
+            .post(url)
+            .multipart(form)
+            .send()
+            .await
+            .map_err(UploadError::ResponseReadFailed)?;
+
+        if response.status().is_success() {
+            let body = response
+                .text()
+                .await
+                .map_err(UploadError::ResponseReadFailed)?;
+
+            let remote_job: RemoteJob =
+                serde_json::from_str(&body).map_err(UploadError::DeserializationFailed)?;
+
+            Ok(remote_job.id)
+        } else {
+            let status = response.status();
+            let body = response
+                .text()
+                .await
+                .unwrap_or_else(|_| "Unable to read body".to_string());
+            Err(UploadError::UnexpectedStatus { status, body })
+        }
+    }
+
+    async fn status(&self, j: &Job, url: &str) -> Result<StatusReport, StatusError> {
+        // `/jobs/{id}` has the status of the remote job, and why it failed
+        let client = reqwest::Client::new();
+        let response = client
+            .get(format!("{url}/{0}", j.dest_id))
+            .send()
+            .await
+            .map_err(StatusError::RequestFailed)?;
+
+        let status = response.status();
+        match status {
+            StatusCode::OK => {
+                let body = response
+                    .text()
+                    .await
+                    .map_err(StatusError::ResponseReadFailed)?;
+                let remote_job: RemoteStatus =
+                    serde_json::from_str(&body).map_err(StatusError::DeserializationFailed)?;
+                let status = match remote_job.status {
+                    // Dispatched to a client of the partner, so running as far as we know
+                    Status::Submitted => Status::Processing,
+                    status => status,
+                };
+                Ok(StatusReport {
+                    status,
+                    failure: remote_job.failure,
+                })
+            }
+            StatusCode::NOT_FOUND => Err(StatusError::JobNotFound),
+            _ => {
+                let body = response
+                    .text()
+                    .await
+                    .unwrap_or_else(|_| "Unable to read response body".to_string());
+                Err(StatusError::UnexpectedStatus { status, body })
+            }
+        }
+    }
+
+    async fn download(&self, j: &Job, url: &str) -> Result<(), DownloadError> {
+        // `/download/{id}` answers with the same status codes as the client `/retrieve/{id}`
+        Client.download(j, url).await
+    }
+
+    async fn cancel(&self, j: &Job, url: &str) -> Result<(), CancelError> {
+        // `/jobs/{id}` answers with the same status codes as the client `/payload/{id}`
+        Client.cancel(j, url).await
+    }
+
+    async fn logs(&self, j: &Job, url: &str) -> Result<Logs, LogsError> {
+        // Partners serve them at `/jobs/{id}/logs`, with `url` pointing at `/jobs`
+        fetch_logs(&format!("{url}/{0}/logs", j.dest_id)).await
+    }
+
+    async fn stream(
+        &self,
+        j: &Job,
+        url: &str,
+        tx: mpsc::Sender<String>,
+    ) -> Result<(), StreamError> {
+        // Partners stream at `/jobs/{id}/stream`, their status events are left out
+        relay_stdout(&format!("{url}/{0}/stream", j.dest_id), tx).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_upload() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("POST", "/upload")
+            .match_body(mockito::Matcher::AllOf(vec![
+                mockito::Matcher::Regex("name=\"user_id\"".to_string()),
+                mockito::Matcher::Regex("name=\"service\"".to_string()),
+                mockito::Matcher::Regex("hello".to_string()),
+            ]))
+            .with_status(200)
+            .with_body(r#"{"id": 7, "user_id": 1, "service": "example", "status": "Queued"}"#)
+            .create_async()
+            .await;
+
+        let tempdir = TempDir::new().unwrap();
+        let mut job = Job::new(tempdir.path().to_str().unwrap());
+        std::fs::create_dir_all(&job.loc).unwrap();
+        std::fs::write(job.loc.join("input.txt"), b"hello").unwrap();
+        job.set_user_id(1);
+        job.set_service("example".to_string());
+        job.status = Status::Queued;
+
+        let result = Partner
+            .upload(&job, &format!("{}/upload", server.url()))
+            .await;
+
+        mock.assert_async().await;
+        assert_eq!(result.unwrap(), 7);
+    }
+
+    #[tokio::test]
+    async fn test_upload_rejected() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("POST", "/upload")
+            .with_status(400)
+            .with_body("Invalid service")
+            .create_async()
+            .await;
+
+        let tempdir = TempDir::new().unwrap();
+        let job = Job::new(tempdir.path().to_str().unwrap());
+        std::fs::create_dir_all(&job.loc).unwrap();
+
+        let result = Partner
+            .upload(&job, &format!("{}/upload", server.url()))
+            .await;
+
+        assert!(matches!(result, Err(UploadError::UnexpectedStatus { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_status() {
+        let mut server = mockito::Server::new_async().await;
+        let detail = |id: u32, status: &str, failure: &str| {
+            format!(
+                r#"{{"id": {id}, "status": "{status}", "failure": {failure}, "expires_at": null}}"#
+            )
+        };
+        for (id, status, failure) in [
+            (7, "Submitted", "null"),
+            (8, "Completed", "null"),
+            (9, "Cancelled", "null"),
+            (
+                10,
+                "Failed",
+                r#"{"kind": "script", "exit_code": 2, "signal": null, "stderr_tail": null}"#,
+            ),
+            (11, "Unknown", "null"),
+        ] {
+            server
+                .mock("GET", format!("/jobs/{id}").as_str())
+                .with_status(200)
+                .with_body(detail(id, status, failure))
+                .create_async()
+                .await;
+        }
+        server
+            .mock("GET", "/jobs/12")
+            .with_status(204)
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/jobs/13")
+            .with_status(404)
+            .create_async()
+            .await;
+
+        let mut job = Job::new("");
+        let url = format!("{}/jobs", server.url());
+
+        for (id, status) in [
+            (7, Status::Processing),
+            (8, Status::Completed),
+            (9, Status::Cancelled),
+            (11, Status::Unknown),
+        ] {
+            job.dest_id = id;
+            let result = Partner.status(&job, &url).await;
+            assert_eq!(result.unwrap().status, status);
+        }
+
+        job.dest_id = 10;
+        let report = Partner.status(&job, &url).await.unwrap();
+        assert_eq!(report.status, Status::Failed);
+        assert_eq!(report.failure.unwrap().exit_code, Some(2));
+
+        job.dest_id = 12;
+        let result = Partner.status(&job, &url).await;
+        assert!(matches!(
+            result,
+            Err(StatusError::UnexpectedStatus {
+                status: StatusCode::NO_CONTENT,
+                ..
+            })
+        ));
+
+        job.dest_id = 13;
+        let result = Partner.status(&job, &url).await;
+        assert!(matches!(result, Err(StatusError::JobNotFound)));
+    }
+
+    #[tokio::test]
+    async fn test_download() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/download/7")
+            .with_status(200)
+            .with_body("zipdata")
+            .create_async()
+            .await;
+
+        let tempdir = TempDir::new().unwrap();
+        let mut job = Job::new(tempdir.path().to_str().unwrap());
+        std::fs::create_dir_all(&job.loc).unwrap();
+        job.dest_id = 7;
+
+        let result = Partner
+            .download(&job, &format!("{}/download", server.url()))
+            .await;
+
+        assert!(result.is_ok());
+        assert_eq!(std::fs::read(job.results_path()).unwrap(), b"zipdata");
+    }
+
+    #[tokio::test]
+    async fn test_logs() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/jobs/7/logs")
+            .with_status(200)
+            .with_body(r#"{"stdout": "out", "stderr": "err"}"#)
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/jobs/8/logs")
+            .with_status(404)
+            .create_async()
+            .await;
+
+        let mut job = Job::new("");
+        job.dest_id = 7;
+        let url = format!("{}/jobs", server.url());
+
+        let logs = Partner.logs(&job, &url).await.unwrap();
+        assert_eq!(logs.stdout, "out");
+        assert_eq!(logs.stderr, "err");
+
+        job.dest_id = 8;
+        let result = Partner.logs(&job, &url).await;
+        assert!(matches!(result, Err(LogsError::JobNotFound)));
+    }
+
+    #[tokio::test]
+    async fn test_stream() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/jobs/7/stream")
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "event: status\ndata: {\"to_status\": \"Submitted\"}\n\n",
+                "event: stdout\ndata: step 1\n\n",
+                ":\n\n",
+                "event: stdout\ndata: step 2\n\n",
+            ))
+            .create_async()
+            .await;
+
+        let mut job = Job::new("");
+        job.dest_id = 7;
+        let (tx, mut rx) = mpsc::channel(10);
+
+        let result = Partner
+            .stream(&job, &format!("{}/jobs", server.url()), tx)
+            .await;
+
+        assert!(result.is_ok());
+        assert_eq!(rx.recv().await.as_deref(), Some("step 1"));
+        assert_eq!(rx.recv().await.as_deref(), Some("step 2"));
+        assert_eq!(rx.recv().await, None);
+    }
+}
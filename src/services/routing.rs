@@ -0,0 +1,262 @@
+use crate::config::loader::{parse_time_of_day, Config, DatasetPolicy, Rule};
+use crate::models::job_dao::Job;
+use crate::models::registration_dao::Registration;
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Backends a job can be dispatched to, in order of preference, an empty name being the
+//  default backend of the service.
+// The first routing rule that matches the job decides its backend, otherwise it goes to
+//  the default backend and spills over to the additional ones in name order.
+// Backends that registered the datasets the job needs are then moved first, or kept
+//  alone when the service requires them
+pub fn candidates(
+    job: &Job,
+    config: &Config,
+    registrations: &HashMap<(String, String), Registration>,
+    now: SystemTime,
+) -> Vec<String> {
+    let Some(service) = config.services.get(&job.service) else {
+        return Vec::new();
+    };
+
+    let candidates = by_rules(job, config, now);
+
+    let mut datasets = service.datasets.clone();
+    datasets.extend(job.datasets.iter().cloned());
+    if datasets.is_empty() {
+        return candidates;
+    }
+
+    let (holders, others): (Vec<_>, Vec<_>) = candidates.into_iter().partition(|backend| {
+        registrations
+            .get(&(job.service.clone(), backend.clone()))
+            .is_some_and(|r| r.has_datasets(&datasets))
+    });
+    match service.dataset_policy {
+        DatasetPolicy::Prefer => holders.into_iter().chain(others).collect(),
+        DatasetPolicy::Require => holders,
+    }
+}
+
+fn by_rules(job: &Job, config: &Config, now: SystemTime) -> Vec<String> {
+    let Some(service) = config.services.get(&job.service) else {
+        return Vec::new();
+    };
+
+    let minute_of_day = now
+        .duration_since(UNIX_EPOCH)
+        .map(|d| (d.as_secs() % 86400 / 60) as u32)
+        .unwrap_or(0);
+
+    for rule in &config.routing {
+        let backend = match rule.backend.as_str() {
+            "default" => "",
+            name => name,
+        };
+        // A rule can only send jobs to backends of their own service
+        if !backend.is_empty() && !service.backends.contains_key(backend) {
+            continue;
+        }
+        if matches(rule, job, minute_of_day) {
+            return vec![backend.to_string()];
+        }
+    }
+
+    let mut overflow: Vec<_> = service.backends.keys().cloned().collect();
+    overflow.sort();
+
+    let mut candidates = vec![String::new()];
+    candidates.extend(overflow);
+    candidates
+}
+
+pub fn matches(rule: &Rule, job: &Job, minute_of_day: u32) -> bool {
+    if rule.service.as_ref().is_some_and(|s| *s != job.service) {
+        return false;
+    }
+    if !rule.user_ids.is_empty() && !rule.user_ids.contains(&job.user_id) {
+        return false;
+    }
+    if rule.min_input_size.is_some_and(|min| job.input_size < min) {
+        return false;
+    }
+    if rule.max_input_size.is_some_and(|max| job.input_size > max) {
+        return false;
+    }
+    if !rule.labels.iter().all(|l| job.labels.contains(l)) {
+        return false;
+    }
+
+    let after = rule.after.as_deref().and_then(parse_time_of_day);
+    let before = rule.before.as_deref().and_then(parse_time_of_day);
+    match (after, before) {
+        // The window wraps around midnight, ie `22:00` to `06:00`
+        (Some(after), Some(before)) if after > before => {
+            minute_of_day >= after || minute_of_day < before
+        }
+        (Some(after), Some(before)) => minute_of_day >= after && minute_of_day < before,
+        (Some(after), None) => minute_of_day >= after,
+        (None, Some(before)) => minute_of_day < before,
+        (None, None) => true,
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::config::loader::{Backend, Service};
+    use crate::models::resources_dto::Advertisement;
+    use std::time::Duration;
+
+    fn config_with_backends(routing: Vec<Rule>) -> Config {
+        let backends = ["hpc", "gpu"]
+            .iter()
+            .map(|name| {
+                (
+                    name.to_string(),
+                    Backend {
+                        name: name.to_string(),
+                        ..Default::default()
+                    },
+                )
+            })
+            .collect();
+        Config {
+            services: HashMap::from([(
+                "A".to_string(),
+                Service {
+                    name: "A".to_string(),
+                    backends,
+                    ..Default::default()
+                },
+            )]),
+            routing,
+            ..Default::default()
+        }
+    }
+
+    fn at(hours: u64, minutes: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(hours * 3600 + minutes * 60)
+    }
+
+    #[test]
+    fn test_candidates_without_rules() {
+        let config = config_with_backends(Vec::new());
+        let mut job = Job::new("");
+        job.set_service("A".to_string());
+
+        let result = candidates(&job, &config, &HashMap::new(), at(12, 0));
+        assert_eq!(result, vec!["", "gpu", "hpc"]);
+    }
+
+    #[test]
+    fn test_candidates_first_matching_rule() {
+        let config = config_with_backends(vec![
+            Rule {
+                backend: "hpc".to_string(),
+                min_input_size: Some(100),
+                ..Default::default()
+            },
+            Rule {
+                backend: "gpu".to_string(),
+                labels: vec!["gpu".to_string()],
+                ..Default::default()
+            },
+            Rule {
+                backend: "default".to_string(),
+                user_ids: vec![7],
+                ..Default::default()
+            },
+        ]);
+        let mut job = Job::new("");
+        job.set_service("A".to_string());
+        job.labels = vec!["gpu".to_string()];
+
+        assert_eq!(
+            candidates(&job, &config, &HashMap::new(), at(12, 0)),
+            vec!["gpu"]
+        );
+
+        job.input_size = 1000;
+        assert_eq!(
+            candidates(&job, &config, &HashMap::new(), at(12, 0)),
+            vec!["hpc"]
+        );
+
+        job.input_size = 0;
+        job.labels.clear();
+        job.set_user_id(7);
+        assert_eq!(
+            candidates(&job, &config, &HashMap::new(), at(12, 0)),
+            vec![""]
+        );
+    }
+
+    #[test]
+    fn test_candidates_skip_unknown_backend() {
+        let config = config_with_backends(vec![Rule {
+            backend: "teaching".to_string(),
+            ..Default::default()
+        }]);
+        let mut job = Job::new("");
+        job.set_service("A".to_string());
+
+        let result = candidates(&job, &config, &HashMap::new(), at(12, 0));
+        assert_eq!(result, vec!["", "gpu", "hpc"]);
+    }
+
+    #[test]
+    fn test_candidates_dataset_affinity() {
+        let mut config = config_with_backends(Vec::new());
+        let registrations = HashMap::from([(
+            ("A".to_string(), "hpc".to_string()),
+            Registration::new(
+                "A",
+                "hpc",
+                Advertisement {
+                    datasets: vec!["genomes".to_string(), "maps".to_string()],
+                    ..Default::default()
+                },
+            ),
+        )]);
+        let mut job = Job::new("");
+        job.set_service("A".to_string());
+
+        // Without datasets the order is left as is
+        let result = candidates(&job, &config, &registrations, at(12, 0));
+        assert_eq!(result, vec!["", "gpu", "hpc"]);
+
+        job.set_datasets("genomes");
+        let result = candidates(&job, &config, &registrations, at(12, 0));
+        assert_eq!(result, vec!["hpc", "", "gpu"]);
+
+        let service = config.services.get_mut("A").unwrap();
+        service.dataset_policy = DatasetPolicy::Require;
+        let result = candidates(&job, &config, &registrations, at(12, 0));
+        assert_eq!(result, vec!["hpc"]);
+
+        // Datasets of the service are needed as well
+        let service = config.services.get_mut("A").unwrap();
+        service.datasets = vec!["weather".to_string()];
+        let result = candidates(&job, &config, &registrations, at(12, 0));
+        assert!(result.is_empty());
+    }
+
+    #[test]
+    fn test_matches_time_of_day() {
+        let rule = Rule {
+            backend: "hpc".to_string(),
+            after: Some("22:00".to_string()),
+            before: Some("06:00".to_string()),
+            ..Default::default()
+        };
+        let job = Job::new("");
+
+        assert!(matches(&rule, &job, 23 * 60));
+        assert!(matches(&rule, &job, 5 * 60 + 59));
+        assert!(!matches(&rule, &job, 6 * 60));
+        assert!(!matches(&rule, &job, 12 * 60));
+    }
+}
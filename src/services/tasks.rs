@@ -1,21 +1,55 @@
 use std::fs;
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 
-use crate::config::loader::Config;
+use crate::config::loader::{BackendKind, Config};
+use crate::models::failure_dto::FailureKind;
 use crate::models::job_dao::Job;
+use crate::models::job_dto::{list_abandoned_uploads, list_overdue_jobs};
+use crate::models::job_event_dao::Actor;
+use crate::models::payload_dao::Payload;
 use crate::models::queue_dao::PayloadQueue;
+use crate::models::registration_dao::Registration;
+use crate::models::webhook_dto::{list_due_webhooks, queue_webhooks};
 use crate::models::{queue_dao::Queue, status_dto::Status};
-use crate::services::client::{execute_payload, Client};
+use crate::services::client::{
+    fetch_resources, kill_payload, spawn_payload, wait_payload, ClientError, KILL_GRACE,
+};
 use crate::services::orchestrator;
+use crate::services::webhooks::{self, MAX_ATTEMPTS};
+use crate::utils::retry::backoff;
 use futures::stream::{self, StreamExt};
 use sqlx::SqlitePool;
 use tracing::info;
 use tracing::{debug, error, warn};
 
-use super::client::ClientError;
-use super::orchestrator::DownloadError;
+use super::orchestrator::{CancelError, DownloadError, LogsError, StatusError};
 
 pub async fn cleaner(pool: SqlitePool, config: Config) {
+    // Uploads that were given up on go by the time of their last chunk, writing a file
+    //  deep in the job directory does not change when the directory was modified
+    match list_abandoned_uploads(config.upload_ttl, &pool).await {
+        Ok(jobs) => {
+            for mut job in jobs {
+                job.actor = Actor::Cleaner;
+                // Unless it was finalized in the meantime
+                match job
+                    .transition_status(Status::Uploading, Status::Cleaned, &pool)
+                    .await
+                {
+                    Ok(true) => {
+                        info!("Upload of job {} abandoned", job.id);
+                        if let Err(e) = job.remove_from_disk() {
+                            error!("error: {:?} - could not remove {:?}", e, job.loc)
+                        }
+                    }
+                    Ok(false) => {}
+                    Err(e) => error!("Could not clean job {}: {:?}", job.id, e),
+                }
+            }
+        }
+        Err(e) => error!("Could not list abandoned uploads: {:?}", e),
+    }
+
     // List all directories inside the config.data_path
     let elements = match fs::read_dir(&config.data_path) {
         Ok(e) => e,
@@ -56,7 +90,17 @@ pub async fn cleaner(pool: SqlitePool, config: Config) {
                     );
                     let mut job = Job::new("");
                     match job.retrieve_by_loc(path.display().to_string(), &pool).await {
+                        // Still being uploaded, see above
+                        Ok(_) if job.status == Status::Uploading => {}
                         Ok(_) => {
+                            job.actor = Actor::Cleaner;
+                            // Still running somewhere, there is no point in letting it finish
+                            if job.status == Status::Submitted {
+                                let target = config.get_backend_kind(&job.service, &job.backend);
+                                if let Err(e) = orchestrator::cancel(&job, &config, target).await {
+                                    warn!("Could not cancel job {}: {:?}", job.id, e);
+                                }
+                            }
                             let _ = job.update_status(Status::Cleaned, &pool).await;
                             if let Err(e) = job.remove_from_disk() {
                                 error!("error: {:?} - could not remove {:?}", e, path)
@@ -72,6 +116,14 @@ pub async fn cleaner(pool: SqlitePool, config: Config) {
     futures::future::join_all(futures).await;
 }
 
+const FIRST_RETRY: Duration = Duration::from_secs(5);
+const MAX_RETRY: Duration = Duration::from_secs(300);
+
+// When to try again after another transient error, `None` once `max_attempts` are used up
+fn next_retry(attempts: u32, max_attempts: u32) -> Option<Duration> {
+    (attempts + 1 < max_attempts).then(|| backoff(FIRST_RETRY, MAX_RETRY, attempts + 1))
+}
+
 pub async fn sender(pool: SqlitePool, config: Config) {
     let mut queue = Queue::new(&config);
     if queue.load(&pool).await.is_ok() {
@@ -80,22 +132,94 @@ pub async fn sender(pool: SqlitePool, config: Config) {
             .jobs
             .into_iter()
             .map(|mut j| {
+                j.actor = Actor::Sender;
                 // info!("{:?}", j);
                 let pool_clone = pool.clone();
                 let config_clone = config.clone();
                 tokio::spawn(async move {
-                    j.update_status(Status::Processing, &pool_clone).await.ok();
+                    // Claim the job, unless it was cancelled in the meantime
+                    match j
+                        .transition_status(Status::Queued, Status::Processing, &pool_clone)
+                        .await
+                    {
+                        Ok(true) => {}
+                        Ok(false) => return,
+                        Err(e) => {
+                            error!("Failed to claim job {}: {:?}", j.id, e);
+                            return;
+                        }
+                    }
+                    // The backend was picked when loading the queue, keep track of it
+                    let backend = j.backend.clone();
+                    j.update_backend(&backend, &pool_clone).await.ok();
 
-                    match orchestrator::send(&j, &config_clone, Client).await {
+                    let target = config_clone.get_backend_kind(&j.service, &j.backend);
+                    match orchestrator::send(&j, &config_clone, target).await {
                         Ok(upload_id) => {
                             info!("submitting: {:?}", j);
-                            j.update_status(Status::Submitted, &pool_clone).await.ok();
                             j.update_dest_id(upload_id, &pool_clone).await.ok();
+                            if j.attempts > 0 {
+                                j.reset_attempts(&pool_clone).await.ok();
+                            }
+                            let submitted = j
+                                .transition_status(
+                                    Status::Processing,
+                                    Status::Submitted,
+                                    &pool_clone,
+                                )
+                                .await;
+                            // Cancelled while it was being uploaded, undo it on the backend
+                            if let Ok(false) = submitted {
+                                if let Err(e) =
+                                    orchestrator::cancel(&j, &config_clone, target).await
+                                {
+                                    warn!("Could not cancel job {}: {:?}", j.id, e);
+                                }
+                                remove_cancelled(&j);
+                            }
                             debug!("{:?}", j);
                         }
                         Err(e) => {
+                            let retry_in = e
+                                .is_transient()
+                                .then(|| next_retry(j.attempts, config_clone.max_attempts))
+                                .flatten();
+                            if let Some(retry_in) = retry_in {
+                                warn!(
+                                    "Upload of job {} failed, retrying in {:?}: {}",
+                                    j.id, retry_in, e
+                                );
+                                j.record_failed_attempt(retry_in, &pool_clone).await.ok();
+                                let reason = format!(
+                                    "Upload failed, attempt {} of {}: {e}",
+                                    j.attempts, config_clone.max_attempts
+                                );
+                                let requeued =
+                                    j.requeue(Status::Processing, &reason, &pool_clone).await;
+                                if let Ok(false) = requeued {
+                                    remove_cancelled(&j);
+                                }
+                                return;
+                            }
+
                             error!("Upload error: {:?}", e);
-                            j.update_status(Status::Failed, &pool_clone).await.ok();
+                            let reason = match e.is_transient() {
+                                true => {
+                                    format!("Upload failed after {} attempts: {e}", j.attempts + 1)
+                                }
+                                false => format!("Upload failed: {e}"),
+                            };
+                            let failed = j
+                                .update_failure(
+                                    Some(Status::Processing),
+                                    Status::Failed,
+                                    &reason,
+                                    &pool_clone,
+                                )
+                                .await;
+                            if let Ok(false) = failed {
+                                remove_cancelled(&j);
+                            }
                         }
                     }
                 })
@@ -106,6 +230,26 @@ pub async fn sender(pool: SqlitePool, config: Config) {
     }
 }
 
+// The files of a job cancelled while the sender had it are left for the sender to remove
+fn remove_cancelled(j: &Job) {
+    if let Err(e) = j.remove_from_disk() {
+        error!("error: {:?} - could not remove {:?}", e, j.loc)
+    }
+}
+
+// Keep the logs of a finished job next to its results, they are served even when it failed
+async fn collect_logs(j: &Job, config: &Config, target: BackendKind) {
+    match orchestrator::logs(j, config, target).await {
+        Ok(logs) => {
+            if let Err(e) = logs.write(&j.state_dir()) {
+                warn!("Could not store the logs of job {}: {:?}", j.id, e);
+            }
+        }
+        Err(LogsError::NotConfigured) => {}
+        Err(e) => warn!("Could not fetch the logs of job {}: {:?}", j.id, e),
+    }
+}
+
 pub async fn getter(pool: SqlitePool, config: Config) {
     let mut queue = Queue::new(&config);
 
@@ -116,11 +260,115 @@ pub async fn getter(pool: SqlitePool, config: Config) {
 
     let _: Vec<_> = stream::iter(queue.jobs)
         .map(|mut j| {
+            j.actor = Actor::Getter;
             let pool = pool.clone();
             let config = config.clone();
             async move {
-                match orchestrator::retrieve(&j, &config, Client).await {
+                let target = config.get_backend_kind(&j.service, &j.backend);
+
+                // Ask for the status first, the results are only downloaded once the job is done.
+                //  Without a status url, try to download them straight away
+                let report = orchestrator::status(&j, &config, target).await;
+                match report.map(|r| (r.status, r.failure)) {
+                    Ok((Status::Completed, _)) | Err(StatusError::NotConfigured) => {}
+                    Ok((status @ (Status::Failed | Status::TimedOut), failure)) => {
+                        warn!("Job {} {}", j.id, status);
+                        collect_logs(&j, &config, target).await;
+                        let reason = match &failure {
+                            Some(failure) => failure.to_string(),
+                            None => format!("{} on its backend", status),
+                        };
+                        if let Some(failure) = failure {
+                            j.update_failure_details(failure, &pool).await.ok();
+                        }
+                        j.update_failure(None, status, &reason, &pool).await.ok();
+                        return;
+                    }
+                    Ok((Status::Cancelled, _)) => {
+                        warn!("Job {} was cancelled", j.id);
+                        j.update_status(Status::Cancelled, &pool).await.ok();
+                        return;
+                    }
+                    Ok((Status::Cleaned, _)) => {
+                        warn!("Job {} was cleaned before its results were retrieved", j.id);
+                        j.update_failure(
+                            None,
+                            Status::Unknown,
+                            "Cleaned on its backend before retrieval",
+                            &pool,
+                        )
+                        .await
+                        .ok();
+                        return;
+                    }
+                    Ok((Status::Unknown, _)) => {
+                        warn!("Job {} was lost on its backend", j.id);
+                        j.update_failure(None, Status::Unknown, "Lost on its backend", &pool)
+                            .await
+                            .ok();
+                        return;
+                    }
+                    Ok((Status::Processing, _)) => {
+                        debug!("Job {} is running", j.id);
+                        j.update_started(&pool).await.ok();
+                        if j.attempts > 0 {
+                            j.reset_attempts(&pool).await.ok();
+                        }
+                        return;
+                    }
                     Ok(_) => {
+                        debug!("Job {} not ready yet", j.id);
+                        if j.attempts > 0 {
+                            j.reset_attempts(&pool).await.ok();
+                        }
+                        return;
+                    }
+                    Err(e) if e.is_transient() => {
+                        match next_retry(j.attempts, config.max_attempts) {
+                            Some(retry_in) => {
+                                warn!(
+                                    "Status check of job {} failed, retrying in {:?}: {}",
+                                    j.id, retry_in, e
+                                );
+                                j.record_failed_attempt(retry_in, &pool).await.ok();
+                            }
+                            None => {
+                                error!("Failed to get the status of job {}: {:?}", j.id, e);
+                                let reason = format!(
+                                    "Status check failed after {} attempts: {e}",
+                                    j.attempts + 1
+                                );
+                                j.update_failure(None, Status::Unknown, &reason, &pool)
+                                    .await
+                                    .ok();
+                            }
+                        }
+                        return;
+                    }
+                    Err(StatusError::JobNotFound) => {
+                        warn!("Job {} not found on server", j.id);
+                        j.update_failure(None, Status::Unknown, "Not found on its backend", &pool)
+                            .await
+                            .ok();
+                        return;
+                    }
+                    Err(e) => {
+                        error!("Failed to get the status of job {}: {:?}", j.id, e);
+                        j.update_failure(
+                            None,
+                            Status::Unknown,
+                            &format!("Status check failed: {e}"),
+                            &pool,
+                        )
+                        .await
+                        .ok();
+                        return;
+                    }
+                }
+
+                match orchestrator::retrieve(&j, &config, target).await {
+                    Ok(_) => {
+                        collect_logs(&j, &config, target).await;
                         if let Err(e) = j.update_status(Status::Completed, &pool).await {
                             error!("Failed to update job {} to Completed: {:?}", j.id, e);
                         } else {
@@ -130,13 +378,55 @@ pub async fn getter(pool: SqlitePool, config: Config) {
                     Err(DownloadError::JobNotReady) => {
                         debug!("Job {} not ready yet", j.id);
                     }
+                    // Clients tell why their payload failed when there is no status url
+                    Err(DownloadError::JobFailedOrCleaned(Some(failure))) => {
+                        let status = match failure.kind {
+                            FailureKind::Timeout => Status::TimedOut,
+                            _ => Status::Failed,
+                        };
+                        warn!("Job {} {}", j.id, status);
+                        collect_logs(&j, &config, target).await;
+                        let reason = failure.to_string();
+                        j.update_failure_details(failure, &pool).await.ok();
+                        j.update_failure(None, status, &reason, &pool).await.ok();
+                    }
+                    Err(e) if e.is_transient() => {
+                        match next_retry(j.attempts, config.max_attempts) {
+                            Some(retry_in) => {
+                                warn!(
+                                    "Download of job {} failed, retrying in {:?}: {}",
+                                    j.id, retry_in, e
+                                );
+                                j.record_failed_attempt(retry_in, &pool).await.ok();
+                            }
+                            None => {
+                                error!("Failed to download job {}: {:?}", j.id, e);
+                                let reason = format!(
+                                    "Download failed after {} attempts: {e}",
+                                    j.attempts + 1
+                                );
+                                j.update_failure(None, Status::Unknown, &reason, &pool)
+                                    .await
+                                    .ok();
+                            }
+                        }
+                    }
                     Err(DownloadError::JobNotFound) => {
                         warn!("Job {} not found on server", j.id);
-                        j.update_status(Status::Unknown, &pool).await.ok();
+                        j.update_failure(None, Status::Unknown, "Not found on its backend", &pool)
+                            .await
+                            .ok();
                     }
                     Err(e) => {
                         error!("Failed to download job {}: {:?}", j.id, e);
-                        j.update_status(Status::Unknown, &pool).await.ok();
+                        j.update_failure(
+                            None,
+                            Status::Unknown,
+                            &format!("Download failed: {e}"),
+                            &pool,
+                        )
+                        .await
+                        .ok();
                     }
                 }
             }
@@ -148,6 +438,108 @@ pub async fn getter(pool: SqlitePool, config: Config) {
         .await;
 }
 
+// Time out the jobs that have been submitted for longer than their `max_runtime`, plus a
+//  grace period for their backend to report it, and cancel them there
+pub async fn timekeeper(pool: SqlitePool, config: Config) {
+    let jobs = match list_overdue_jobs(config.runtime_grace, &pool).await {
+        Ok(jobs) => jobs,
+        Err(e) => {
+            error!("Failed to fetch overdue jobs: {:?}", e);
+            return;
+        }
+    };
+
+    for mut j in jobs {
+        j.actor = Actor::Timekeeper;
+        let reason = format!(
+            "Submitted for longer than its max runtime of {}s",
+            j.max_runtime.unwrap_or_default()
+        );
+        match j
+            .update_failure(Some(Status::Submitted), Status::TimedOut, &reason, &pool)
+            .await
+        {
+            Ok(true) => warn!("Job {} timed out", j.id),
+            // The getter got to it first
+            Ok(false) => continue,
+            Err(e) => {
+                error!("Failed to time out job {}: {:?}", j.id, e);
+                continue;
+            }
+        }
+
+        let target = config.get_backend_kind(&j.service, &j.backend);
+        match orchestrator::cancel(&j, &config, target).await {
+            Ok(_) | Err(CancelError::NotConfigured) => {}
+            Err(e) => warn!("Could not cancel job {} on its backend: {:?}", j.id, e),
+        }
+    }
+}
+
+// Tell the callback urls, and the webhooks of the services, about the jobs that finished
+pub async fn notifier(pool: SqlitePool, config: Config) {
+    if let Err(e) = queue_webhooks(&config, &pool).await {
+        error!("Failed to queue webhooks: {:?}", e);
+        return;
+    }
+
+    let due = match list_due_webhooks(MAX_ATTEMPTS, &pool).await {
+        Ok(due) => due,
+        Err(e) => {
+            error!("Failed to fetch webhooks: {:?}", e);
+            return;
+        }
+    };
+
+    let _: Vec<_> = stream::iter(due)
+        .map(|mut w| {
+            let pool = pool.clone();
+            let secret = config.webhook_secret.0.clone();
+            async move {
+                match webhooks::deliver(&w, &secret).await {
+                    Ok(_) => {
+                        debug!("Webhook of job {} delivered to {}", w.job_id, w.url);
+                        w.mark_delivered(&pool).await.ok();
+                    }
+                    Err(e) => {
+                        warn!("Webhook of job {} to {} failed: {}", w.job_id, w.url, e);
+                        let retry_in = webhooks::backoff(w.attempts + 1);
+                        w.mark_failed(&e.to_string(), retry_in, &pool).await.ok();
+                        if w.attempts >= MAX_ATTEMPTS {
+                            error!("Giving up on the webhook of job {} to {}", w.job_id, w.url);
+                        }
+                    }
+                }
+            }
+        })
+        .buffer_unordered(10)
+        .collect()
+        .await;
+}
+
+// Refresh the resources advertised by the backends that have a resources url
+pub async fn registrar(pool: SqlitePool, config: Config) {
+    for (name, service) in &config.services {
+        let backends = std::iter::once("").chain(service.backends.keys().map(|b| b.as_str()));
+        for backend in backends {
+            let Some(url) = config.get_resources_url(name, backend) else {
+                continue;
+            };
+            match fetch_resources(url).await {
+                Ok(advertisement) => {
+                    debug!("{} {:?} advertises {:?}", name, backend, advertisement);
+                    let registration = Registration::new(name, backend, advertisement);
+                    if let Err(e) = registration.save(&pool).await {
+                        error!("Failed to register {} {:?}: {:?}", name, backend, e);
+                    }
+                }
+                // Keep what was advertised before, the backend might be restarting
+                Err(e) => warn!("Could not register {} {:?}: {:?}", name, backend, e),
+            }
+        }
+    }
+}
+
 // Client side
 pub async fn runner(pool: SqlitePool, config: Config) {
     let mut queue = PayloadQueue::new(&config);
@@ -158,19 +550,53 @@ pub async fn runner(pool: SqlitePool, config: Config) {
             .map(|mut j| {
                 let pool_clone = pool.clone();
                 tokio::spawn(async move {
-                    match execute_payload(&j) {
-                        Ok(_) => {
-                            j.update_status(Status::Completed, &pool_clone).await.ok();
-                        }
-                        // TODO: Add specific error handler for each case
-                        Err(
-                            ClientError::Execution
-                            | ClientError::Script
-                            | ClientError::NoExecScript,
-                        ) => {
-                            j.update_status(Status::Failed, &pool_clone).await.ok();
+                    // Claim the payload, unless it was cancelled in the meantime
+                    match j
+                        .transition_status(Status::Prepared, Status::Processing, &pool_clone)
+                        .await
+                    {
+                        Ok(true) => {}
+                        Ok(false) => return,
+                        Err(e) => {
+                            error!("Failed to claim payload {}: {:?}", j.id, e);
+                            return;
                         }
                     }
+
+                    let result = match spawn_payload(&j) {
+                        Ok(child) => {
+                            if let Some(pid) = child.id() {
+                                j.update_pid(pid, &pool_clone).await.ok();
+                                // Cancelled before the pid was known, stop it right away
+                                let mut current = Payload::new();
+                                if current.retrieve_id(j.id, &pool_clone).await.is_ok()
+                                    && current.status == Status::Cancelled
+                                {
+                                    kill_payload(pid, KILL_GRACE).ok();
+                                }
+                            }
+                            wait_payload(&j, child).await
+                        }
+                        Err(e) => Err(e),
+                    };
+
+                    // A cancelled payload keeps its status
+                    let status = match result {
+                        Ok(_) => Status::Completed,
+                        Err(e @ ClientError::Timeout { .. }) => {
+                            warn!("Payload {} timed out: {}", j.id, e);
+                            j.update_failure(e.to_failure(), &pool_clone).await.ok();
+                            Status::TimedOut
+                        }
+                        Err(e) => {
+                            warn!("Payload {} failed: {}", j.id, e);
+                            j.update_failure(e.to_failure(), &pool_clone).await.ok();
+                            Status::Failed
+                        }
+                    };
+                    j.transition_status(Status::Processing, status, &pool_clone)
+                        .await
+                        .ok();
                 })
             })
             .collect::<Vec<_>>();
@@ -183,8 +609,10 @@ pub async fn runner(pool: SqlitePool, config: Config) {
 mod test {
 
     use super::*;
-    use crate::config::loader::{Config, Service};
-    use crate::models::payload_dao::Payload;
+    use crate::config::loader::{Config, Secret, Service};
+    use crate::models::job_event_dto::list_events;
+    use crate::models::logs_dto::Logs;
+    use crate::models::registration_dto::{create_registrations_table, list_registrations};
     use crate::models::{job_dao::Job, job_dto::create_jobs_table};
     use std::{path::Path, time::Duration};
     use tempfile::TempDir;
@@ -203,10 +631,14 @@ mod test {
                 upload_url: "http://example.com/upload_a".to_string(),
                 download_url: "http://example.com/download_a".to_string(),
                 runs_per_user: 5,
+                ..Default::default()
             },
         );
+        // Without retries, see `test_sender_retries` for those
+        config.max_attempts = 1;
 
         create_jobs_table(&pool).await.unwrap();
+        create_registrations_table(&pool).await.unwrap();
 
         // add a job
         let tempdir = TempDir::new().unwrap();
@@ -230,6 +662,181 @@ mod test {
         // TODO: Add mock the `send` function to test the match arm
     }
 
+    #[tokio::test]
+    async fn test_sender_retries() {
+        let pool = SqlitePool::connect(":memory:")
+            .await
+            .unwrap_or_else(|e| panic!("Database connection failed: {e}"));
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("POST", "/busy")
+            .with_status(503)
+            .create_async()
+            .await;
+        server
+            .mock("POST", "/rejected")
+            .with_status(400)
+            .create_async()
+            .await;
+
+        let mut config = Config::new().unwrap();
+        config.max_attempts = 2;
+        for name in ["busy", "rejected"] {
+            config.services.insert(
+                name.to_string(),
+                Service {
+                    name: name.to_string(),
+                    upload_url: format!("{}/{name}", server.url()),
+                    ..Default::default()
+                },
+            );
+        }
+
+        create_jobs_table(&pool).await.unwrap();
+        create_registrations_table(&pool).await.unwrap();
+
+        let tempdir = TempDir::new().unwrap();
+        let mut ids = Vec::new();
+        for name in ["busy", "rejected"] {
+            let mut job = Job::new(tempdir.path().to_str().unwrap());
+            fs::create_dir_all(&job.loc).unwrap();
+            job.set_service(name.to_string());
+            job.add_to_db(&pool).await.unwrap();
+            job.update_status(Status::Queued, &pool).await.unwrap();
+            ids.push(job.id);
+        }
+
+        sender(pool.clone(), config.clone()).await;
+
+        // A transient error puts the job back in the queue for later
+        let mut job = Job::new("");
+        job.retrieve_id(ids[0], &pool).await.unwrap();
+        assert_eq!(job.status, Status::Queued);
+        assert_eq!(job.attempts, 1);
+        assert!(job.next_attempt_at.is_some());
+        // A permanent one is final
+        job.retrieve_id(ids[1], &pool).await.unwrap();
+        assert_eq!(job.status, Status::Failed);
+        assert_eq!(job.attempts, 0);
+
+        // Not picked up again before its retry
+        sender(pool.clone(), config.clone()).await;
+        job.retrieve_id(ids[0], &pool).await.unwrap();
+        assert_eq!(job.attempts, 1);
+
+        // The last attempt fails the job
+        sqlx::query("UPDATE jobs SET next_attempt_at = NULL")
+            .execute(&pool)
+            .await
+            .unwrap();
+        sender(pool.clone(), config).await;
+        job.retrieve_id(ids[0], &pool).await.unwrap();
+        assert_eq!(job.status, Status::Failed);
+        assert_eq!(
+            job.failure_reason.as_deref(),
+            Some("Upload failed after 2 attempts: Server returned error status 503 Service Unavailable: ")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sender_cancelled() {
+        let pool = SqlitePool::connect(":memory:")
+            .await
+            .unwrap_or_else(|e| panic!("Database connection failed: {e}"));
+        let mut server = mockito::Server::new_async().await;
+        // Slow enough for the job to be cancelled during its upload
+        server
+            .mock("POST", "/slow")
+            .with_status(400)
+            .with_body_from_request(|_| {
+                std::thread::sleep(Duration::from_millis(500));
+                Vec::new()
+            })
+            .create_async()
+            .await;
+
+        let mut config = Config::new().unwrap();
+        config.services.insert(
+            "slow".to_string(),
+            Service {
+                name: "slow".to_string(),
+                upload_url: format!("{}/slow", server.url()),
+                ..Default::default()
+            },
+        );
+
+        create_jobs_table(&pool).await.unwrap();
+        create_registrations_table(&pool).await.unwrap();
+
+        let tempdir = TempDir::new().unwrap();
+        let mut job = Job::new(tempdir.path().to_str().unwrap());
+        fs::create_dir_all(&job.loc).unwrap();
+        job.set_service("slow".to_string());
+        job.add_to_db(&pool).await.unwrap();
+        job.update_status(Status::Queued, &pool).await.unwrap();
+
+        let sending = tokio::spawn(sender(pool.clone(), config));
+        sleep(Duration::from_millis(200)).await;
+        // What the cancel controller does with a job that is being uploaded
+        job.transition_status(Status::Processing, Status::Cancelled, &pool)
+            .await
+            .unwrap();
+        sending.await.unwrap();
+
+        job.retrieve_id(job.id, &pool).await.unwrap();
+        assert_eq!(job.status, Status::Cancelled);
+        assert!(!job.loc.exists());
+    }
+
+    #[tokio::test]
+    async fn test_getter_retries() {
+        let pool = SqlitePool::connect(":memory:")
+            .await
+            .unwrap_or_else(|e| panic!("Database connection failed: {e}"));
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/status/1")
+            .with_status(502)
+            .create_async()
+            .await;
+
+        let mut config = Config::new().unwrap();
+        config.max_attempts = 2;
+        config.services.insert(
+            "A".to_string(),
+            Service {
+                name: "A".to_string(),
+                status_url: format!("{}/status", server.url()),
+                ..Default::default()
+            },
+        );
+        create_jobs_table(&pool).await.unwrap();
+
+        let mut job = Job::new("");
+        job.set_service("A".to_string());
+        job.add_to_db(&pool).await.unwrap();
+        job.update_status(Status::Submitted, &pool).await.unwrap();
+        job.update_dest_id(1, &pool).await.unwrap();
+
+        // Still submitted, polled again after the backoff
+        getter(pool.clone(), config.clone()).await;
+        job.retrieve_id(job.id, &pool).await.unwrap();
+        assert_eq!(job.status, Status::Submitted);
+        assert_eq!(job.attempts, 1);
+
+        sqlx::query("UPDATE jobs SET next_attempt_at = NULL")
+            .execute(&pool)
+            .await
+            .unwrap();
+        getter(pool.clone(), config).await;
+        job.retrieve_id(job.id, &pool).await.unwrap();
+        assert_eq!(job.status, Status::Unknown);
+        assert!(job
+            .failure_reason
+            .unwrap()
+            .starts_with("Status check failed after 2 attempts"));
+    }
+
     #[tokio::test]
     async fn test_getter() {
         let pool = SqlitePool::connect(":memory:")
@@ -259,6 +866,214 @@ mod test {
         // TODO: Add mock the `retrieve` function to test the match arm
     }
 
+    #[tokio::test]
+    async fn test_getter_polls_status() {
+        let pool = SqlitePool::connect(":memory:")
+            .await
+            .unwrap_or_else(|e| panic!("Database connection failed: {e}"));
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/status/1")
+            .with_status(200)
+            .with_body(r#"{"id": 1, "input": {}, "status": "Processing", "loc": ""}"#)
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/status/2")
+            .with_status(200)
+            .with_body(r#"{"id": 2, "input": {}, "status": "Failed", "loc": "", "failure": {"kind": "script", "exit_code": 3, "signal": null, "stderr_tail": "oops"}}"#)
+            .create_async()
+            .await;
+        // Nothing is downloaded while the job is not done
+        let download = server
+            .mock("GET", mockito::Matcher::Regex("^/retrieve/.*".to_string()))
+            .expect(0)
+            .create_async()
+            .await;
+        // Only the logs of the finished job are collected
+        let logs = server
+            .mock("GET", mockito::Matcher::Regex("^/logs/.*".to_string()))
+            .with_status(200)
+            .with_body(r#"{"stdout": "", "stderr": "oops"}"#)
+            .expect(1)
+            .create_async()
+            .await;
+
+        let mut config = Config::new().unwrap();
+        config.services.insert(
+            "A".to_string(),
+            Service {
+                name: "A".to_string(),
+                download_url: format!("{}/retrieve", server.url()),
+                status_url: format!("{}/status", server.url()),
+                logs_url: format!("{}/logs", server.url()),
+                ..Default::default()
+            },
+        );
+
+        create_jobs_table(&pool).await.unwrap();
+
+        let tempdir = TempDir::new().unwrap();
+        let mut ids = Vec::new();
+        for dest_id in [1, 2] {
+            let mut job = Job::new(tempdir.path().to_str().unwrap());
+            fs::create_dir_all(&job.loc).unwrap();
+            job.set_service("A".to_string());
+            job.add_to_db(&pool).await.unwrap();
+            job.update_status(Status::Submitted, &pool).await.unwrap();
+            job.update_dest_id(dest_id, &pool).await.unwrap();
+            ids.push(job.id);
+        }
+
+        getter(pool.clone(), config).await;
+
+        let mut job = Job::new("");
+        job.retrieve_id(ids[0], &pool).await.unwrap();
+        assert_eq!(job.status, Status::Submitted);
+        job.retrieve_id(ids[1], &pool).await.unwrap();
+        assert_eq!(job.status, Status::Failed);
+        assert_eq!(
+            job.failure_reason.as_deref(),
+            Some("run.sh exited with code 3")
+        );
+        let failure = job.failure.clone().unwrap();
+        assert_eq!(failure.exit_code, Some(3));
+        assert_eq!(failure.stderr_tail.as_deref(), Some("oops"));
+        assert_eq!(Logs::read(&job.state_dir()).unwrap().stderr, "oops");
+        download.assert_async().await;
+        logs.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_timekeeper() {
+        let pool = SqlitePool::connect(":memory:")
+            .await
+            .unwrap_or_else(|e| panic!("Database connection failed: {e}"));
+        let mut config = Config::new().unwrap();
+        config.runtime_grace = Duration::from_secs(60);
+        create_jobs_table(&pool).await.unwrap();
+
+        let mut ids = Vec::new();
+        for _ in 0..3 {
+            let mut job = Job::new("");
+            job.set_service("A".to_string());
+            job.set_max_runtime(Some(Duration::from_secs(60)));
+            job.add_to_db(&pool).await.unwrap();
+            job.update_status(Status::Submitted, &pool).await.unwrap();
+            ids.push(job.id);
+        }
+        // Only the first one has been on its backend for longer than the limit and grace
+        sqlx::query("UPDATE jobs SET dispatched_at = datetime('now', '-10 minutes') WHERE id = ?")
+            .bind(ids[0])
+            .execute(&pool)
+            .await
+            .unwrap();
+        // The last one waited in the queue of its backend, it only started a minute ago
+        sqlx::query("UPDATE jobs SET dispatched_at = datetime('now', '-10 minutes'), started_at = datetime('now', '-1 minutes') WHERE id = ?")
+            .bind(ids[2])
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        timekeeper(pool.clone(), config).await;
+
+        let mut job = Job::new("");
+        job.retrieve_id(ids[0], &pool).await.unwrap();
+        assert_eq!(job.status, Status::TimedOut);
+        assert_eq!(
+            job.failure_reason.as_deref(),
+            Some("Submitted for longer than its max runtime of 60s")
+        );
+        assert!(job.timestamps.finished_at.is_some());
+        let events = list_events(ids[0], &pool).await.unwrap();
+        assert_eq!(events.last().unwrap().actor, Actor::Timekeeper);
+
+        job.retrieve_id(ids[1], &pool).await.unwrap();
+        assert_eq!(job.status, Status::Submitted);
+        job.retrieve_id(ids[2], &pool).await.unwrap();
+        assert_eq!(job.status, Status::Submitted);
+    }
+
+    #[tokio::test]
+    async fn test_notifier() {
+        let pool = SqlitePool::connect(":memory:")
+            .await
+            .unwrap_or_else(|e| panic!("Database connection failed: {e}"));
+        let mut server = mockito::Server::new_async().await;
+        // The first attempt fails, the retry only comes after the backoff
+        let hook = server
+            .mock("POST", "/hook")
+            .match_header(
+                "x-webhook-signature",
+                mockito::Matcher::Regex("^sha256=".to_string()),
+            )
+            .match_body(mockito::Matcher::PartialJsonString(
+                r#"{"event": "job.failed"}"#.to_string(),
+            ))
+            .with_status(503)
+            .expect(1)
+            .create_async()
+            .await;
+
+        let mut config = Config::new().unwrap();
+        config.webhook_secret = Secret("secret".to_string());
+        create_jobs_table(&pool).await.unwrap();
+
+        let mut job = Job::new("");
+        job.set_callback_url(format!("{}/hook", server.url()));
+        job.add_to_db(&pool).await.unwrap();
+        job.update_status(Status::Failed, &pool).await.unwrap();
+
+        notifier(pool.clone(), config.clone()).await;
+        notifier(pool.clone(), config).await;
+        hook.assert_async().await;
+
+        let (attempts, last_error): (u32, String) =
+            sqlx::query_as("SELECT attempts, last_error FROM webhooks WHERE job_id = ?")
+                .bind(job.id)
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert_eq!(attempts, 1);
+        assert!(last_error.contains("503"));
+    }
+
+    #[tokio::test]
+    async fn test_registrar() {
+        let pool = SqlitePool::connect(":memory:")
+            .await
+            .unwrap_or_else(|e| panic!("Database connection failed: {e}"));
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/resources")
+            .with_status(200)
+            .with_body(
+                r#"{"cores": 12, "memory_mb": 32000, "scratch_mb": 0, "datasets": ["maps"]}"#,
+            )
+            .create_async()
+            .await;
+
+        let mut config = Config::new().unwrap();
+        config.services.insert(
+            "A".to_string(),
+            Service {
+                name: "A".to_string(),
+                resources_url: format!("{}/resources", server.url()),
+                ..Default::default()
+            },
+        );
+
+        create_registrations_table(&pool).await.unwrap();
+
+        registrar(pool.clone(), config).await;
+
+        let registrations = list_registrations(&pool).await.unwrap();
+        let registration = &registrations[&("A".to_string(), "".to_string())];
+        assert_eq!(registration.resources.cores, 12);
+        assert_eq!(registration.resources.memory_mb, 32000);
+        assert_eq!(registration.datasets, vec!["maps"]);
+    }
+
     #[tokio::test]
     async fn test_cleaner() {
         let pool = SqlitePool::connect(":memory:")
@@ -284,7 +1099,7 @@ mod test {
 
         assert!(Path::new(&job.loc).exists());
 
-        cleaner(pool.clone(), config).await;
+        cleaner(pool.clone(), config.clone()).await;
 
         assert!(!Path::new(&job.loc).exists());
 
@@ -292,6 +1107,28 @@ mod test {
         let _ = _job.retrieve_id(job.id, &pool).await;
 
         assert_eq!(_job.status, Status::Cleaned);
+
+        // Uploads go by the time of their last chunk instead
+        let mut uploading = Job::new(tempdir.path().to_str().unwrap());
+        fs::create_dir_all(&uploading.loc).unwrap();
+        uploading.add_to_db(&pool).await.unwrap();
+        uploading
+            .update_status(Status::Uploading, &pool)
+            .await
+            .unwrap();
+        uploading.update_last_chunk(&pool).await.unwrap();
+
+        config.upload_ttl = Duration::from_secs(3600);
+        cleaner(pool.clone(), config.clone()).await;
+        assert!(Path::new(&uploading.loc).exists());
+        uploading.retrieve_id(uploading.id, &pool).await.unwrap();
+        assert_eq!(uploading.status, Status::Uploading);
+
+        config.upload_ttl = Duration::ZERO;
+        cleaner(pool.clone(), config).await;
+        assert!(!Path::new(&uploading.loc).exists());
+        uploading.retrieve_id(uploading.id, &pool).await.unwrap();
+        assert_eq!(uploading.status, Status::Cleaned);
     }
 
     #[tokio::test]
@@ -385,4 +1222,48 @@ mod test {
 
         assert_eq!(_payload.status, Status::Failed);
     }
+
+    #[tokio::test]
+    async fn test_runner_timeout() {
+        let pool = crate::datasource::db::init_payload_db().await;
+        let mut config = Config::new().unwrap();
+        let tempdir = TempDir::new().unwrap();
+        config.data_path = tempdir.path().to_str().unwrap().to_string();
+
+        let mut payload = Payload::new();
+        payload.set_max_runtime(1);
+        payload
+            .add_to_db(&pool)
+            .await
+            .expect("Failed to add payload to DB");
+        payload.add_input(
+            "run.sh".to_string(),
+            b"#!/bin/bash
+sleep 30
+"
+            .to_vec(),
+        );
+        payload
+            .prepare(&config.data_path)
+            .expect("Failed to prepare payload");
+        payload
+            .update_status(Status::Prepared, &pool)
+            .await
+            .expect("Failed to update payload status");
+
+        // Killed long before `sleep` is done
+        tokio::time::timeout(Duration::from_secs(10), runner(pool.clone(), config))
+            .await
+            .expect("The runner did not stop the payload");
+
+        let mut _payload = Payload::new();
+        _payload
+            .retrieve_id(payload.id, &pool)
+            .await
+            .expect("Failed to retrieve payload");
+
+        assert_eq!(_payload.status, Status::TimedOut);
+        assert_eq!(_payload.max_runtime, Some(1));
+        assert_eq!(_payload.failure.unwrap().kind, FailureKind::Timeout);
+    }
 }
@@ -0,0 +1,429 @@
+use crate::config::loader::{InputSchema, Validator};
+use crate::models::upload_dao::InputError;
+use crate::services::client::signal_group;
+use crate::utils::io::list_files;
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+use tokio::io::AsyncReadExt;
+use tokio::process::Command;
+
+// Of the output of a validator that fails, what is kept as the error message
+const VALIDATOR_OUTPUT_SIZE: usize = 1000;
+
+// Of all the validator runs of a job together, in seconds, unless the schema sets it
+const VALIDATION_TIMEOUT: u64 = 300;
+
+// Whether `path` matches `pattern`, where `*` matches any part of a file or directory
+//  name but never crosses a `/`
+pub fn matches_pattern(pattern: &str, path: &str) -> bool {
+    let patterns: Vec<&str> = pattern.split('/').collect();
+    let components: Vec<&str> = path.split('/').collect();
+    patterns.len() == components.len()
+        && patterns
+            .iter()
+            .zip(&components)
+            .all(|(pattern, component)| matches_component(pattern, component))
+}
+
+fn matches_component(pattern: &str, component: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = component.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        // No `*`, the whole name must match
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+// Check the uploaded `files`, sizes by path, and the metadata of a job against `schema`.
+//  Every problem is reported, not only the first one
+pub fn check_inputs(
+    files: &BTreeMap<String, u64>,
+    metadata: &BTreeMap<String, String>,
+    schema: &InputSchema,
+) -> Vec<InputError> {
+    let mut errors = Vec::new();
+    let error = |input: Option<&str>, message: String| InputError {
+        input: input.map(str::to_string),
+        message,
+    };
+
+    for param in &schema.params {
+        if metadata.get(param).is_none_or(|v| v.trim().is_empty()) {
+            errors.push(error(Some(param), "Missing parameter".to_string()));
+        }
+    }
+
+    for (path, size) in files {
+        let name = path.to_ascii_lowercase();
+        if !schema.extensions.is_empty()
+            && !schema
+                .extensions
+                .iter()
+                .any(|e| name.ends_with(&format!(".{}", e.to_ascii_lowercase())))
+        {
+            errors.push(error(
+                Some(path),
+                format!(
+                    "Extension not allowed, expected {}",
+                    schema.extensions.join(", ")
+                ),
+            ));
+        }
+
+        if schema.files.is_empty() {
+            continue;
+        }
+        let mut matching = schema
+            .files
+            .iter()
+            .filter(|f| matches_pattern(&f.path, path))
+            .peekable();
+        if matching.peek().is_none() {
+            errors.push(error(
+                Some(path),
+                "Not an input of this service".to_string(),
+            ));
+        }
+        if let Some(max_size) = matching.filter_map(|f| f.max_size).min() {
+            if *size > max_size {
+                errors.push(error(Some(path), format!("Larger than {max_size} bytes")));
+            }
+        }
+    }
+
+    for file in schema.files.iter().filter(|f| f.required) {
+        if !files.keys().any(|path| matches_pattern(&file.path, path)) {
+            errors.push(error(Some(&file.path), "Missing required file".to_string()));
+        }
+    }
+
+    if let Some(max_size) = schema.max_size {
+        let size: u64 = files.values().sum();
+        if size > max_size {
+            errors.push(error(
+                None,
+                format!("Inputs are {size} bytes, more than {max_size}"),
+            ));
+        }
+    }
+
+    errors
+}
+
+// Run `validator` in `loc`, on `file` when it is given, for at most `timeout`. None when
+//  it passed, an error when the program could not be started at all
+async fn run_validator(
+    loc: &Path,
+    validator: &Validator,
+    file: Option<&str>,
+    timeout: Duration,
+) -> io::Result<Option<InputError>> {
+    let program = &validator.command[0];
+    let mut command = Command::new(program);
+    command
+        .args(&validator.command[1..])
+        // Relative to `loc`, so that a file named like an option is not taken for one
+        .args(file.map(|file| format!("./{file}")))
+        .current_dir(loc)
+        // Nothing of the server, its secrets included, but where to find programs
+        .env_clear()
+        .envs(std::env::var_os("PATH").map(|path| ("PATH", path)))
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        // In a group of its own, so that what it starts can be killed with it
+        .process_group(0)
+        .kill_on_drop(true);
+
+    let mut child = command.spawn()?;
+    let pid = child.id();
+    let mut stdout = child.stdout.take().expect("stdout is piped");
+    let mut stderr = child.stderr.take().expect("stderr is piped");
+    // The validator is only reaped once it is done, until then its group id can not be
+    //  taken by another process and the group is safe to kill
+    let run = async {
+        let (mut out, mut err) = (Vec::new(), Vec::new());
+        tokio::try_join!(stdout.read_to_end(&mut out), stderr.read_to_end(&mut err))?;
+        let status = child.wait().await?;
+        io::Result::Ok((status, out, err))
+    };
+
+    let message = match tokio::time::timeout(timeout, run).await {
+        Err(_) => {
+            if let Some(pid) = pid {
+                signal_group(pid, libc::SIGKILL).ok();
+            }
+            child.wait().await?;
+            format!(
+                "{program} timed out after {}s",
+                timeout.as_secs_f64().ceil()
+            )
+        }
+        Ok(output) => {
+            let (status, stdout, stderr) = output?;
+            if status.success() {
+                return Ok(None);
+            }
+            // What it has to say about the input, usually on stderr
+            let stderr = String::from_utf8_lossy(&stderr);
+            let stdout = String::from_utf8_lossy(&stdout);
+            let said = match stderr.trim() {
+                "" => stdout.trim(),
+                stderr => stderr,
+            };
+            match said {
+                "" => format!("{program} failed with {status}"),
+                said => said.chars().take(VALIDATOR_OUTPUT_SIZE).collect(),
+            }
+        }
+    };
+    Ok(Some(InputError {
+        input: file.map(str::to_string),
+        message,
+    }))
+}
+
+// Check the inputs of a job in `loc` against `schema`. The validators only run once the
+//  other checks passed, they can assume the files they are given are there. Together
+//  they have the timeout of the schema, the runs left once it is over are not made
+pub async fn validate(
+    loc: &Path,
+    metadata: &BTreeMap<String, String>,
+    schema: &InputSchema,
+) -> io::Result<Vec<InputError>> {
+    let files = list_files(loc)?;
+    let mut errors = check_inputs(&files, metadata, schema);
+    if !errors.is_empty() {
+        return Ok(errors);
+    }
+
+    let limit = schema.timeout.unwrap_or(VALIDATION_TIMEOUT);
+    let deadline = Instant::now() + Duration::from_secs(limit);
+    for validator in &schema.validators {
+        let targets: Vec<Option<&str>> = match &validator.files {
+            Some(pattern) => files
+                .keys()
+                .filter(|path| matches_pattern(pattern, path))
+                .map(|path| Some(path.as_str()))
+                .collect(),
+            None => vec![None],
+        };
+        for file in targets {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                errors.push(InputError {
+                    input: None,
+                    message: format!("Validators took more than {limit}s"),
+                });
+                return Ok(errors);
+            }
+            let timeout = Duration::from_secs(validator.timeout).min(left);
+            if let Some(error) = run_validator(loc, validator, file, timeout).await? {
+                errors.push(error);
+            }
+        }
+    }
+    Ok(errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::loader::InputFile;
+
+    fn schema() -> InputSchema {
+        InputSchema {
+            files: vec![
+                InputFile {
+                    path: "run.sh".to_string(),
+                    required: true,
+                    max_size: None,
+                },
+                InputFile {
+                    path: "data/*.pdb".to_string(),
+                    required: true,
+                    max_size: Some(10),
+                },
+            ],
+            extensions: vec!["sh".to_string(), "pdb".to_string()],
+            max_size: Some(30),
+            params: vec!["run_name".to_string()],
+            validators: Vec::new(),
+            timeout: None,
+        }
+    }
+
+    #[test]
+    fn test_matches_pattern() {
+        assert!(matches_pattern("run.sh", "run.sh"));
+        assert!(matches_pattern("*.pdb", "2oob.pdb"));
+        assert!(matches_pattern("data/*", "data/a.mol2"));
+        assert!(matches_pattern("lig*_*.mol2", "ligand_1.mol2"));
+        assert!(!matches_pattern("*.pdb", "data/2oob.pdb"));
+        assert!(!matches_pattern("*.pdb", "2oob.pdb.gz"));
+        assert!(!matches_pattern("run.sh", "run.sh.bak"));
+        assert!(!matches_pattern("a*a", "a"));
+    }
+
+    #[test]
+    fn test_check_inputs() {
+        let metadata = BTreeMap::from([("run_name".to_string(), "first".to_string())]);
+        let files = BTreeMap::from([("run.sh".to_string(), 4), ("data/2oob.pdb".to_string(), 8)]);
+        assert!(check_inputs(&files, &metadata, &schema()).is_empty());
+
+        let files = BTreeMap::from([
+            ("data/2oob.pdb".to_string(), 20),
+            ("notes.txt".to_string(), 20),
+        ]);
+        let errors = check_inputs(&files, &BTreeMap::new(), &schema());
+        let inputs: Vec<Option<&str>> = errors.iter().map(|e| e.input.as_deref()).collect();
+        assert_eq!(
+            inputs,
+            [
+                Some("run_name"),
+                Some("data/2oob.pdb"),
+                Some("notes.txt"),
+                Some("notes.txt"),
+                Some("run.sh"),
+                None
+            ]
+        );
+        assert_eq!(errors[1].message, "Larger than 10 bytes");
+        assert_eq!(errors[4].message, "Missing required file");
+    }
+
+    #[tokio::test]
+    async fn test_validate() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let loc = temp_dir.path();
+        std::fs::write(loc.join("run.sh"), "echo").unwrap();
+        std::fs::create_dir(loc.join("data")).unwrap();
+        std::fs::write(loc.join("data/a.pdb"), "ATOM").unwrap();
+        std::fs::write(loc.join("data/b.pdb"), "HTML").unwrap();
+
+        let mut schema = schema();
+        schema.validators = vec![Validator {
+            command: vec![
+                "sh".to_string(),
+                "-c".to_string(),
+                "grep -q ATOM \"$0\" || { echo \"no atoms in $0\" >&2; exit 1; }".to_string(),
+            ],
+            files: Some("data/*.pdb".to_string()),
+            timeout: 10,
+        }];
+        let metadata = BTreeMap::from([("run_name".to_string(), "first".to_string())]);
+        let errors = validate(loc, &metadata, &schema).await.unwrap();
+        assert_eq!(
+            errors,
+            [InputError {
+                input: Some("data/b.pdb".to_string()),
+                message: "no atoms in ./data/b.pdb".to_string(),
+            }]
+        );
+
+        // A file named like an option is still a file
+        std::fs::write(loc.join("-x.pdb"), "ATOM").unwrap();
+        let lenient = InputSchema {
+            validators: vec![Validator {
+                command: vec!["grep".to_string(), "-q".to_string(), "ATOM".to_string()],
+                files: Some("*.pdb".to_string()),
+                timeout: 10,
+            }],
+            ..Default::default()
+        };
+        let errors = validate(loc, &metadata, &lenient).await.unwrap();
+        assert!(errors.is_empty(), "{errors:?}");
+        std::fs::remove_file(loc.join("-x.pdb")).unwrap();
+
+        // Without the environment of the server
+        let clean = InputSchema {
+            validators: vec![Validator {
+                command: vec![
+                    "sh".to_string(),
+                    "-c".to_string(),
+                    "test -z \"$HOME\" && command -v grep".to_string(),
+                ],
+                files: None,
+                timeout: 10,
+            }],
+            ..Default::default()
+        };
+        let errors = validate(loc, &metadata, &clean).await.unwrap();
+        assert!(errors.is_empty(), "{errors:?}");
+
+        // Not run when the files are already wrong
+        let errors = validate(loc, &BTreeMap::new(), &schema).await.unwrap();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].input.as_deref(), Some("run_name"));
+
+        schema.validators = vec![Validator {
+            command: vec!["sleep".to_string(), "5".to_string()],
+            files: None,
+            timeout: 0,
+        }];
+        let errors = validate(loc, &metadata, &schema).await.unwrap();
+        assert_eq!(errors[0].message, "sleep timed out after 0s");
+
+        // The runs together can not take longer than the schema allows
+        schema.validators = vec![Validator {
+            command: vec!["sh".to_string(), "-c".to_string(), "sleep 5".to_string()],
+            files: Some("data/*.pdb".to_string()),
+            timeout: 10,
+        }];
+        schema.timeout = Some(1);
+        let errors = validate(loc, &metadata, &schema).await.unwrap();
+        assert_eq!(
+            errors,
+            [
+                InputError {
+                    input: Some("data/a.pdb".to_string()),
+                    message: "sh timed out after 1s".to_string(),
+                },
+                InputError {
+                    input: None,
+                    message: "Validators took more than 1s".to_string(),
+                }
+            ]
+        );
+        schema.timeout = None;
+
+        // What it started is killed with it
+        schema.validators = vec![Validator {
+            command: vec![
+                "sh".to_string(),
+                "-c".to_string(),
+                "sleep 30 & echo $! > sleeper; wait".to_string(),
+            ],
+            files: None,
+            timeout: 1,
+        }];
+        let errors = validate(loc, &metadata, &schema).await.unwrap();
+        assert_eq!(errors[0].message, "sh timed out after 1s");
+        let sleeper = std::fs::read_to_string(loc.join("sleeper")).unwrap();
+        let stat = format!("/proc/{}/stat", sleeper.trim());
+        let mut running = true;
+        for _ in 0..50 {
+            // Gone, or a zombie waiting for init
+            running = std::fs::read_to_string(&stat).is_ok_and(|s| !s.contains(") Z "));
+            if !running {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert!(!running);
+    }
+}
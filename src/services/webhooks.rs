@@ -0,0 +1,200 @@
+use crate::models::webhook_dao::Webhook;
+use crate::utils::io::hex;
+use crate::utils::retry;
+use hmac::{Hmac, Mac};
+use http::StatusCode;
+use sha2::Sha256;
+use std::net::{IpAddr, Ipv4Addr};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// A webhook is given up after this many attempts
+pub const MAX_ATTEMPTS: u32 = 8;
+
+const FIRST_RETRY: Duration = Duration::from_secs(10);
+const MAX_RETRY: Duration = Duration::from_secs(3600);
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, thiserror::Error)]
+pub enum WebhookError {
+    #[error("Request failed: {0}")]
+    RequestFailed(#[from] reqwest::Error),
+
+    #[error("Receiver returned error status {status}: {body}")]
+    UnexpectedStatus { status: StatusCode, body: String },
+}
+
+// How long to wait after the given number of failed attempts
+pub fn backoff(attempts: u32) -> Duration {
+    retry::backoff(FIRST_RETRY, MAX_RETRY, attempts)
+}
+
+// Hex encoded HMAC-SHA256 of `content`
+pub fn sign(secret: &str, content: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+    mac.update(content.as_bytes());
+    hex(&mac.finalize().into_bytes())
+}
+
+// What is signed, the time of the attempt goes with the body so that receivers can
+//  refuse a notification that is replayed later on
+pub fn signed_content(timestamp: u64, body: &str) -> String {
+    format!("{timestamp}.{body}")
+}
+
+// Whether the url points at this host or at its private network. Only literal
+//  addresses are checked, names are not resolved
+pub fn is_internal(url: &reqwest::Url) -> bool {
+    let Some(host) = url.host_str() else {
+        return true;
+    };
+    if host == "localhost" || host.ends_with(".localhost") {
+        return true;
+    }
+    match host.trim_start_matches('[').trim_end_matches(']').parse() {
+        Ok(IpAddr::V4(ip)) => is_internal_v4(ip),
+        Ok(IpAddr::V6(ip)) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_internal_v4(ip),
+            None => {
+                ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_unique_local()
+                    || ip.is_unicast_link_local()
+            }
+        },
+        Err(_) => false,
+    }
+}
+
+fn is_internal_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        // 0.0.0.0/8 and the shared 100.64.0.0/10
+        || a == 0
+        || (a == 100 && (b & 0xc0) == 64)
+}
+
+// Post the notification, signed with the secret. Any 2xx answer counts as delivered
+pub async fn deliver(webhook: &Webhook, secret: &str) -> Result<(), WebhookError> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let signature = sign(secret, &signed_content(timestamp, &webhook.payload));
+
+    let client = reqwest::Client::new();
+    let response = client
+        .post(&webhook.url)
+        .timeout(TIMEOUT)
+        .header(http::header::CONTENT_TYPE, "application/json")
+        .header("x-webhook-id", webhook.id.to_string())
+        .header("x-webhook-timestamp", timestamp.to_string())
+        .header("x-webhook-signature", format!("sha256={signature}"))
+        .body(webhook.payload.clone())
+        .send()
+        .await
+        .map_err(WebhookError::RequestFailed)?;
+
+    let status = response.status();
+    if status.is_success() {
+        return Ok(());
+    }
+    let body = response
+        .text()
+        .await
+        .unwrap_or_else(|_| "Unable to read response body".to_string());
+    Err(WebhookError::UnexpectedStatus { status, body })
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    fn webhook(url: String) -> Webhook {
+        Webhook {
+            id: 3,
+            job_id: 1,
+            url,
+            payload: r#"{"event":"job.completed"}"#.to_string(),
+            attempts: 0,
+            last_error: None,
+            delivered_at: None,
+        }
+    }
+
+    #[test]
+    fn test_is_internal() {
+        let internal = |url: &str| is_internal(&reqwest::Url::parse(url).unwrap());
+        for url in [
+            "http://localhost:8080/",
+            "http://api.localhost/",
+            "http://127.0.0.1/",
+            "http://2130706433/",
+            "http://10.0.0.1/",
+            "http://172.16.3.4/",
+            "http://192.168.1.1/",
+            "http://169.254.169.254/latest/meta-data/",
+            "http://100.64.0.1/",
+            "http://0.0.0.0/",
+            "http://[::1]/",
+            "http://[::ffff:127.0.0.1]/",
+            "http://[fd00::1]/",
+            "http://[fe80::1]/",
+        ] {
+            assert!(internal(url), "{url}");
+        }
+        for url in [
+            "https://portal.example.com/hooks",
+            "http://93.184.216.34/",
+            "http://100.128.0.1/",
+            "http://[2606:4700::1111]/",
+        ] {
+            assert!(!internal(url), "{url}");
+        }
+    }
+
+    #[test]
+    fn test_sign() {
+        // RFC 4231, test case 2
+        assert_eq!(
+            sign("Jefe", "what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_deliver() {
+        let mut server = mockito::Server::new_async().await;
+        // The signature covers the timestamp it is sent with
+        let mock = server
+            .mock("POST", "/hook")
+            .match_request(|request| {
+                let header = |name| request.header(name)[0].to_str().unwrap().to_string();
+                let timestamp: u64 = header("x-webhook-timestamp").parse().unwrap();
+                let content = signed_content(timestamp, r#"{"event":"job.completed"}"#);
+                header("x-webhook-signature") == format!("sha256={}", sign("secret", &content))
+            })
+            .match_header("x-webhook-id", "3")
+            .match_body(r#"{"event":"job.completed"}"#)
+            .with_status(204)
+            .create_async()
+            .await;
+        server
+            .mock("POST", "/broken")
+            .with_status(500)
+            .create_async()
+            .await;
+
+        let result = deliver(&webhook(format!("{}/hook", server.url())), "secret").await;
+        assert!(result.is_ok());
+        mock.assert_async().await;
+
+        let result = deliver(&webhook(format!("{}/broken", server.url())), "secret").await;
+        assert!(matches!(result, Err(WebhookError::UnexpectedStatus { .. })));
+    }
+}
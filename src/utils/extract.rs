@@ -0,0 +1,317 @@
+use crate::config::loader::ExtractLimits;
+use crate::utils::io::{create_input_dirs, create_input_file, normalize_path, PathError};
+use axum::http::StatusCode;
+use flate2::read::GzDecoder;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use zip::ZipArchive;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ExtractError {
+    #[error(transparent)]
+    Path(#[from] PathError),
+    #[error("Entry '{0}' is not a regular file or directory")]
+    Unsupported(String),
+    #[error("More than {0} files in the archive")]
+    TooManyFiles(u64),
+    #[error("More than {0} bytes once extracted")]
+    TooLarge(u64),
+    #[error("Archive expands to more than {0} times its size")]
+    Ratio(u64),
+    #[error("Invalid archive: {0}")]
+    Invalid(String),
+    #[error("Failed to write extracted file: {0}")]
+    Io(#[from] io::Error),
+}
+
+impl ExtractError {
+    // What the request is answered with
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            ExtractError::Path(e) => e.status_code(),
+            ExtractError::Io(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArchiveKind {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+impl ArchiveKind {
+    // Going by the extension of the uploaded file
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.to_ascii_lowercase();
+        if name.ends_with(".zip") {
+            Some(ArchiveKind::Zip)
+        } else if name.ends_with(".tar") {
+            Some(ArchiveKind::Tar)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(ArchiveKind::TarGz)
+        } else {
+            None
+        }
+    }
+}
+
+// What has been extracted so far, against the limits. Sizes are counted as the files are
+//  written, the sizes an archive claims for its entries are not trusted
+struct Budget<'a> {
+    limits: &'a ExtractLimits,
+    archive_size: u64,
+    files: u64,
+    size: u64,
+}
+
+impl Budget<'_> {
+    fn write<R: Read>(&mut self, entry: &mut R, path: &Path) -> Result<(), ExtractError> {
+        self.files += 1;
+        if self.files > self.limits.max_files {
+            return Err(ExtractError::TooManyFiles(self.limits.max_files));
+        }
+
+        let max_by_ratio = self.archive_size.saturating_mul(self.limits.max_ratio);
+        let mut file = create_input_file(path)?;
+        let mut buffer = vec![0; 64 * 1024];
+        loop {
+            let n = entry
+                .read(&mut buffer)
+                .map_err(|e| ExtractError::Invalid(e.to_string()))?;
+            if n == 0 {
+                return Ok(());
+            }
+            self.size += n as u64;
+            // Stop as soon as a limit is crossed, before the rest is written
+            if self.size > self.limits.max_size {
+                return Err(ExtractError::TooLarge(self.limits.max_size));
+            }
+            if self.size > max_by_ratio {
+                return Err(ExtractError::Ratio(self.limits.max_ratio));
+            }
+            file.write_all(&buffer[..n])?;
+        }
+    }
+}
+
+// Path in `dest` of an entry, refused when it would overwrite a file
+fn entry_path(dest: &Path, name: &str) -> Result<std::path::PathBuf, ExtractError> {
+    let relative = normalize_path(name)?;
+    let path = create_input_dirs(dest, &relative)?;
+    match fs::symlink_metadata(&path) {
+        Ok(_) => Err(PathError::Conflict(relative.display().to_string()).into()),
+        Err(_) => Ok(path),
+    }
+}
+
+// Extract `archive` into `dest`, with the same path checks as uploaded inputs. Directories
+//  are created along with the files in them, links and special files are refused.
+//  Gives the number of bytes extracted
+pub fn extract(
+    archive: &Path,
+    kind: ArchiveKind,
+    dest: &Path,
+    limits: &ExtractLimits,
+) -> Result<u64, ExtractError> {
+    let file = File::open(archive)?;
+    let mut budget = Budget {
+        limits,
+        archive_size: file.metadata()?.len(),
+        files: 0,
+        size: 0,
+    };
+
+    match kind {
+        ArchiveKind::Zip => extract_zip(file, dest, &mut budget)?,
+        ArchiveKind::Tar => extract_tar(file, dest, &mut budget)?,
+        ArchiveKind::TarGz => extract_tar(GzDecoder::new(file), dest, &mut budget)?,
+    }
+    Ok(budget.size)
+}
+
+fn extract_zip(file: File, dest: &Path, budget: &mut Budget) -> Result<(), ExtractError> {
+    let mut zip = ZipArchive::new(file).map_err(|e| ExtractError::Invalid(e.to_string()))?;
+    for i in 0..zip.len() {
+        let mut entry = zip
+            .by_index(i)
+            .map_err(|e| ExtractError::Invalid(e.to_string()))?;
+        let name = entry.name().to_string();
+        if entry.is_dir() {
+            continue;
+        }
+        if entry.is_symlink() {
+            return Err(ExtractError::Unsupported(name));
+        }
+        let path = entry_path(dest, &name)?;
+        budget.write(&mut entry, &path)?;
+    }
+    Ok(())
+}
+
+fn extract_tar<R: Read>(reader: R, dest: &Path, budget: &mut Budget) -> Result<(), ExtractError> {
+    let mut tar = tar::Archive::new(reader);
+    let entries = tar
+        .entries()
+        .map_err(|e| ExtractError::Invalid(e.to_string()))?;
+    for entry in entries {
+        let mut entry = entry.map_err(|e| ExtractError::Invalid(e.to_string()))?;
+        let name = String::from_utf8_lossy(&entry.path_bytes()).to_string();
+        match entry.header().entry_type() {
+            tar::EntryType::Regular | tar::EntryType::Continuous => {}
+            tar::EntryType::Directory | tar::EntryType::XGlobalHeader | tar::EntryType::XHeader => {
+                continue
+            }
+            _ => return Err(ExtractError::Unsupported(name)),
+        }
+        let path = entry_path(dest, &name)?;
+        budget.write(&mut entry, &path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::{write::GzEncoder, Compression};
+    use zip::write::SimpleFileOptions;
+    use zip::ZipWriter;
+
+    fn write_zip(path: &Path, files: &[(&str, &[u8])]) {
+        let mut zip = ZipWriter::new(File::create(path).unwrap());
+        for (name, content) in files {
+            zip.start_file(*name, SimpleFileOptions::default()).unwrap();
+            zip.write_all(content).unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    fn write_tar_gz(path: &Path, files: &[(&str, &[u8])]) {
+        let gz = GzEncoder::new(File::create(path).unwrap(), Compression::default());
+        let mut tar = tar::Builder::new(gz);
+        for (name, content) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            tar.append_data(&mut header, name, *content).unwrap();
+        }
+        tar.into_inner().unwrap().finish().unwrap();
+    }
+
+    #[test]
+    fn test_archive_kind() {
+        assert_eq!(ArchiveKind::from_name("in.ZIP"), Some(ArchiveKind::Zip));
+        assert_eq!(ArchiveKind::from_name("in.tar"), Some(ArchiveKind::Tar));
+        assert_eq!(ArchiveKind::from_name("in.tgz"), Some(ArchiveKind::TarGz));
+        assert_eq!(
+            ArchiveKind::from_name("in.tar.gz"),
+            Some(ArchiveKind::TarGz)
+        );
+        assert_eq!(ArchiveKind::from_name("in.gz"), None);
+    }
+
+    #[test]
+    fn test_extract() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dest = temp_dir.path().join("job");
+        fs::create_dir(&dest).unwrap();
+        let limits = ExtractLimits::default();
+
+        let archive = temp_dir.path().join("in.zip");
+        write_zip(&archive, &[("run.sh", b"echo"), ("data/a.mol2", b"mol")]);
+        let size = extract(&archive, ArchiveKind::Zip, &dest, &limits).unwrap();
+        assert_eq!(size, 7);
+        assert_eq!(fs::read(dest.join("data/a.mol2")).unwrap(), b"mol");
+
+        let archive = temp_dir.path().join("in.tar.gz");
+        write_tar_gz(&archive, &[("more/b.mol2", b"mol")]);
+        extract(&archive, ArchiveKind::TarGz, &dest, &limits).unwrap();
+        assert_eq!(fs::read(dest.join("more/b.mol2")).unwrap(), b"mol");
+
+        // What is there already is not overwritten
+        let result = extract(&archive, ArchiveKind::TarGz, &dest, &limits);
+        assert!(matches!(
+            result,
+            Err(ExtractError::Path(PathError::Conflict(_)))
+        ));
+    }
+
+    #[test]
+    fn test_extract_zip_slip() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dest = temp_dir.path().join("job");
+        fs::create_dir(&dest).unwrap();
+
+        let archive = temp_dir.path().join("in.zip");
+        write_zip(&archive, &[("../escape.txt", b"nope")]);
+        let result = extract(&archive, ArchiveKind::Zip, &dest, &ExtractLimits::default());
+        assert!(matches!(
+            result,
+            Err(ExtractError::Path(PathError::Parent(_)))
+        ));
+        assert!(!temp_dir.path().join("escape.txt").exists());
+
+        // `tar::Builder` refuses `..` itself, so the name is written in the header directly
+        let archive = temp_dir.path().join("in.tar");
+        let mut tar = tar::Builder::new(File::create(&archive).unwrap());
+        let mut header = tar::Header::new_old();
+        header.as_old_mut().name[..13].copy_from_slice(b"../escape.txt");
+        header.set_size(4);
+        header.set_cksum();
+        tar.append(&header, &b"nope"[..]).unwrap();
+        tar.finish().unwrap();
+        let result = extract(&archive, ArchiveKind::Tar, &dest, &ExtractLimits::default());
+        assert!(matches!(
+            result,
+            Err(ExtractError::Path(PathError::Parent(_)))
+        ));
+        assert!(!temp_dir.path().join("escape.txt").exists());
+
+        let archive = temp_dir.path().join("link.tar");
+        let mut tar = tar::Builder::new(File::create(&archive).unwrap());
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Symlink);
+        header.set_size(0);
+        tar.append_link(&mut header, "link", "/etc/passwd").unwrap();
+        tar.finish().unwrap();
+        let result = extract(&archive, ArchiveKind::Tar, &dest, &ExtractLimits::default());
+        assert!(matches!(result, Err(ExtractError::Unsupported(_))));
+        assert!(!dest.join("link").exists());
+    }
+
+    #[test]
+    fn test_extract_limits() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let archive = temp_dir.path().join("in.zip");
+        let zeros = vec![0; 100_000];
+        write_zip(&archive, &[("a", b"a"), ("b", b"b"), ("zeros", &zeros)]);
+
+        let cases = [
+            ExtractLimits {
+                max_files: 2,
+                ..Default::default()
+            },
+            ExtractLimits {
+                max_size: 50_000,
+                ..Default::default()
+            },
+            ExtractLimits {
+                max_ratio: 2,
+                ..Default::default()
+            },
+        ];
+        for (i, limits) in cases.iter().enumerate() {
+            let dest = temp_dir.path().join(i.to_string());
+            fs::create_dir(&dest).unwrap();
+            let result = extract(&archive, ArchiveKind::Zip, &dest, limits);
+            match i {
+                0 => assert!(matches!(result, Err(ExtractError::TooManyFiles(2)))),
+                1 => assert!(matches!(result, Err(ExtractError::TooLarge(50_000)))),
+                _ => assert!(matches!(result, Err(ExtractError::Ratio(2)))),
+            }
+        }
+    }
+}
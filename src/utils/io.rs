@@ -1,41 +1,151 @@
 use axum::http::StatusCode;
-use std::fs::File;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
 use std::io;
 use std::io::{Read, Write};
-use std::path::PathBuf;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
 use tokio::io::AsyncWriteExt;
 use walkdir::WalkDir;
 use zip::write::FileOptions;
 use zip::ZipWriter;
 
-/// Sanitize filename to prevent path traversal attacks
-pub fn sanitize_filename(filename: &str) -> String {
-    std::path::Path::new(filename)
-        .file_name()
-        .and_then(|n| n.to_str())
-        .unwrap_or("file")
-        .to_string()
+/// Directory of a job, or payload, where the orchestrator and the client keep their own
+/// files apart from the inputs. No input can be uploaded there
+pub const STATE_DIR: &str = ".orchestrator";
+
+#[derive(Debug, thiserror::Error)]
+pub enum PathError {
+    #[error("Empty path")]
+    Empty,
+    #[error("Absolute path '{0}'")]
+    Absolute(String),
+    #[error("Path '{0}' goes outside of the job directory")]
+    Parent(String),
+    #[error("Invalid character in path '{0}'")]
+    InvalidCharacter(String),
+    #[error("Path '{0}' goes through a symlink")]
+    Symlink(String),
+    #[error("Path '{0}' conflicts with another input")]
+    Conflict(String),
+    #[error("Path '{0}' is reserved")]
+    Reserved(String),
+    #[error("Failed to create directory: {0}")]
+    Io(#[from] io::Error),
+}
+
+impl PathError {
+    // What the request is answered with
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            PathError::Io(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
 }
 
-/// Save a multipart field to disk
+/// Normalize a relative path of an input, `./` and repeated `/` are dropped. Absolute
+/// paths, `..` and backslashes are refused rather than fixed, to prevent path traversal
+pub fn normalize_path(path: &str) -> Result<PathBuf, PathError> {
+    if path.contains('\\') || path.chars().any(char::is_control) {
+        return Err(PathError::InvalidCharacter(
+            path.escape_default().to_string(),
+        ));
+    }
+    if path.starts_with('/') {
+        return Err(PathError::Absolute(path.to_string()));
+    }
+
+    let mut normalized = PathBuf::new();
+    for component in path.split('/') {
+        match component {
+            "" | "." => continue,
+            ".." => return Err(PathError::Parent(path.to_string())),
+            component => normalized.push(component),
+        }
+    }
+    if normalized.starts_with(STATE_DIR) {
+        return Err(PathError::Reserved(path.to_string()));
+    }
+    match normalized.as_os_str().is_empty() {
+        true => Err(PathError::Empty),
+        false => Ok(normalized),
+    }
+}
+
+/// Where an uploaded file goes, relative to the job directory. `Client::upload` sends the
+/// path as the field name along with the bare file name, curl and browsers as the file name
+pub fn input_path(field_name: Option<&str>, file_name: &str) -> Result<PathBuf, PathError> {
+    match field_name {
+        Some(name) if name.ends_with(&format!("/{file_name}")) => normalize_path(name),
+        _ => normalize_path(file_name),
+    }
+}
+
+/// Create the directories of the input at `relative` in `root`, without going through
+/// symlinks, and give the path to write it to
+pub fn create_input_dirs(root: &Path, relative: &Path) -> Result<PathBuf, PathError> {
+    let display = || relative.display().to_string();
+    let mut path = root.to_path_buf();
+    for component in relative.parent().into_iter().flat_map(|p| p.components()) {
+        path.push(component);
+        match fs::symlink_metadata(&path) {
+            Ok(m) if m.file_type().is_symlink() => return Err(PathError::Symlink(display())),
+            Ok(m) if m.is_dir() => continue,
+            Ok(_) => return Err(PathError::Conflict(display())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => fs::create_dir(&path)?,
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    let path = root.join(relative);
+    match fs::symlink_metadata(&path) {
+        Ok(m) if m.file_type().is_symlink() => Err(PathError::Symlink(display())),
+        Ok(m) if m.is_dir() => Err(PathError::Conflict(display())),
+        _ => Ok(path),
+    }
+}
+
+/// Create the file of an input, refusing to follow a symlink that would be in its place
+pub fn create_input_file(path: &Path) -> io::Result<File> {
+    fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .custom_flags(libc::O_NOFOLLOW)
+        .open(path)
+}
+
+/// Save a multipart field to disk, returns the number of bytes written
 pub async fn save_file(
     mut field: axum::extract::multipart::Field<'_>,
     path: &std::path::Path,
-) -> Result<(), (StatusCode, String)> {
-    let mut file = tokio::fs::File::create(path).await.map_err(|e| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("File creation failed: {e}"),
-        )
-    })?;
+) -> Result<u64, (StatusCode, String)> {
+    let mut file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .custom_flags(libc::O_NOFOLLOW)
+        .open(path)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("File creation failed: {e}"),
+            )
+        })?;
 
     let mut buffer = Vec::with_capacity(1024 * 1024); // 1MB buffer
+    let mut size: u64 = 0;
 
     while let Some(chunk) = field
         .chunk()
         .await
         .map_err(|e| (StatusCode::BAD_REQUEST, format!("Chunk read failed: {e}")))?
     {
+        size += chunk.len() as u64;
         buffer.extend_from_slice(&chunk);
 
         // Write in chunks to balance memory and performance
@@ -67,10 +177,85 @@ pub async fn save_file(
         )
     })?;
 
-    Ok(())
+    Ok(size)
 }
 
-pub fn zip_directory(src_dir: &PathBuf, dst_file: &PathBuf) -> zip::result::ZipResult<()> {
+/// Hold an exclusive lock on `path`, a file or a directory, until the returned file is
+/// dropped. Blocks until the lock is free
+pub fn lock_exclusive(path: &Path) -> io::Result<File> {
+    let file = File::open(path)?;
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(file)
+}
+
+/// Size of every file in `root`, by its path relative to `root`
+pub fn list_files(root: &Path) -> io::Result<BTreeMap<String, u64>> {
+    let mut files = BTreeMap::new();
+    for entry in WalkDir::new(root) {
+        let entry = entry?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let relative = entry.path().strip_prefix(root).unwrap_or(entry.path());
+        files.insert(
+            relative.to_string_lossy().to_string(),
+            entry.metadata()?.len(),
+        );
+    }
+    Ok(files)
+}
+
+/// Lowercase hex of `bytes`, as digests are shown
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Hex SHA-256 of the content of a file
+pub fn sha256_file(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hex(&hasher.finalize()))
+}
+
+/// Size and hex SHA-256 of every file in `root`, by its path relative to `root`
+pub fn checksum_files(root: &Path) -> io::Result<BTreeMap<String, (u64, String)>> {
+    let mut files = BTreeMap::new();
+    for entry in WalkDir::new(root) {
+        let entry = entry?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let relative = entry.path().strip_prefix(root).unwrap_or(entry.path());
+        files.insert(
+            relative.to_string_lossy().to_string(),
+            (entry.metadata()?.len(), sha256_file(entry.path())?),
+        );
+    }
+    Ok(files)
+}
+
+// Extensions of files that gain nothing from being compressed again
+const COMPRESSED_EXTENSIONS: &[&str] = &[
+    "7z", "br", "bz2", "gz", "jpeg", "jpg", "lz4", "mp3", "mp4", "png", "rar", "tgz", "webp", "xz",
+    "zip", "zst",
+];
+
+/// Whether the file at `name` is already compressed, going by its extension
+pub fn is_compressed(name: &str) -> bool {
+    std::path::Path::new(name)
+        .extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| COMPRESSED_EXTENSIONS.contains(&e.to_ascii_lowercase().as_str()))
+}
+
+/// Zip everything in `src_dir` into `dst_file`, but what is at the `exclude` paths
+pub fn zip_directory(
+    src_dir: &PathBuf,
+    dst_file: &PathBuf,
+    exclude: &[&str],
+) -> zip::result::ZipResult<()> {
     // Create the output file
     let file = File::create(dst_file)?;
     let mut zip = ZipWriter::new(file);
@@ -88,7 +273,7 @@ pub fn zip_directory(src_dir: &PathBuf, dst_file: &PathBuf) -> zip::result::ZipR
         let path = entry.path();
         if let Ok(name) = path.strip_prefix(src_dir) {
             // Skip the root directory itself
-            if name.as_os_str().is_empty() {
+            if name.as_os_str().is_empty() || exclude.iter().any(|e| name.starts_with(e)) {
                 continue;
             }
 
@@ -104,7 +289,11 @@ pub fn zip_directory(src_dir: &PathBuf, dst_file: &PathBuf) -> zip::result::ZipR
                 // Add directory entry
                 zip.add_directory(name_str, options)?;
             } else {
-                // Add file to the zip archive
+                // Add file to the zip archive, as is when it is compressed already
+                let options = match is_compressed(name_str) {
+                    true => options.compression_method(zip::CompressionMethod::Stored),
+                    false => options,
+                };
                 zip.start_file(name_str, options)?;
                 let mut f = File::open(path)?;
                 let mut buffer = Vec::new();
@@ -124,4 +313,121 @@ pub fn zip_directory(src_dir: &PathBuf, dst_file: &PathBuf) -> zip::result::ZipR
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_path() {
+        assert_eq!(
+            normalize_path("data/ligands/a.mol2").unwrap(),
+            PathBuf::from("data/ligands/a.mol2")
+        );
+        assert_eq!(
+            normalize_path("./data//a.mol2").unwrap(),
+            PathBuf::from("data/a.mol2")
+        );
+        assert_eq!(
+            normalize_path("data/.orchestrator").unwrap(),
+            PathBuf::from("data/.orchestrator")
+        );
+        for path in [
+            "",
+            "./",
+            "/etc/passwd",
+            "data/../../a",
+            "..",
+            "a\\b",
+            "a\nb",
+            ".orchestrator/stdout.log",
+            "./.orchestrator",
+        ] {
+            assert!(normalize_path(path).is_err(), "{path:?}");
+        }
+    }
+
+    #[test]
+    fn test_input_path() {
+        assert_eq!(
+            input_path(Some("data/ligands/a.mol2"), "a.mol2").unwrap(),
+            PathBuf::from("data/ligands/a.mol2")
+        );
+        assert_eq!(
+            input_path(Some("file"), "data/a.mol2").unwrap(),
+            PathBuf::from("data/a.mol2")
+        );
+        assert_eq!(
+            input_path(Some("file"), "a.mol2").unwrap(),
+            PathBuf::from("a.mol2")
+        );
+        assert!(input_path(Some("../a.mol2"), "a.mol2").is_err());
+    }
+
+    #[test]
+    fn test_create_input_dirs() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+
+        let path = create_input_dirs(root, Path::new("data/ligands/a.mol2")).unwrap();
+        assert_eq!(path, root.join("data/ligands/a.mol2"));
+        assert!(root.join("data/ligands").is_dir());
+        create_input_file(&path).unwrap();
+
+        // A file and a directory can't have the same path
+        assert!(matches!(
+            create_input_dirs(root, Path::new("data/ligands/a.mol2/b")),
+            Err(PathError::Conflict(_))
+        ));
+        assert!(matches!(
+            create_input_dirs(root, Path::new("data/ligands")),
+            Err(PathError::Conflict(_))
+        ));
+
+        // Nothing is written through a symlink
+        let outside = tempfile::tempdir().unwrap();
+        std::os::unix::fs::symlink(outside.path(), root.join("link")).unwrap();
+        assert!(matches!(
+            create_input_dirs(root, Path::new("link/a.mol2")),
+            Err(PathError::Symlink(_))
+        ));
+        std::os::unix::fs::symlink(outside.path().join("b"), root.join("b")).unwrap();
+        assert!(matches!(
+            create_input_dirs(root, Path::new("b")),
+            Err(PathError::Symlink(_))
+        ));
+        assert!(create_input_file(&root.join("b")).is_err());
+        assert!(!outside.path().join("b").exists());
+    }
+
+    #[test]
+    fn test_checksum_files() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        fs::create_dir(temp_dir.path().join("data")).unwrap();
+        fs::write(temp_dir.path().join("data/a.txt"), b"abc").unwrap();
+
+        let files = checksum_files(temp_dir.path()).unwrap();
+        assert_eq!(
+            files,
+            BTreeMap::from([(
+                "data/a.txt".to_string(),
+                (
+                    3,
+                    "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad".to_string()
+                )
+            )])
+        );
+    }
+
+    #[test]
+    fn test_hex() {
+        assert_eq!(hex(&[0x00, 0x0f, 0xa0, 0xff]), "000fa0ff");
+        assert_eq!(hex(&[]), "");
+    }
+
+    #[test]
+    fn test_is_compressed() {
+        assert!(is_compressed("results/scores.tar.gz"));
+        assert!(is_compressed("plot.PNG"));
+        assert!(!is_compressed("scores.csv"));
+        assert!(!is_compressed("README"));
+    }
+}
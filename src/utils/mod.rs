@@ -1 +1,4 @@
+pub mod extract;
 pub mod io;
+pub mod retry;
+pub mod serve;
@@ -0,0 +1,24 @@
+use std::time::Duration;
+
+// Wait `first` after the first failed attempt, doubling with every next one up to `max`
+pub fn backoff(first: Duration, max: Duration, attempts: u32) -> Duration {
+    let factor = 2u32.saturating_pow(attempts.saturating_sub(1));
+    first.saturating_mul(factor).min(max)
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        let first = Duration::from_secs(10);
+        let max = Duration::from_secs(3600);
+        assert_eq!(backoff(first, max, 1), Duration::from_secs(10));
+        assert_eq!(backoff(first, max, 2), Duration::from_secs(20));
+        assert_eq!(backoff(first, max, 4), Duration::from_secs(80));
+        assert_eq!(backoff(first, max, 20), max);
+        assert_eq!(backoff(first, max, 100), max);
+    }
+}
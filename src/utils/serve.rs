@@ -0,0 +1,198 @@
+use axum::body::Body;
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use std::io::SeekFrom;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+
+// Part of a file asked for with a `Range` header
+#[derive(Debug, PartialEq)]
+pub enum ByteRange {
+    // No range, or one that is not understood
+    Full,
+    // First and last byte, both included
+    Partial(u64, u64),
+    Unsatisfiable,
+}
+
+// Only single `bytes` ranges are served, a file sent whole is a valid answer to others
+pub fn parse_range(value: &str, size: u64) -> ByteRange {
+    let Some(range) = value.trim().strip_prefix("bytes=") else {
+        return ByteRange::Full;
+    };
+    let Some((start, end)) = range.split_once('-').filter(|_| !range.contains(',')) else {
+        return ByteRange::Full;
+    };
+
+    match (start.trim(), end.trim()) {
+        // The last `suffix` bytes
+        ("", suffix) => match suffix.parse::<u64>() {
+            Ok(0) => ByteRange::Unsatisfiable,
+            Ok(_) if size == 0 => ByteRange::Unsatisfiable,
+            Ok(suffix) => ByteRange::Partial(size.saturating_sub(suffix), size - 1),
+            Err(_) => ByteRange::Full,
+        },
+        (start, end) => {
+            let Ok(start) = start.parse::<u64>() else {
+                return ByteRange::Full;
+            };
+            let end = match end {
+                "" => u64::MAX,
+                end => match end.parse::<u64>() {
+                    Ok(end) if end >= start => end,
+                    _ => return ByteRange::Full,
+                },
+            };
+            if start >= size {
+                return ByteRange::Unsatisfiable;
+            }
+            ByteRange::Partial(start, end.min(size - 1))
+        }
+    }
+}
+
+// Changes whenever the file is written again
+fn etag(size: u64, modified: u128) -> String {
+    format!("\"{size:x}-{modified:x}\"")
+}
+
+// Stream the file at `path`, or the part of it the `Range` of the request asks for.
+//  `If-None-Match` and `If-Range` are checked against its `ETag`
+pub async fn serve_file(
+    path: &Path,
+    content_type: &str,
+    request: &HeaderMap,
+) -> std::io::Result<Response> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let metadata = file.metadata().await?;
+    let size = metadata.len();
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+    let etag = etag(size, modified);
+
+    let mut headers = HeaderMap::new();
+    headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    if let Ok(value) = HeaderValue::from_str(&etag) {
+        headers.insert(header::ETAG, value);
+    }
+
+    let get = |name| request.get(name).and_then(|v| v.to_str().ok());
+    if let Some(if_none_match) = get(header::IF_NONE_MATCH) {
+        if if_none_match
+            .split(',')
+            .any(|tag| tag.trim() == "*" || tag.trim() == etag)
+        {
+            return Ok((StatusCode::NOT_MODIFIED, headers).into_response());
+        }
+    }
+
+    // A range of another version of the file would not fit with what the client has
+    let range = match (get(header::RANGE), get(header::IF_RANGE)) {
+        (Some(_), Some(if_range)) if if_range.trim() != etag => ByteRange::Full,
+        (Some(range), _) => parse_range(range, size),
+        (None, _) => ByteRange::Full,
+    };
+
+    let (status, start, length) = match range {
+        ByteRange::Full => (StatusCode::OK, 0, size),
+        ByteRange::Partial(start, end) => {
+            let content_range = format!("bytes {start}-{end}/{size}");
+            if let Ok(value) = HeaderValue::from_str(&content_range) {
+                headers.insert(header::CONTENT_RANGE, value);
+            }
+            (StatusCode::PARTIAL_CONTENT, start, end - start + 1)
+        }
+        ByteRange::Unsatisfiable => {
+            if let Ok(value) = HeaderValue::from_str(&format!("bytes */{size}")) {
+                headers.insert(header::CONTENT_RANGE, value);
+            }
+            return Ok((StatusCode::RANGE_NOT_SATISFIABLE, headers).into_response());
+        }
+    };
+
+    if let Ok(value) = HeaderValue::from_str(content_type) {
+        headers.insert(header::CONTENT_TYPE, value);
+    }
+    headers.insert(header::CONTENT_LENGTH, HeaderValue::from(length));
+
+    file.seek(SeekFrom::Start(start)).await?;
+    let body = Body::from_stream(ReaderStream::new(file.take(length)));
+    Ok((status, headers, body).into_response())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::body::to_bytes;
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range("bytes=0-3", 10), ByteRange::Partial(0, 3));
+        assert_eq!(parse_range("bytes=4-", 10), ByteRange::Partial(4, 9));
+        assert_eq!(parse_range("bytes=-3", 10), ByteRange::Partial(7, 9));
+        assert_eq!(parse_range("bytes=-30", 10), ByteRange::Partial(0, 9));
+        assert_eq!(parse_range("bytes=8-30", 10), ByteRange::Partial(8, 9));
+        assert_eq!(parse_range("bytes=10-", 10), ByteRange::Unsatisfiable);
+        assert_eq!(parse_range("bytes=-0", 10), ByteRange::Unsatisfiable);
+        assert_eq!(parse_range("bytes=0-1,4-5", 10), ByteRange::Full);
+        assert_eq!(parse_range("bytes=5-2", 10), ByteRange::Full);
+        assert_eq!(parse_range("lines=0-1", 10), ByteRange::Full);
+    }
+
+    #[tokio::test]
+    async fn test_serve_file() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("results.zip");
+        std::fs::write(&path, b"0123456789").unwrap();
+
+        let response = serve_file(&path, "application/zip", &HeaderMap::new())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_LENGTH], "10");
+        assert_eq!(response.headers()[header::ACCEPT_RANGES], "bytes");
+        let etag = response.headers()[header::ETAG].clone();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"0123456789");
+
+        let mut request = HeaderMap::new();
+        request.insert(header::RANGE, HeaderValue::from_static("bytes=2-4"));
+        request.insert(header::IF_RANGE, etag.clone());
+        let response = serve_file(&path, "application/zip", &request)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes 2-4/10");
+        assert_eq!(response.headers()[header::CONTENT_LENGTH], "3");
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"234");
+
+        // Another version of the file, it is sent whole
+        request.insert(header::IF_RANGE, HeaderValue::from_static("\"other\""));
+        let response = serve_file(&path, "application/zip", &request)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let mut request = HeaderMap::new();
+        request.insert(header::RANGE, HeaderValue::from_static("bytes=20-"));
+        let response = serve_file(&path, "application/zip", &request)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes */10");
+
+        let mut request = HeaderMap::new();
+        request.insert(header::IF_NONE_MATCH, etag);
+        let response = serve_file(&path, "application/zip", &request)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+    }
+}